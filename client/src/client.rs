@@ -1,10 +1,13 @@
 use reqwest::{Client, ClientBuilder, RequestBuilder};
 use serde::Serialize;
-use std::{fmt::Display, future::Future, marker::PhantomData, time::Duration};
+use std::{fmt::Display, future::Future, marker::PhantomData, sync::Arc, time::Duration};
 
 use crate::endpoints::chat::{ChatMessage, ChatRequest};
 
-const BASE_URL: &str = "https://api.straico.com";
+/// Default base URL of the Straico API.
+pub const DEFAULT_BASE_URL: &str = "https://api.straico.com";
+/// Default path of the chat completions endpoint, relative to the base URL.
+pub const DEFAULT_CHAT_PATH: &str = "/v2/chat/completions";
 
 /// Represents the state where no API key has been set for the request
 pub struct NoApiKey;
@@ -29,7 +32,11 @@ pub struct StraicoRequestBuilder<Api, Payload>(
 
 impl From<Client> for StraicoClient {
     fn from(value: Client) -> Self {
-        Self { client: value }
+        Self {
+            client: value,
+            base_url: DEFAULT_BASE_URL.into(),
+            chat_path: DEFAULT_CHAT_PATH.into(),
+        }
     }
 }
 
@@ -41,17 +48,21 @@ impl From<Client> for StraicoClient {
 #[derive(Clone)]
 pub struct StraicoClient {
     pub client: reqwest::Client,
+    /// Base URL requests are sent to (without a trailing slash)
+    pub base_url: Arc<str>,
+    /// Path of the chat completions endpoint, relative to `base_url`
+    pub chat_path: Arc<str>,
 }
 
 pub struct StraicoClientBuilder {
     pub client: ClientBuilder,
+    pub base_url: String,
+    pub chat_path: String,
 }
 
 impl Default for StraicoClient {
     fn default() -> Self {
-        Self {
-            client: Client::new(),
-        }
+        Client::new().into()
     }
 }
 
@@ -71,17 +82,16 @@ impl StraicoClient {
     /// Creates a request builder for the new chat endpoint.
     ///
     /// This corresponds to `POST /v2/chat/completions` on the Straico API.
+    /// The path can be overridden with [`StraicoClientBuilder::chat_path`].
     pub fn chat(self) -> StraicoRequestBuilder<NoApiKey, ChatRequest<ChatMessage>> {
-        self.client
-            .post(BASE_URL.to_string() + "/v2/chat/completions")
-            .into()
+        self.client.post(self.url(&self.chat_path)).into()
     }
 
     /// Creates a request builder for listing models.
     ///
     /// This corresponds to `GET /v2/models` on the Straico API.
     pub fn models(self) -> StraicoRequestBuilder<NoApiKey, ()> {
-        self.client.get(BASE_URL.to_string() + "/v2/models").into()
+        self.client.get(self.url("/v2/models")).into()
     }
 
     /// Creates a request builder for retrieving a single model by ID.
@@ -90,46 +100,110 @@ impl StraicoClient {
     /// Pass the model ID exactly as returned by the `/v2/models` endpoint
     /// (for example: `"amazon/nova-lite-v1"`).
     pub fn model(self, model_id: &str) -> StraicoRequestBuilder<NoApiKey, ()> {
-        let mut url = BASE_URL.to_string() + "/v2/models";
-        url.push_str(model_id);
+        let url = self.url(&format!("/v2/models/{model_id}"));
         self.client.get(&url).into()
     }
 
     pub fn builder() -> StraicoClientBuilder {
         StraicoClientBuilder {
             client: reqwest::Client::builder(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            chat_path: DEFAULT_CHAT_PATH.to_string(),
         }
     }
+
+    /// Joins an endpoint path onto the configured base URL.
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
 }
 
 impl StraicoClientBuilder {
     pub fn pool_max_idle_per_host(self, max: usize) -> StraicoClientBuilder {
         Self {
             client: self.client.pool_max_idle_per_host(max),
+            ..self
         }
     }
 
     pub fn pool_idle_timeout<D: Into<Option<Duration>>>(self, val: D) -> StraicoClientBuilder {
         Self {
             client: self.client.pool_idle_timeout(val),
+            ..self
         }
     }
 
     pub fn tcp_keepalive<D: Into<Option<Duration>>>(self, val: D) -> StraicoClientBuilder {
         Self {
             client: self.client.tcp_keepalive(val),
+            ..self
         }
     }
 
     pub fn timeout(self, timeout: Duration) -> StraicoClientBuilder {
         Self {
             client: self.client.timeout(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the longest wait for each read from a connection, including the first byte
+    /// of the response. Unlike [`timeout`](Self::timeout), it does not limit the whole
+    /// request, so a body that keeps arriving is never cut off.
+    pub fn read_timeout(self, timeout: Duration) -> StraicoClientBuilder {
+        Self {
+            client: self.client.read_timeout(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the base URL requests are sent to, e.g. to target a Straico-compatible backend.
+    pub fn base_url<S: Into<String>>(self, base_url: S) -> StraicoClientBuilder {
+        Self {
+            base_url: base_url.into(),
+            ..self
+        }
+    }
+
+    /// Overrides the path of the chat completions endpoint.
+    pub fn chat_path<S: Into<String>>(self, chat_path: S) -> StraicoClientBuilder {
+        Self {
+            chat_path: chat_path.into(),
+            ..self
+        }
+    }
+
+    /// Routes all requests through `proxy` instead of the `HTTP_PROXY`/`HTTPS_PROXY`
+    /// environment variables, which are honored by default.
+    pub fn proxy(self, proxy: reqwest::Proxy) -> StraicoClientBuilder {
+        Self {
+            client: self.client.proxy(proxy),
+            ..self
+        }
+    }
+
+    /// Trusts `cert` as a root certificate, in addition to the built-in roots.
+    pub fn add_root_certificate(self, cert: reqwest::Certificate) -> StraicoClientBuilder {
+        Self {
+            client: self.client.add_root_certificate(cert),
+            ..self
+        }
+    }
+
+    /// Disables TLS certificate verification. Only for testing against servers with
+    /// self-signed or intercepted certificates.
+    pub fn danger_accept_invalid_certs(self, accept: bool) -> StraicoClientBuilder {
+        Self {
+            client: self.client.danger_accept_invalid_certs(accept),
+            ..self
         }
     }
 
     pub fn build(self) -> Result<StraicoClient, reqwest::Error> {
         Ok(StraicoClient {
             client: self.client.build()?,
+            base_url: self.base_url.trim_end_matches('/').into(),
+            chat_path: self.chat_path.into(),
         })
     }
 }
@@ -164,6 +238,21 @@ impl<K, T: Serialize> StraicoRequestBuilder<K, T> {
     }
 }
 
+impl<K, T> StraicoRequestBuilder<K, T> {
+    /// Sets a timeout for this request, overriding the client's default
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Time allowed from sending the request until the response body is read
+    ///
+    /// # Returns
+    ///
+    /// The same StraicoRequestBuilder with the timeout applied
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.0.timeout(timeout).into()
+    }
+}
+
 impl<T> StraicoRequestBuilder<ApiKeySet, T> {
     /// Sends the configured request to the API and returns the raw response
     ///
@@ -197,3 +286,34 @@ impl<T, U> From<RequestBuilder> for StraicoRequestBuilder<T, U> {
         StraicoRequestBuilder(value, PhantomData, PhantomData)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_urls() {
+        let client = StraicoClient::new();
+        assert_eq!(
+            client.url(&client.chat_path),
+            "https://api.straico.com/v2/chat/completions"
+        );
+        assert_eq!(
+            client.url("/v2/models/amazon/nova-lite-v1"),
+            "https://api.straico.com/v2/models/amazon/nova-lite-v1"
+        );
+    }
+
+    #[test]
+    fn test_overridden_urls() {
+        let client = StraicoClient::builder()
+            .base_url("http://localhost:9000/")
+            .chat_path("custom/chat")
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.url(&client.chat_path),
+            "http://localhost:9000/custom/chat"
+        );
+    }
+}
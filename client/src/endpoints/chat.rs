@@ -12,5 +12,6 @@ pub use error::*;
 pub use request_types::*;
 pub use response_types::*;
 pub use tool_calling::{
-    ChatFunctionCall, ModelProvider, OpenAiFunction, OpenAiTool, OpenAiToolChoice, ToolCall,
+    ChatFunctionCall, ModelProvider, OpenAiFunction, OpenAiTool, OpenAiToolChoice, StringArgs,
+    ToolCall, ToolCallFormat,
 };
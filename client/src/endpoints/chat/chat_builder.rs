@@ -117,6 +117,7 @@ impl ChatRequestBuilder {
             messages: self.messages,
             temperature: self.temperature,
             max_tokens: self.max_tokens,
+            extra: Default::default(),
         }
     }
 }
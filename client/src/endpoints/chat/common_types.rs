@@ -23,14 +23,40 @@ pub enum ChatContent {
 /// Represents a single content object.
 ///
 /// This structure supports content represented as an array of typed objects
-/// within message content arrays.
+/// within message content arrays. Parts of any type are accepted, so multimodal
+/// requests don't fail to parse; only `text` parts carry text.
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct ContentObject {
     /// The type of content (typically "text")
     #[serde(rename = "type")]
     pub content_type: String,
-    /// The actual text content
+    /// The actual text content (empty for non-text parts)
+    #[serde(default)]
     pub text: String,
+    /// The image of an `image_url` part
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<ImageUrl>,
+}
+
+/// The `image_url` of an OpenAI image content part.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ImageUrl {
+    /// A web URL, or a `data:` URL holding the image inline
+    pub url: String,
+    /// Requested image detail (`auto`, `low` or `high`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ContentObject {
+    /// Creates a `text` content part.
+    pub fn text<S: Into<String>>(text: S) -> Self {
+        ContentObject {
+            content_type: "text".to_string(),
+            text: text.into(),
+            image_url: None,
+        }
+    }
 }
 
 pub use super::tool_calling::ToolCall;
@@ -59,10 +85,28 @@ pub enum ChatMessage {
     /// Assistant message with mandatory content
     Assistant {
         /// The message content (string or array of content objects)
+        ///
+        /// A `null` from upstreams that return native tool calls is read as empty content.
+        #[serde(default = "empty_content", deserialize_with = "nullable_content")]
         content: ChatContent,
+        /// Native OpenAI-style tool calls, for upstreams that return them structured
+        /// instead of embedded in the content. Never sent upstream.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tool_calls: Option<Vec<ToolCall>>,
     },
 }
 
+fn empty_content() -> ChatContent {
+    ChatContent::String(String::new())
+}
+
+fn nullable_content<'de, D>(deserializer: D) -> Result<ChatContent, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<ChatContent>::deserialize(deserializer)?.unwrap_or_else(empty_content))
+}
+
 /// Represents a chat message in OpenAI format.
 ///
 /// This structure is used in both requests and responses.
@@ -75,11 +119,25 @@ pub enum OpenAiChatMessage {
     System {
         /// The message content in either string or array format
         content: ChatContent,
+        /// Optional participant name, for multi-agent conversations
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// Developer message (newer OpenAI models), which takes precedence over system messages
+    Developer {
+        /// The message content in either string or array format
+        content: ChatContent,
+        /// Optional participant name, for multi-agent conversations
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
     },
     /// User message with mandatory content
     User {
         /// The message content in either string or array format
         content: ChatContent,
+        /// Optional participant name, for multi-agent conversations
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
     },
     /// Assistant message with optional content
     Assistant {
@@ -88,6 +146,13 @@ pub enum OpenAiChatMessage {
         /// Optional tool calls made by assistant messages
         #[serde(skip_serializing_if = "Option::is_none")]
         tool_calls: Option<Vec<ToolCall>>,
+        /// Optional participant name, for multi-agent conversations
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// Reasoning the model produced before its answer, kept apart from `content`.
+        /// Never sent upstream.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reasoning_content: Option<String>,
     },
     /// Tool message with mandatory content
     Tool {
@@ -135,6 +200,16 @@ impl ChatMessage {
     pub fn assistant<S: Into<String>>(text: S) -> Self {
         ChatMessage::Assistant {
             content: ChatContent::String(text.into()),
+            tool_calls: None,
+        }
+    }
+
+    /// Returns the content of the message, regardless of its role.
+    pub fn content(&self) -> &ChatContent {
+        match self {
+            ChatMessage::System { content }
+            | ChatMessage::User { content }
+            | ChatMessage::Assistant { content, .. } => content,
         }
     }
 }
@@ -1,25 +1,99 @@
 use super::tool_calling;
 use super::{
-    ChatContent, ChatError, ChatMessage, OpenAiChatMessage,
-    common_types::ModelProvider,
-    request_types::{ChatRequest, OpenAiChatRequest, StraicoChatRequest},
-    response_types::{ChatChoice, OpenAiChatResponse, StraicoChatResponse},
+    ChatContent, ChatError, ChatMessage, OpenAiChatMessage, StringArgs, ToolCallFormat,
+    common_types::{ContentObject, ModelProvider},
+    request_types::{
+        ChatRequest, ExtraFields, OpenAiChatRequest, OpenAiTool, OpenAiToolChoice,
+        StraicoChatRequest,
+    },
+    response_types::{CHAT_COMPLETION_OBJECT, ChatChoice, OpenAiChatResponse, StraicoChatResponse},
 };
 use log::debug;
+use serde_json::{Map, Value};
 
 // Tool-related helper functions moved to tool_calling submodules
 
+/// Maps provider-native finish reasons onto the OpenAI vocabulary.
+///
+/// Straico passes through whatever the underlying model reports, so Anthropic's
+/// `end_turn`/`max_tokens` or Google's `STOP`/`SAFETY` can show up here. Unknown values
+/// are passed through unchanged.
+pub fn normalize_finish_reason(reason: &str) -> String {
+    match reason.to_ascii_lowercase().as_str() {
+        "end_turn" | "stop_sequence" | "eos" | "complete" | "finished" => "stop".to_string(),
+        "max_tokens" | "max_output_tokens" | "length_limit" => "length".to_string(),
+        "tool_use" | "function_call" => "tool_calls".to_string(),
+        "safety" | "recitation" | "content_filtered" => "content_filter".to_string(),
+        "stop" | "length" | "tool_calls" | "content_filter" => reason.to_ascii_lowercase(),
+        _ => reason.to_string(),
+    }
+}
+
+/// Straico has no participant names, so a message's `name` is kept as a `[name]: ` prefix
+/// on its content.
+fn prefix_with_name(content: ChatContent, name: Option<String>) -> ChatContent {
+    let Some(name) = name.filter(|name| !name.is_empty()) else {
+        return content;
+    };
+    let prefix = format!("[{name}]: ");
+    match content {
+        ChatContent::String(text) => ChatContent::String(prefix + &text),
+        ChatContent::Array(mut parts) => match parts.first_mut() {
+            Some(first) => {
+                first.text.insert_str(0, &prefix);
+                ChatContent::Array(parts)
+            }
+            None => ChatContent::String(prefix.trim_end().to_string()),
+        },
+    }
+}
+
+/// Straico only takes text, so non-text content parts are replaced before a message is sent
+/// upstream. An `image_url` part with a web URL becomes an `[Image: <url>]` note, so the
+/// model at least knows what was attached; inline `data:` images and parts of other types
+/// are dropped.
+fn text_parts_only(content: ChatContent) -> ChatContent {
+    let ChatContent::Array(parts) = content else {
+        return content;
+    };
+    let parts = parts
+        .into_iter()
+        .filter_map(|part| match part.image_url {
+            Some(image) if image.url.starts_with("data:") => {
+                debug!("Dropping inline image content part");
+                None
+            }
+            Some(image) => Some(ContentObject::text(format!("[Image: {}]", image.url))),
+            None if part.content_type == "text" || !part.text.is_empty() => Some(part),
+            None => {
+                debug!("Dropping unsupported {:?} content part", part.content_type);
+                None
+            }
+        })
+        .collect();
+    ChatContent::Array(parts)
+}
+
 pub fn convert_openai_message_with_provider(
     message: OpenAiChatMessage,
     provider: ModelProvider,
 ) -> Result<ChatMessage, ChatError> {
     Ok(match message {
-        OpenAiChatMessage::System { content } => ChatMessage::System { content },
-        OpenAiChatMessage::User { content } => ChatMessage::User { content },
+        OpenAiChatMessage::System { content, name }
+        | OpenAiChatMessage::Developer { content, name } => ChatMessage::System {
+            content: prefix_with_name(text_parts_only(content), name),
+        },
+        OpenAiChatMessage::User { content, name } => ChatMessage::User {
+            content: prefix_with_name(text_parts_only(content), name),
+        },
+        // Reasoning from earlier turns is not replayed upstream
         OpenAiChatMessage::Assistant {
             content,
             tool_calls,
+            name,
+            reasoning_content: _,
         } => {
+            let content = content.map(|content| prefix_with_name(content, name));
             if let Some(tool_calls) = tool_calls {
                 tool_calling::convert_assistant_with_tools_to_straico(
                     content,
@@ -29,6 +103,7 @@ pub fn convert_openai_message_with_provider(
             } else {
                 ChatMessage::Assistant {
                     content: content.unwrap_or(ChatContent::String(String::new())),
+                    tool_calls: None,
                 }
             }
         }
@@ -39,29 +114,133 @@ pub fn convert_openai_message_with_provider(
 impl TryFrom<OpenAiChatRequest> for StraicoChatRequest {
     type Error = ChatError;
 
-    fn try_from(mut request: OpenAiChatRequest) -> Result<Self, Self::Error> {
+    fn try_from(request: OpenAiChatRequest) -> Result<Self, Self::Error> {
         let provider = ModelProvider::from(request.chat_request.model.as_str());
+        convert_request_with_provider(request, provider)
+    }
+}
 
-        let messages: Vec<ChatMessage> = request
-            .chat_request
-            .messages
-            .into_iter()
-            .map(|msg| convert_openai_message_with_provider(msg, provider))
-            .collect::<Result<_, _>>()?;
+/// Converts an OpenAI request to Straico format, describing and replaying tool calls in
+/// `provider`'s format instead of the one of the request's model.
+pub fn convert_request_with_provider(
+    mut request: OpenAiChatRequest,
+    provider: ModelProvider,
+) -> Result<StraicoChatRequest, ChatError> {
+    let tool_choice = request.tool_choice.take();
+    let tools_disabled =
+        matches!(&tool_choice, Some(OpenAiToolChoice::String(choice)) if choice == "none");
+    let mut tools = request.tools.take().filter(|tools| !tools.is_empty());
+    if tools_disabled {
+        debug!("tool_choice is \"none\"; not injecting tools");
+        tools = None;
+    }
+    // A client that also describes its tools in the system prompt would otherwise see
+    // them twice
+    if let Some(structured) = tools.take() {
+        tools = merge_inline_tools(&mut request.chat_request.messages, structured);
+    }
 
-        let mut builder = ChatRequest::builder()
-            .model(std::mem::take(&mut request.chat_request.model))
-            .max_tokens(request.chat_request.max_tokens)
-            .temperature(request.chat_request.temperature)
-            .messages(messages);
+    // Straico has no developer role: developer messages become system messages placed
+    // ahead of everything else, so they take precedence over regular system messages
+    let (developer, rest): (Vec<_>, Vec<_>) = request
+        .chat_request
+        .messages
+        .into_iter()
+        .partition(|message| matches!(message, OpenAiChatMessage::Developer { .. }));
+    let messages: Vec<ChatMessage> = developer
+        .into_iter()
+        .chain(rest)
+        .map(|msg| convert_openai_message_with_provider(msg, provider))
+        .collect::<Result<_, _>>()?;
 
-        if let Some(tools) = request.tools
-            && !tools.is_empty()
-        {
-            builder = builder.message(tool_calling::tools_system_message(&tools, provider)?);
+    let mut builder = ChatRequest::builder()
+        .model(std::mem::take(&mut request.chat_request.model))
+        .max_tokens(request.chat_request.max_tokens)
+        .temperature(request.chat_request.temperature)
+        .messages(messages);
+
+    if let Some(tools) = tools {
+        builder = builder.message(tool_calling::tools_system_message(
+            &tools,
+            provider,
+            tool_choice.as_ref(),
+        )?);
+    }
+
+    let mut straico_request = builder.build();
+    if let Some(extra_body) = request.extra_body.take() {
+        straico_request.extra = extra_fields(extra_body);
+    }
+    Ok(straico_request)
+}
+
+/// Fields of the Straico request that the proxy sets itself and `extra_body` may not replace.
+const MANAGED_FIELDS: &[&str] = &[
+    "model",
+    "messages",
+    "temperature",
+    "max_tokens",
+    "max_completion_tokens",
+];
+
+/// Keeps the `extra_body` fields that don't collide with a managed one.
+fn extra_fields(extra_body: Map<String, Value>) -> ExtraFields {
+    ExtraFields(
+        extra_body
+            .into_iter()
+            .filter(|(key, _)| {
+                let managed = MANAGED_FIELDS.contains(&key.as_str());
+                if managed {
+                    debug!("Ignoring extra_body.{key}: the field is set by the proxy");
+                }
+                !managed
+            })
+            .collect(),
+    )
+}
+
+/// Moves the definitions in system and developer `<tools>` blocks into `tools`, keeping one
+/// per function name and preferring the structured ones.
+///
+/// Returns `None`, leaving the messages untouched, if a block is not a list of function
+/// definitions: the client's own prompt then describes the tools and nothing is injected.
+fn merge_inline_tools(
+    messages: &mut [OpenAiChatMessage],
+    tools: Vec<OpenAiTool>,
+) -> Option<Vec<OpenAiTool>> {
+    let mut inline = Vec::new();
+    let mut rewritten = Vec::new();
+    for (index, message) in messages.iter_mut().enumerate() {
+        let Some(content) = system_content_mut(message) else {
+            continue;
+        };
+        let mut text = content.to_string();
+        if !tool_calling::contains_tools_block(&text) {
+            continue;
         }
+        while tool_calling::contains_tools_block(&text) {
+            let Some((rest, functions)) = tool_calling::take_tools_block(&text) else {
+                debug!("System prompt has its own <tools> block; not injecting another");
+                return None;
+            };
+            text = rest;
+            inline.extend(functions);
+        }
+        rewritten.push((index, text));
+    }
+    for (index, text) in rewritten {
+        if let Some(content) = system_content_mut(&mut messages[index]) {
+            *content = ChatContent::String(text);
+        }
+    }
+    Some(tool_calling::merge_tools(tools, inline))
+}
 
-        Ok(builder.build())
+fn system_content_mut(message: &mut OpenAiChatMessage) -> Option<&mut ChatContent> {
+    match message {
+        OpenAiChatMessage::System { content, .. }
+        | OpenAiChatMessage::Developer { content, .. } => Some(content),
+        _ => None,
     }
 }
 
@@ -74,16 +253,52 @@ impl TryFrom<OpenAiChatMessage> for ChatMessage {
     }
 }
 
+/// Converts a Straico message to OpenAI format, extracting tool calls from assistant content.
+///
+/// Structured `tool_calls` on the upstream message take precedence: when present, the
+/// content is returned as-is and never scanned, so calls are not counted twice.
 pub fn convert_message_with_provider(
     message: ChatMessage,
     provider: ModelProvider,
+) -> Result<OpenAiChatMessage, ChatError> {
+    convert_message_with_format(message, provider, None, None)
+}
+
+/// Like [`convert_message_with_provider`], trying a `preferred` tool call format first and
+/// keeping the values of `string_args` as written.
+fn convert_message_with_format(
+    message: ChatMessage,
+    provider: ModelProvider,
+    preferred: Option<ToolCallFormat>,
+    string_args: Option<&dyn StringArgs>,
 ) -> Result<OpenAiChatMessage, ChatError> {
     match message {
-        ChatMessage::System { content } => Ok(OpenAiChatMessage::System { content }),
-        ChatMessage::User { content } => Ok(OpenAiChatMessage::User { content }),
-        ChatMessage::Assistant { content } => Ok(
-            tool_calling::convert_straico_assistant_to_openai(content, provider)?,
-        ),
+        ChatMessage::System { content } => Ok(OpenAiChatMessage::System {
+            content,
+            name: None,
+        }),
+        ChatMessage::User { content } => Ok(OpenAiChatMessage::User {
+            content,
+            name: None,
+        }),
+        // Structured tool calls from the upstream are kept as-is; the content is not re-parsed
+        ChatMessage::Assistant {
+            content,
+            tool_calls: Some(tool_calls),
+        } if !tool_calls.is_empty() => Ok(OpenAiChatMessage::Assistant {
+            content: (!content.to_string().is_empty()).then_some(content),
+            tool_calls: Some(tool_calls),
+            name: None,
+            reasoning_content: None,
+        }),
+        ChatMessage::Assistant { content, .. } => {
+            Ok(tool_calling::convert_straico_assistant_to_openai(
+                content,
+                provider,
+                preferred,
+                string_args,
+            )?)
+        }
     }
 }
 
@@ -96,10 +311,58 @@ impl TryFrom<ChatMessage> for OpenAiChatMessage {
     }
 }
 
+/// Converts a Straico message to OpenAI format without extracting tool calls from content.
+fn convert_message_verbatim(message: ChatMessage) -> OpenAiChatMessage {
+    match message {
+        ChatMessage::System { content } => OpenAiChatMessage::System {
+            content,
+            name: None,
+        },
+        ChatMessage::User { content } => OpenAiChatMessage::User {
+            content,
+            name: None,
+        },
+        ChatMessage::Assistant {
+            content,
+            tool_calls,
+        } => OpenAiChatMessage::Assistant {
+            content: Some(content),
+            tool_calls: tool_calls.filter(|calls| !calls.is_empty()),
+            name: None,
+            reasoning_content: None,
+        },
+    }
+}
+
 impl TryFrom<StraicoChatResponse> for OpenAiChatResponse {
     type Error = ChatError;
 
     fn try_from(response: StraicoChatResponse) -> Result<Self, Self::Error> {
+        OpenAiChatResponse::from_straico(response, true)
+    }
+}
+
+impl OpenAiChatResponse {
+    /// Converts a Straico response into OpenAI format.
+    ///
+    /// When `parse_tool_calls` is false, assistant content is returned verbatim and never
+    /// scanned for embedded tool calls.
+    pub fn from_straico(
+        response: StraicoChatResponse,
+        parse_tool_calls: bool,
+    ) -> Result<Self, ChatError> {
+        Self::from_straico_with_format(response, parse_tool_calls, None, None)
+    }
+
+    /// Like [`from_straico`](Self::from_straico), trying a `preferred` tool call format
+    /// before the formats expected from the model's provider. Arguments in `string_args`
+    /// are kept as written rather than decoded as JSON.
+    pub fn from_straico_with_format(
+        response: StraicoChatResponse,
+        parse_tool_calls: bool,
+        preferred: Option<ToolCallFormat>,
+        string_args: Option<&dyn StringArgs>,
+    ) -> Result<Self, ChatError> {
         let provider = ModelProvider::from(response.response.model.as_str());
 
         let choices = response
@@ -107,23 +370,33 @@ impl TryFrom<StraicoChatResponse> for OpenAiChatResponse {
             .choices
             .into_iter()
             .map(|choice| {
-                let open_ai_message: OpenAiChatMessage =
-                    convert_message_with_provider(choice.message, provider)?;
+                let open_ai_message: OpenAiChatMessage = if parse_tool_calls {
+                    convert_message_with_format(choice.message, provider, preferred, string_args)?
+                } else {
+                    convert_message_verbatim(choice.message)
+                };
+                // A completed response without a finish reason is treated as a normal stop
+                let upstream_reason = choice
+                    .finish_reason
+                    .as_deref()
+                    .map_or_else(|| "stop".to_string(), normalize_finish_reason);
+                // Tool calls always finish with "tool_calls", even when content is kept
+                // alongside them or the upstream reports a plain stop
                 let finish_reason = match &open_ai_message {
                     OpenAiChatMessage::Assistant { tool_calls, .. } => {
                         if tool_calls.is_some() {
                             "tool_calls".to_string()
                         } else {
-                            choice.finish_reason
+                            upstream_reason
                         }
                     }
-                    _ => choice.finish_reason,
+                    _ => upstream_reason,
                 };
 
                 Ok(ChatChoice {
                     index: choice.index,
                     message: open_ai_message,
-                    finish_reason,
+                    finish_reason: Some(finish_reason),
                     logprobs: None,
                 })
             })
@@ -131,11 +404,12 @@ impl TryFrom<StraicoChatResponse> for OpenAiChatResponse {
 
         let openai_response = OpenAiChatResponse {
             id: response.response.id,
-            object: response.response.object,
+            object: CHAT_COMPLETION_OBJECT.to_string(),
             created: response.response.created,
             model: response.response.model,
             choices,
             usage: response.response.usage,
+            service_tier: response.response.service_tier,
         };
 
         debug!("Model: {}", openai_response.model);
@@ -143,6 +417,7 @@ impl TryFrom<StraicoChatResponse> for OpenAiChatResponse {
             if let OpenAiChatMessage::Assistant {
                 content,
                 tool_calls,
+                ..
             } = &choice.message
             {
                 debug!("Choice {}:", choice.index);
@@ -168,3 +443,533 @@ impl TryFrom<StraicoChatResponse> for OpenAiChatResponse {
 }
 
 // Integration tests for conversions are in tool_calling submodules
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::chat::{ChatFunctionCall, ToolCall};
+
+    fn straico_response(content: &str) -> StraicoChatResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "openai/gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            "price": {"input": 0.0, "output": 0.0, "total": 0.0},
+            "words": {"input": 1.0, "output": 1.0, "total": 2.0}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_straico_without_tool_parsing_keeps_content() {
+        let content = "<tool_calls>\n[{\"name\": \"view\", \"arguments\": {}}]\n</tool_calls>";
+        let response = OpenAiChatResponse::from_straico(straico_response(content), false).unwrap();
+
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        match &choice.message {
+            OpenAiChatMessage::Assistant {
+                content: Some(text),
+                tool_calls: None,
+                ..
+            } => assert_eq!(text.to_string(), content),
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_from_straico_with_tool_parsing_extracts_calls() {
+        let content = "<tool_calls>\n[{\"name\": \"view\", \"arguments\": {}}]\n</tool_calls>";
+        let response = OpenAiChatResponse::from_straico(straico_response(content), true).unwrap();
+        assert_eq!(
+            response.choices[0].finish_reason.as_deref(),
+            Some("tool_calls")
+        );
+    }
+
+    #[test]
+    fn test_mistral_tool_calls_are_extracted() {
+        let message = ChatMessage::Assistant {
+            content: ChatContent::String(
+                r#"[TOOL_CALLS][{"name": "get_weather", "arguments": {"city": "Paris"}}]"#
+                    .to_string(),
+            ),
+            tool_calls: None,
+        };
+        match convert_message_with_provider(message, ModelProvider::Mistral).unwrap() {
+            OpenAiChatMessage::Assistant {
+                content: None,
+                tool_calls: Some(calls),
+                ..
+            } => {
+                assert_eq!(calls.len(), 1);
+                assert_eq!(calls[0].function.name, "get_weather");
+                assert_eq!(
+                    calls[0].function.arguments,
+                    serde_json::json!({"city": "Paris"})
+                );
+            }
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_empty_tool_call_arguments_become_empty_object() {
+        for content in [
+            "<tool_calls>\n[{\"name\": \"list_files\", \"arguments\": {}}]\n</tool_calls>",
+            "<tool_calls>\n[{\"name\": \"list_files\", \"arguments\": \"\"}]\n</tool_calls>",
+        ] {
+            let response =
+                OpenAiChatResponse::from_straico(straico_response(content), true).unwrap();
+            match &response.choices[0].message {
+                OpenAiChatMessage::Assistant {
+                    tool_calls: Some(calls),
+                    ..
+                } => {
+                    assert_eq!(calls[0].function.name, "list_files");
+                    assert_eq!(calls[0].function.arguments, serde_json::json!({}));
+                }
+                other => panic!("Unexpected message: {other:?}"),
+            }
+        }
+
+        let moonshot = "<|tool_calls_section_begin|><|tool_call_begin|>functions.list_files:0<|tool_call_argument_begin|><|tool_call_end|><|tool_calls_section_end|>";
+        let calls = ModelProvider::MoonshotAI
+            .parse_tool_calls(moonshot)
+            .unwrap();
+        assert_eq!(calls[0].function.arguments, serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_message_name_is_prepended_to_content() {
+        let message: OpenAiChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "name": "alice",
+            "content": "Hello"
+        }))
+        .unwrap();
+        match ChatMessage::try_from(message).unwrap() {
+            ChatMessage::User { content } => assert_eq!(content.to_string(), "[alice]: Hello"),
+            other => panic!("Unexpected message: {other:?}"),
+        }
+
+        let message: OpenAiChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "name": "researcher",
+            "content": [{"type": "text", "text": "Found it"}]
+        }))
+        .unwrap();
+        match ChatMessage::try_from(message).unwrap() {
+            ChatMessage::Assistant { content, .. } => {
+                assert_eq!(content.to_string(), "[researcher]: Found it")
+            }
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_image_url_parts_become_text_notes() {
+        let message: OpenAiChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "content": [
+                {"type": "text", "text": "What is in these pictures?"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/cat.png", "detail": "low"}},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                {"type": "file", "file": {"file_id": "file-123"}}
+            ]
+        }))
+        .unwrap();
+
+        let converted =
+            convert_openai_message_with_provider(message, ModelProvider::OpenAI).unwrap();
+        let ChatContent::Array(parts) = converted.content() else {
+            panic!("Unexpected content: {:?}", converted.content());
+        };
+        assert_eq!(
+            parts,
+            &[
+                ContentObject::text("What is in these pictures?"),
+                ContentObject::text("[Image: https://example.com/cat.png]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nameless_message_is_unchanged() {
+        let message: OpenAiChatMessage =
+            serde_json::from_value(serde_json::json!({"role": "user", "content": "Hello"}))
+                .unwrap();
+        match ChatMessage::try_from(message).unwrap() {
+            ChatMessage::User { content } => assert_eq!(content.to_string(), "Hello"),
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_from_straico_null_finish_reason_defaults_to_stop() {
+        let mut response = straico_response("Hello");
+        response.response.choices[0].finish_reason = None;
+        let response = OpenAiChatResponse::from_straico(response, true).unwrap();
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[test]
+    fn test_native_tool_calls_pass_through_without_reparsing() {
+        let content =
+            "Calling <tool_calls>\n[{\"name\": \"other\", \"arguments\": {}}]\n</tool_calls>";
+        let response: StraicoChatResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "acme/custom-model",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": content,
+                    "tool_calls": [{
+                        "id": "call_native",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            "price": {"input": 0.0, "output": 0.0, "total": 0.0},
+            "words": {"input": 1.0, "output": 1.0, "total": 2.0}
+        }))
+        .unwrap();
+        assert_eq!(
+            ModelProvider::from(response.response.model.as_str()),
+            ModelProvider::Unknown
+        );
+
+        let response = OpenAiChatResponse::from_straico(response, true).unwrap();
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        match &choice.message {
+            OpenAiChatMessage::Assistant {
+                content: Some(text),
+                tool_calls: Some(calls),
+                ..
+            } => {
+                // Content is left alone instead of being mined for more calls
+                assert_eq!(text.to_string(), content);
+                assert_eq!(calls.len(), 1);
+                assert_eq!(calls[0].id, "call_native");
+                assert_eq!(calls[0].function.name, "get_weather");
+                assert_eq!(
+                    calls[0].function.arguments,
+                    serde_json::json!({"city": "Paris"})
+                );
+            }
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tool_calls_with_content_finish_with_tool_calls() {
+        let response: StraicoChatResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "openai/gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Let me check the weather.",
+                    "tool_calls": [{
+                        "id": "call_native",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{}"}
+                    }]
+                },
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            "price": {"input": 0.0, "output": 0.0, "total": 0.0},
+            "words": {"input": 1.0, "output": 1.0, "total": 2.0}
+        }))
+        .unwrap();
+
+        let response = OpenAiChatResponse::from_straico(response, true).unwrap();
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        match &choice.message {
+            OpenAiChatMessage::Assistant {
+                content: Some(text),
+                tool_calls: Some(calls),
+                ..
+            } => {
+                assert_eq!(text.to_string(), "Let me check the weather.");
+                assert_eq!(calls.len(), 1);
+            }
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_native_tool_calls_with_null_content() {
+        let message: ChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "view", "arguments": {}}
+            }]
+        }))
+        .unwrap();
+        match convert_message_with_provider(message, ModelProvider::Unknown).unwrap() {
+            OpenAiChatMessage::Assistant {
+                content: None,
+                tool_calls: Some(calls),
+                ..
+            } => assert_eq!(calls[0].id, "call_1"),
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_inline_and_structured_tools_are_merged() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "Be brief.\n<tools>\n[{\"name\": \"lookup_city\"}, {\"name\": \"get_time\"}]\n</tools>"
+                },
+                {"role": "user", "content": "Where is Lyon?"}
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {"name": "lookup_city", "parameters": {"type": "object"}}
+            }]
+        }))
+        .unwrap();
+
+        let straico = StraicoChatRequest::try_from(request).unwrap();
+        assert_eq!(straico.messages.len(), 3);
+        assert_eq!(straico.messages[0].content().to_string(), "Be brief.");
+        let tools_message = straico.messages[2].content().to_string();
+        assert_eq!(tools_message.matches("<tools>\n").count(), 1);
+        // One definition per function, the structured one winning
+        assert_eq!(tools_message.matches("\"lookup_city\"").count(), 1);
+        assert_eq!(tools_message.matches("\"get_time\"").count(), 1);
+        assert!(tools_message.contains("\"parameters\""));
+    }
+
+    #[test]
+    fn test_unparseable_tools_block_is_not_duplicated() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "You can use these tools:\n<tools>\nget_weather(city)\n</tools>"
+                },
+                {"role": "user", "content": "Weather in Paris?"}
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {"name": "get_weather", "parameters": {"type": "object"}}
+            }]
+        }))
+        .unwrap();
+
+        let straico = StraicoChatRequest::try_from(request).unwrap();
+        assert_eq!(straico.messages.len(), 2);
+        let all_content: String = straico
+            .messages
+            .iter()
+            .map(|m| m.content().to_string())
+            .collect();
+        assert_eq!(all_content.matches("<tools>").count(), 1);
+        assert!(!all_content.contains("# Tools"));
+    }
+
+    #[test]
+    fn test_tool_choice_none_does_not_embed_tools() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Weather in Paris?"}],
+            "tools": [{
+                "type": "function",
+                "function": {"name": "get_weather", "parameters": {"type": "object"}}
+            }],
+            "tool_choice": "none"
+        }))
+        .unwrap();
+
+        let straico = StraicoChatRequest::try_from(request).unwrap();
+        assert_eq!(straico.messages.len(), 1);
+        assert!(
+            !straico.messages[0]
+                .content()
+                .to_string()
+                .contains("<tools>")
+        );
+    }
+
+    #[test]
+    fn test_type_only_tool_choice_requires_a_call() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4o",
+            "messages": [{"role": "user", "content": "What's the weather?"}],
+            "tools": [{"type": "function", "function": {"name": "get_weather"}}],
+            "tool_choice": {"type": "function"}
+        }))
+        .unwrap();
+
+        let straico = StraicoChatRequest::try_from(request).unwrap();
+        let tools_message = straico.messages.last().unwrap().content().to_string();
+        assert!(tools_message.contains("You MUST call at least one of the functions"));
+    }
+
+    #[test]
+    fn test_developer_message_precedes_system() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4o",
+            "messages": [
+                {"role": "system", "content": "You are helpful."},
+                {"role": "user", "content": "Hi"},
+                {"role": "developer", "content": "Answer in French."}
+            ]
+        }))
+        .unwrap();
+
+        let straico = StraicoChatRequest::try_from(request).unwrap();
+        let roles_and_content: Vec<(&str, String)> = straico
+            .messages
+            .iter()
+            .map(|message| {
+                let role = match message {
+                    ChatMessage::System { .. } => "system",
+                    ChatMessage::User { .. } => "user",
+                    ChatMessage::Assistant { .. } => "assistant",
+                };
+                (role, message.content().to_string())
+            })
+            .collect();
+        assert_eq!(
+            roles_and_content,
+            vec![
+                ("system", "Answer in French.".to_string()),
+                ("system", "You are helpful.".to_string()),
+                ("user", "Hi".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extra_body_is_merged_without_replacing_managed_fields() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "top_p": 0.5,
+            "extra_body": {
+                "web_search": true,
+                "model": "openai/gpt-3.5-turbo",
+                "messages": []
+            }
+        }))
+        .unwrap();
+
+        let straico = StraicoChatRequest::try_from(request).unwrap();
+        let body = serde_json::to_value(&straico).unwrap();
+        assert_eq!(body["web_search"], true);
+        assert_eq!(body["model"], "openai/gpt-4o");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        // Unknown top-level fields of the incoming request are still not forwarded
+        assert!(body.get("top_p").is_none());
+        assert!(body.get("extra_body").is_none());
+    }
+
+    #[test]
+    fn test_assistant_tool_calls_round_trip_for_every_provider() {
+        let calls = vec![
+            ToolCall {
+                id: "call_1".to_string(),
+                index: Some(0),
+                tool_type: "function".to_string(),
+                function: ChatFunctionCall {
+                    name: "get_weather".to_string(),
+                    arguments: serde_json::json!({"city": "Paris", "days": 3}),
+                },
+            },
+            ToolCall {
+                id: "call_2".to_string(),
+                index: Some(1),
+                tool_type: "function".to_string(),
+                function: ChatFunctionCall {
+                    name: "search_files".to_string(),
+                    arguments: serde_json::json!({"query": "a \"quoted\" term", "paths": ["src", "docs"]}),
+                },
+            },
+        ];
+        let providers = [
+            ModelProvider::Anthropic,
+            ModelProvider::OpenAI,
+            ModelProvider::Zai,
+            ModelProvider::MoonshotAI,
+            ModelProvider::Qwen,
+            ModelProvider::Google,
+            ModelProvider::Mistral,
+            ModelProvider::Meta,
+            ModelProvider::Unknown,
+        ];
+        for provider in providers {
+            let message = OpenAiChatMessage::Assistant {
+                content: None,
+                tool_calls: Some(calls.clone()),
+                name: None,
+                reasoning_content: None,
+            };
+            let straico = convert_openai_message_with_provider(message, provider).unwrap();
+            let replayed = convert_message_with_provider(straico.clone(), provider).unwrap();
+            let OpenAiChatMessage::Assistant {
+                content,
+                tool_calls: Some(replayed_calls),
+                ..
+            } = replayed
+            else {
+                panic!("{provider:?}: no tool calls parsed back from {straico:?}");
+            };
+            assert!(
+                content.is_none(),
+                "{provider:?}: leftover content {content:?}"
+            );
+            let names_and_arguments = |calls: &[ToolCall]| {
+                calls
+                    .iter()
+                    .map(|call| (call.function.name.clone(), call.function.arguments.clone()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                names_and_arguments(&replayed_calls),
+                names_and_arguments(&calls),
+                "{provider:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_finish_reason() {
+        assert_eq!(normalize_finish_reason("end_turn"), "stop");
+        assert_eq!(normalize_finish_reason("STOP"), "stop");
+        assert_eq!(normalize_finish_reason("max_tokens"), "length");
+        assert_eq!(normalize_finish_reason("tool_use"), "tool_calls");
+        assert_eq!(normalize_finish_reason("SAFETY"), "content_filter");
+        assert_eq!(normalize_finish_reason("stop"), "stop");
+        assert_eq!(normalize_finish_reason("something_else"), "something_else");
+    }
+}
@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 
 use super::common_types::{ChatMessage, OpenAiChatMessage};
 
@@ -28,6 +29,24 @@ pub struct ChatRequest<T> {
     #[serde(alias = "max_completion_tokens")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    /// Additional top-level fields of the outgoing request, taken from the client's
+    /// `extra_body`
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+/// Top-level fields added to a serialized request as-is.
+///
+/// They are never read back: deserializing yields no fields, so the flattened field does
+/// not swallow the unknown fields of an incoming request.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct ExtraFields(pub Map<String, Value>);
+
+impl<'de> Deserialize<'de> for ExtraFields {
+    fn deserialize<D: Deserializer<'de>>(_deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::default())
+    }
 }
 
 /// A type alias for a Straico-specific chat request.
@@ -51,6 +70,63 @@ pub struct OpenAiChatRequest {
     /// Optional tool choice
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<OpenAiToolChoice>,
+    /// Requested OpenAI service tier (e.g. "auto", "default")
+    ///
+    /// Straico has no tiers, so this is not forwarded; it is only echoed back in the response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    /// Number of choices the client asked for
+    ///
+    /// Not forwarded to Straico; the proxy only uses it to decide whether several upstream
+    /// choices are reduced to one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Client hint for bucketing cached prompts (newer OpenAI requests)
+    ///
+    /// Not forwarded to Straico; only used by the proxy when it caches responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+    /// Options for streamed responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<OpenAiStreamOptions>,
+    /// Sequences at which generation should stop
+    ///
+    /// Straico has no equivalent, so this is not forwarded; the proxy cuts the response
+    /// content at the first match instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<StopSequences>,
+    /// Provider-specific options sent by LangChain and similar libraries
+    ///
+    /// Merged into the top level of the Straico request, except for the fields the proxy
+    /// sets itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_body: Option<Map<String, Value>>,
+}
+
+/// OpenAI's `stream_options` request field.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct OpenAiStreamOptions {
+    /// Send a final chunk with the token usage of the whole request and no choices
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+/// OpenAI's `stop` request field: a single sequence or a list of them.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum StopSequences {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl StopSequences {
+    /// Returns the sequences as a slice, whichever form they were sent in.
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            StopSequences::Single(sequence) => std::slice::from_ref(sequence),
+            StopSequences::Multiple(sequences) => sequences,
+        }
+    }
 }
 
 pub use super::tool_calling::{OpenAiFunction, OpenAiTool, OpenAiToolChoice};
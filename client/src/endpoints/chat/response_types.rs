@@ -1,8 +1,12 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 
 use super::common_types::{ChatMessage, OpenAiChatMessage};
 
+/// `object` of a non-streaming chat completion. Upstreams report assorted values, so
+/// converted responses always use this one.
+pub const CHAT_COMPLETION_OBJECT: &str = "chat.completion";
+
 /// Generic chat completion response structure.
 ///
 /// This structure can be used for both OpenAI-compatible and Straico-specific
@@ -25,6 +29,10 @@ pub struct ChatResponse<T> {
     /// The type of object (typically "chat.completion")
     pub object: String,
     /// Unix timestamp of when the completion was created
+    ///
+    /// Accepts integers, floats (truncated) and numeric strings, since not every
+    /// upstream reports it as a plain integer.
+    #[serde(deserialize_with = "flexible_timestamp_deserializer")]
     pub created: u64,
     /// The model used for the completion
     pub model: String,
@@ -32,6 +40,9 @@ pub struct ChatResponse<T> {
     pub choices: Vec<ChatChoice<T>>,
     /// Token usage statistics
     pub usage: Usage,
+    /// Service tier used for the request, echoed from the request when given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
 }
 
 /// Straico-specific chat completion response.
@@ -70,11 +81,18 @@ pub type OpenAiChatResponse = ChatResponse<OpenAiChatMessage>;
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ChatChoice<T> {
     /// Zero-based position of this choice in the list
+    ///
+    /// Omitted by some upstreams on single-choice responses, hence the default of 0.
+    #[serde(default)]
     pub index: u8,
     /// The generated message
     pub message: T,
     /// Why the model stopped generating (e.g., "stop", "length", "tool_calls")
-    pub finish_reason: String,
+    ///
+    /// Some upstreams send `null` (or omit it) even on completed responses, so it is
+    /// optional here; conversion to the OpenAI format fills in "stop" in that case.
+    #[serde(default)]
+    pub finish_reason: Option<String>,
     /// Optional log probabilities for the tokens
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logprobs: Option<String>,
@@ -120,3 +138,128 @@ pub struct MetricBreakdown {
     /// Total combined metric
     pub total: f64,
 }
+
+/// Deserializes a Unix timestamp given as an integer, a float or a numeric string.
+///
+/// Fractional seconds are truncated. Negative or non-numeric values are rejected.
+pub fn flexible_timestamp_deserializer<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Integer(u64),
+        Float(f64),
+        String(String),
+    }
+
+    let float_to_secs = |f: f64| {
+        if f.is_finite() && f >= 0.0 {
+            Ok(f.trunc() as u64)
+        } else {
+            Err(serde::de::Error::custom(format!("invalid timestamp: {f}")))
+        }
+    };
+
+    match Timestamp::deserialize(deserializer)? {
+        Timestamp::Integer(secs) => Ok(secs),
+        Timestamp::Float(f) => float_to_secs(f),
+        Timestamp::String(s) => {
+            let s = s.trim();
+            match s.parse::<u64>() {
+                Ok(secs) => Ok(secs),
+                Err(_) => s
+                    .parse::<f64>()
+                    .map_err(|_| serde::de::Error::custom(format!("invalid timestamp: {s:?}")))
+                    .and_then(float_to_secs),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response_with_created(created: Value) -> Result<OpenAiChatResponse, serde_json::Error> {
+        serde_json::from_value(json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": created,
+            "model": "openai/gpt-4",
+            "choices": [],
+            "usage": {"prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0}
+        }))
+    }
+
+    #[test]
+    fn test_created_as_integer() {
+        let response = response_with_created(json!(1700000000)).unwrap();
+        assert_eq!(response.created, 1700000000);
+    }
+
+    #[test]
+    fn test_created_as_float_is_truncated() {
+        let response = response_with_created(json!(1700000000.987)).unwrap();
+        assert_eq!(response.created, 1700000000);
+    }
+
+    #[test]
+    fn test_created_as_numeric_string() {
+        let response = response_with_created(json!("1700000000")).unwrap();
+        assert_eq!(response.created, 1700000000);
+
+        let response = response_with_created(json!("1700000000.5")).unwrap();
+        assert_eq!(response.created, 1700000000);
+    }
+
+    #[test]
+    fn test_created_rejects_invalid_values() {
+        assert!(response_with_created(json!("yesterday")).is_err());
+        assert!(response_with_created(json!(-5)).is_err());
+    }
+
+    #[test]
+    fn test_created_through_flattened_straico_response() {
+        let response: StraicoChatResponse = serde_json::from_value(json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": "1700000000",
+            "model": "openai/gpt-4",
+            "choices": [],
+            "usage": {"prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0},
+            "price": {"input": 0.0, "output": 0.0, "total": 0.0},
+            "words": {"input": 0.0, "output": 0.0, "total": 0.0}
+        }))
+        .unwrap();
+        assert_eq!(response.response.created, 1700000000);
+    }
+
+    #[test]
+    fn test_null_or_missing_finish_reason() {
+        let choice =
+            |choice: Value| serde_json::from_value::<ChatChoice<ChatMessage>>(choice).unwrap();
+        let message = json!({"role": "assistant", "content": "Hello"});
+
+        let null = choice(json!({"index": 0, "message": message, "finish_reason": null}));
+        assert!(null.finish_reason.is_none());
+
+        let missing = choice(json!({"index": 0, "message": message}));
+        assert!(missing.finish_reason.is_none());
+
+        let present = choice(json!({"index": 0, "message": message, "finish_reason": "stop"}));
+        assert_eq!(present.finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[test]
+    fn test_missing_choice_index_defaults_to_zero() {
+        let choice: ChatChoice<ChatMessage> = serde_json::from_value(json!({
+            "message": {"role": "assistant", "content": "Hello"},
+            "finish_reason": "stop"
+        }))
+        .unwrap();
+        assert_eq!(choice.index, 0);
+    }
+}
@@ -10,7 +10,11 @@ pub use conversions::{
     convert_tool_message_to_straico,
 };
 pub use error::ToolCallingError;
-pub use system_messages::{build_tool_system_message, tools_system_message};
+pub use parsers::{StringArgs, ToolCallFormat};
+pub use system_messages::{
+    build_tool_system_message, contains_tools_block, merge_tools, strip_leaked_tools_prompt,
+    take_tools_block, tools_system_message,
+};
 pub use types::{
     ChatFunctionCall, ModelProvider, OpenAiFunction, OpenAiTool, OpenAiToolChoice, ToolCall,
     string_or_object_to_value_deserializer, value_to_string_serializer,
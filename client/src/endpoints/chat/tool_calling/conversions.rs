@@ -1,5 +1,6 @@
 use super::error::ToolCallingError;
 
+use super::parsers::{self, StringArgs, ToolCallFormat};
 use super::types::{ModelProvider, ToolCall};
 use crate::endpoints::chat::common_types::{ChatContent, ChatMessage, OpenAiChatMessage};
 
@@ -19,15 +20,22 @@ pub fn convert_assistant_with_tools_to_straico(
 
     Ok(ChatMessage::Assistant {
         content: ChatContent::String(final_content),
+        tool_calls: None,
     })
 }
 
+/// Extracts tool calls from assistant content, trying a `preferred` format before the
+/// provider's own formats. Values of `string_args` are kept as written.
 pub fn convert_straico_assistant_to_openai(
     content: ChatContent,
     provider: ModelProvider,
+    preferred: Option<ToolCallFormat>,
+    string_args: Option<&dyn StringArgs>,
 ) -> Result<OpenAiChatMessage, ToolCallingError> {
     let content_str = content.to_string();
-    let mut tool_calls = provider.parse_tool_calls(&content_str);
+    let mut tool_calls =
+        parsers::parse_tool_calls_with_format(&content_str, provider, preferred, string_args)
+            .map(|(calls, _)| calls);
 
     if let Some(ref mut tcs) = tool_calls
         && !tcs.is_empty()
@@ -42,20 +50,45 @@ pub fn convert_straico_assistant_to_openai(
         return Ok(OpenAiChatMessage::Assistant {
             content: None,
             tool_calls: tool_calls.take(),
+            name: None,
+            reasoning_content: None,
         });
     }
 
     Ok(OpenAiChatMessage::Assistant {
         content: Some(content),
         tool_calls: None,
+        name: None,
+        reasoning_content: None,
     })
 }
 
+/// Converts a tool result into a user message holding the tool message as JSON.
+///
+/// The text parts of array content are flattened into a single string, one part per line,
+/// so the model sees the whole tool output rather than a list of content objects.
 pub fn convert_tool_message_to_straico(
     message: &OpenAiChatMessage,
 ) -> Result<ChatMessage, ToolCallingError> {
+    let json = match message {
+        OpenAiChatMessage::Tool {
+            content: ChatContent::Array(parts),
+            tool_call_id,
+        } => {
+            let text: Vec<&str> = parts
+                .iter()
+                .filter(|part| part.content_type == "text")
+                .map(|part| part.text.as_str())
+                .collect();
+            serde_json::to_string_pretty(&OpenAiChatMessage::Tool {
+                content: ChatContent::String(text.join("\n")),
+                tool_call_id: tool_call_id.clone(),
+            })?
+        }
+        _ => serde_json::to_string_pretty(message)?,
+    };
     Ok(ChatMessage::User {
-        content: ChatContent::String(serde_json::to_string_pretty(message)?),
+        content: ChatContent::String(json),
     })
 }
 
@@ -79,7 +112,7 @@ mod tests {
             convert_assistant_with_tools_to_straico(None, &tool_calls, ModelProvider::Unknown)
                 .unwrap();
         match chat_msg {
-            ChatMessage::Assistant { content } => {
+            ChatMessage::Assistant { content, .. } => {
                 let content_str = content.to_string();
                 assert!(content_str.contains("<tool_calls>"));
                 assert!(content_str.contains("test_func"));
@@ -96,11 +129,13 @@ mod tests {
         let content = ChatContent::String(content_str);
 
         let open_ai_msg =
-            convert_straico_assistant_to_openai(content, ModelProvider::Unknown).unwrap();
+            convert_straico_assistant_to_openai(content, ModelProvider::Unknown, None, None)
+                .unwrap();
         match open_ai_msg {
             OpenAiChatMessage::Assistant {
                 content,
                 tool_calls,
+                ..
             } => {
                 assert!(content.is_none());
                 let tool_calls = tool_calls.unwrap();
@@ -110,4 +145,25 @@ mod tests {
             _ => panic!("Incorrect message type"),
         }
     }
+
+    #[test]
+    fn test_tool_message_array_content_is_concatenated() {
+        let message: OpenAiChatMessage = serde_json::from_value(serde_json::json!({
+            "role": "tool",
+            "tool_call_id": "call_1",
+            "content": [
+                {"type": "text", "text": "first part"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/chart.png"}},
+                {"type": "text", "text": "second part"}
+            ]
+        }))
+        .unwrap();
+        let content = match convert_tool_message_to_straico(&message).unwrap() {
+            ChatMessage::User { content } => content.to_string(),
+            other => panic!("Unexpected message: {other:?}"),
+        };
+        let tool_output: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(tool_output["content"], "first part\nsecond part");
+        assert_eq!(tool_output["tool_call_id"], "call_1");
+    }
 }
@@ -1,4 +1,5 @@
 use super::error::ToolCallingError;
+use super::parsers::MISTRAL_TOOL_CALLS_TOKEN;
 use super::types::{ModelProvider, ToolCall};
 
 pub fn format_moonshot_tool_calls(tool_calls: &[ToolCall]) -> Result<String, ToolCallingError> {
@@ -73,6 +74,23 @@ pub fn format_zai_tool_calls(tool_calls: &[ToolCall]) -> Result<String, ToolCall
     Ok(formatted.trim().to_string())
 }
 
+pub fn format_mistral_tool_calls(tool_calls: &[ToolCall]) -> Result<String, ToolCallingError> {
+    let calls: Vec<_> = tool_calls
+        .iter()
+        .map(|tc| {
+            serde_json::json!({
+                "name": if tc.function.name.is_empty() { &tc.id } else { &tc.function.name },
+                "arguments": tc.function.arguments
+            })
+        })
+        .collect();
+    Ok(format!(
+        "{}{}",
+        MISTRAL_TOOL_CALLS_TOKEN,
+        serde_json::to_string(&calls)?
+    ))
+}
+
 pub fn format_json_tool_calls(tool_calls: &[ToolCall]) -> Result<String, ToolCallingError> {
     let simplified: Vec<_> = tool_calls
         .iter()
@@ -97,6 +115,7 @@ pub(super) fn format_tool_calls(
         ModelProvider::MoonshotAI => format_moonshot_tool_calls(tool_calls),
         ModelProvider::Qwen => format_qwen_tool_calls(tool_calls),
         ModelProvider::Zai => format_zai_tool_calls(tool_calls),
+        ModelProvider::Mistral => format_mistral_tool_calls(tool_calls),
         _ => format_json_tool_calls(tool_calls),
     }
 }
@@ -157,4 +176,37 @@ mod tests {
         assert!(formatted.contains("<|tool_call_begin|>test_func<|tool_call_argument_begin|>"));
         assert!(formatted.contains("{\"arg\":\"val\"}"));
     }
+
+    #[test]
+    fn test_mistral_tool_call_round_trip() {
+        let tool_calls = vec![
+            ToolCall {
+                id: "call_1".to_string(),
+                tool_type: "function".to_string(),
+                function: ChatFunctionCall {
+                    name: "get_weather".to_string(),
+                    arguments: serde_json::json!({"city": "Paris"}),
+                },
+                index: None,
+            },
+            ToolCall {
+                id: "call_2".to_string(),
+                tool_type: "function".to_string(),
+                function: ChatFunctionCall {
+                    name: "list_files".to_string(),
+                    arguments: serde_json::json!({}),
+                },
+                index: None,
+            },
+        ];
+        let formatted = ModelProvider::Mistral
+            .format_tool_calls(&tool_calls)
+            .unwrap();
+        assert!(formatted.starts_with("[TOOL_CALLS][{"));
+
+        let parsed = ModelProvider::Mistral.parse_tool_calls(&formatted).unwrap();
+        let functions: Vec<_> = parsed.into_iter().map(|tc| tc.function).collect();
+        let expected: Vec<_> = tool_calls.into_iter().map(|tc| tc.function).collect();
+        assert_eq!(functions, expected);
+    }
 }
@@ -1,6 +1,7 @@
-use super::types::{ChatFunctionCall, ModelProvider, ToolCall};
+use super::types::{ChatFunctionCall, ModelProvider, ToolCall, empty_arguments};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::Serialize;
 use uuid::Uuid;
 
 static XML_TOOL_CALL_REGEX: Lazy<Regex> =
@@ -51,6 +52,12 @@ pub fn strip_markdown_code_block(content: &str) -> &str {
         if !potential_lang.contains('{') && !potential_lang.contains('[') {
             return block_content[newline_idx..].trim();
         }
+    } else if let Some(json_idx) = block_content.find(['{', '[']) {
+        // Single-line fence such as ```json{...}```: drop a bare language tag before the JSON
+        let potential_lang = block_content[..json_idx].trim();
+        if potential_lang.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return &block_content[json_idx..];
+        }
     }
 
     block_content
@@ -77,7 +84,30 @@ pub fn try_parse_json_tool_call(content: &str) -> Option<Vec<ToolCall>> {
     serde_json::from_str::<Vec<ToolCall>>(&raw_json).ok()
 }
 
-pub fn try_parse_xml_tool_call(content: &str) -> Option<Vec<ToolCall>> {
+/// The tool arguments declared with a `string` type in the request's tool schemas.
+pub trait StringArgs {
+    /// Whether argument `key` of `function` is declared as a string.
+    fn is_string(&self, function: &str, key: &str) -> bool;
+}
+
+/// Reads an `<arg_value>`: strings are written raw and other values as JSON, as in the
+/// GLM chat template, so a value that parses as a non-string JSON value is taken as one
+/// unless `raw` (the argument is declared as a string).
+fn parse_arg_value(value: String, raw: bool) -> serde_json::Value {
+    if raw {
+        return serde_json::Value::String(value);
+    }
+    match serde_json::from_str(&value) {
+        Ok(serde_json::Value::String(_)) | Err(_) => serde_json::Value::String(value),
+        Ok(parsed) => parsed,
+    }
+}
+
+/// Parses `<tool_call>` tags, keeping the `<arg_value>`s of `string_args` verbatim.
+pub fn try_parse_xml_tool_call(
+    content: &str,
+    string_args: Option<&dyn StringArgs>,
+) -> Option<Vec<ToolCall>> {
     let mut tool_calls = Vec::new();
 
     for cap in XML_SINGLE_TOOL_CALL_REGEX.captures_iter(content) {
@@ -107,6 +137,17 @@ pub fn try_parse_xml_tool_call(content: &str) -> Option<Vec<ToolCall>> {
             continue;
         }
 
+        // Z.ai hybrid: the name line is followed by a JSON arguments object instead of pairs
+        let rest = inner.split_once('\n').map_or("", |(_, rest)| rest);
+        let rest = strip_markdown_code_block(rest.trim());
+        if let Ok(arguments @ serde_json::Value::Object(_)) = serde_json::from_str(rest) {
+            tool_calls.push(function_call_to_tool_call(ChatFunctionCall {
+                name: function_name,
+                arguments,
+            }));
+            continue;
+        }
+
         // Build JSON arguments by collecting keys and values separately
         let keys: Vec<_> = XML_ARG_KEY_REGEX
             .captures_iter(inner)
@@ -121,8 +162,8 @@ pub fn try_parse_xml_tool_call(content: &str) -> Option<Vec<ToolCall>> {
         if !keys.is_empty() && keys.len() == values.len() {
             let mut args_map = serde_json::Map::new();
             for (k, v) in keys.into_iter().zip(values) {
-                // Ensure values are properly JSON-escaped by storing them as serde_json::Value::String
-                args_map.insert(k, serde_json::Value::String(v));
+                let raw = string_args.is_some_and(|args| args.is_string(&function_name, &k));
+                args_map.insert(k, parse_arg_value(v, raw));
             }
 
             tool_calls.push(function_call_to_tool_call(ChatFunctionCall {
@@ -171,8 +212,13 @@ pub fn try_parse_moonshot_tool_call(content: &str) -> Option<Vec<ToolCall>> {
             .unwrap_or(raw_function_name)
             .to_string();
 
-        // Validate and parse JSON
-        if let Ok(args_value) = serde_json::from_str::<serde_json::Value>(args_json_str) {
+        // Validate and parse JSON; no arguments at all means an empty arguments object
+        let args_value = if args_json_str.is_empty() {
+            Ok(empty_arguments())
+        } else {
+            serde_json::from_str::<serde_json::Value>(args_json_str)
+        };
+        if let Ok(args_value) = args_value {
             tool_calls.push(function_call_to_tool_call(ChatFunctionCall {
                 name: function_name,
                 arguments: args_value,
@@ -187,27 +233,151 @@ pub fn try_parse_moonshot_tool_call(content: &str) -> Option<Vec<ToolCall>> {
     }
 }
 
-/// Dispatches parsing to the appropriate function based on provider and content
-pub(super) fn parse_tool_calls(content: &str, provider: ModelProvider) -> Option<Vec<ToolCall>> {
-    match provider {
-        ModelProvider::Zai => try_parse_xml_tool_call(content)
-            .or_else(|| try_parse_json_tool_call(content))
-            .or_else(|| try_parse_moonshot_tool_call(content)),
-        ModelProvider::MoonshotAI => {
-            try_parse_moonshot_tool_call(content).or_else(|| try_parse_json_tool_call(content))
+/// Token Mistral models emit before the JSON array of their tool calls
+pub const MISTRAL_TOOL_CALLS_TOKEN: &str = "[TOOL_CALLS]";
+
+/// Try parsing Mistral tool calls: `[TOOL_CALLS]` followed by a JSON array of
+/// `{"name", "arguments"}` objects (a single object is accepted too)
+pub fn try_parse_mistral_tool_call(content: &str) -> Option<Vec<ToolCall>> {
+    let (_, rest) = content.split_once(MISTRAL_TOOL_CALLS_TOKEN)?;
+    let rest = strip_markdown_code_block(rest.trim());
+
+    // Only the first JSON value counts; anything the model wrote after it is ignored
+    let value = serde_json::Deserializer::from_str(rest)
+        .into_iter::<serde_json::Value>()
+        .next()?
+        .ok()?;
+    let functions = match value {
+        serde_json::Value::Array(_) => serde_json::from_value::<Vec<ChatFunctionCall>>(value),
+        _ => serde_json::from_value::<ChatFunctionCall>(value).map(|f| vec![f]),
+    }
+    .ok()?;
+
+    if functions.is_empty() {
+        return None;
+    }
+    Some(
+        functions
+            .into_iter()
+            .map(function_call_to_tool_call)
+            .collect(),
+    )
+}
+
+/// Token Llama 3.1 models emit before their tool calls
+pub const LLAMA_PYTHON_TAG: &str = "<|python_tag|>";
+
+/// Try parsing Llama 3.1 tool calls: `<|python_tag|>` followed by one or more JSON objects,
+/// separated by semicolons. Llama names the arguments `parameters`; `arguments` works too.
+pub fn try_parse_llama_tool_call(content: &str) -> Option<Vec<ToolCall>> {
+    let (_, mut rest) = content.split_once(LLAMA_PYTHON_TAG)?;
+    let mut tool_calls = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+        if !rest.starts_with('{') {
+            break;
+        }
+        // Parsing value by value keeps semicolons inside argument strings intact
+        let mut values = serde_json::Deserializer::from_str(rest).into_iter::<serde_json::Value>();
+        let Some(Ok(mut value)) = values.next() else {
+            break;
+        };
+        rest = &rest[values.byte_offset()..];
+
+        if let Some(object) = value.as_object_mut()
+            && !object.contains_key("arguments")
+            && let Some(parameters) = object.remove("parameters")
+        {
+            object.insert("arguments".to_string(), parameters);
+        }
+        if let Ok(function) = serde_json::from_value::<ChatFunctionCall>(value) {
+            tool_calls.push(function_call_to_tool_call(function));
         }
-        ModelProvider::Qwen => {
-            try_parse_xml_tool_call(content).or_else(|| try_parse_json_tool_call(content))
+    }
+
+    if tool_calls.is_empty() {
+        None
+    } else {
+        Some(tool_calls)
+    }
+}
+
+/// A textual tool call encoding a model may emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolCallFormat {
+    /// A JSON array wrapped in `<tool_calls>` tags
+    Json,
+    /// `<tool_call>` tags holding JSON or `<arg_key>`/`<arg_value>` pairs
+    Xml,
+    /// Kimi's `<|tool_call_begin|>` sections
+    Moonshot,
+    /// A JSON array after the `[TOOL_CALLS]` token
+    Mistral,
+    /// Llama's `<|python_tag|>` calls
+    Llama,
+}
+
+impl ToolCallFormat {
+    pub fn parse(self, content: &str) -> Option<Vec<ToolCall>> {
+        self.parse_with(content, None)
+    }
+
+    /// Like [`parse`](Self::parse), keeping the `<arg_value>`s of `string_args` verbatim.
+    pub fn parse_with(
+        self,
+        content: &str,
+        string_args: Option<&dyn StringArgs>,
+    ) -> Option<Vec<ToolCall>> {
+        match self {
+            ToolCallFormat::Json => try_parse_json_tool_call(content),
+            ToolCallFormat::Xml => try_parse_xml_tool_call(content, string_args),
+            ToolCallFormat::Moonshot => try_parse_moonshot_tool_call(content),
+            ToolCallFormat::Mistral => try_parse_mistral_tool_call(content),
+            ToolCallFormat::Llama => try_parse_llama_tool_call(content),
         }
+    }
+}
+
+/// Formats tried for `provider`'s output, in order; the first is the one it is asked to use.
+pub(super) fn format_chain(provider: ModelProvider) -> &'static [ToolCallFormat] {
+    use ToolCallFormat::*;
+    match provider {
+        ModelProvider::Zai => &[Xml, Json, Moonshot],
+        ModelProvider::MoonshotAI => &[Moonshot, Json],
+        ModelProvider::Qwen => &[Xml, Json],
+        ModelProvider::Mistral => &[Mistral, Json],
+        ModelProvider::Meta => &[Llama, Json],
         ModelProvider::Anthropic
         | ModelProvider::Google
         | ModelProvider::OpenAI
-        | ModelProvider::Unknown => try_parse_json_tool_call(content)
-            .or_else(|| try_parse_xml_tool_call(content))
-            .or_else(|| try_parse_moonshot_tool_call(content)),
+        | ModelProvider::Unknown => &[Json, Xml, Moonshot],
     }
 }
 
+/// Parses tool calls with the first format of the chain that matches, `preferred` first.
+pub(super) fn parse_tool_calls_with_format(
+    content: &str,
+    provider: ModelProvider,
+    preferred: Option<ToolCallFormat>,
+    string_args: Option<&dyn StringArgs>,
+) -> Option<(Vec<ToolCall>, ToolCallFormat)> {
+    preferred
+        .into_iter()
+        .chain(format_chain(provider).iter().copied())
+        .find_map(|format| {
+            format
+                .parse_with(content, string_args)
+                .map(|calls| (calls, format))
+        })
+}
+
+/// Dispatches parsing to the appropriate function based on provider and content
+pub(super) fn parse_tool_calls(content: &str, provider: ModelProvider) -> Option<Vec<ToolCall>> {
+    parse_tool_calls_with_format(content, provider, None, None).map(|(calls, _)| calls)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -246,6 +416,10 @@ mod tests {
         let input = "```\n{\"key\": \"value\"}\n```";
         assert_eq!(strip_markdown_code_block(input), "{\"key\": \"value\"}");
 
+        // Case: Single-line fence with language tag
+        let input = "```json{\"key\": \"value\"}```";
+        assert_eq!(strip_markdown_code_block(input), "{\"key\": \"value\"}");
+
         // Case: Ambiguous language tag (looks like JSON)
         let input = "```{\"a\": 1}\ncontent\n```";
         assert_eq!(strip_markdown_code_block(input), "{\"a\": 1}\ncontent");
@@ -256,22 +430,100 @@ mod tests {
         // Test clean JSON
         let content1 =
             "<tool_call>\n{\"name\": \"func1\", \"arguments\": {\"k\": \"v\"}}\n</tool_call>";
-        let tool_calls1 = try_parse_xml_tool_call(content1).expect("Should parse clean JSON");
+        let tool_calls1 = try_parse_xml_tool_call(content1, None).expect("Should parse clean JSON");
         assert_eq!(tool_calls1[0].function.name, "func1");
 
         // Test JSON in markdown block
         let content2 = "<tool_call>\n```json\n{\"name\": \"func2\", \"arguments\": {\"k\": \"v\"}}\n```\n</tool_call>";
-        let tool_calls2 = try_parse_xml_tool_call(content2).expect("Should parse markdown JSON");
+        let tool_calls2 =
+            try_parse_xml_tool_call(content2, None).expect("Should parse markdown JSON");
         assert_eq!(tool_calls2[0].function.name, "func2");
     }
 
+    #[test]
+    fn test_qwen_back_to_back_tool_calls() {
+        let content = concat!(
+            "<tool_call>{\"name\": \"func1\", \"arguments\": {\"a\": 1}}</tool_call>",
+            "<tool_call>{\"name\": \"func2\", \"arguments\": {\"b\": 2}}</tool_call>",
+            "<tool_call>{\"name\": \"func3\", \"arguments\": {\"c\": 3}}</tool_call>"
+        );
+        let tool_calls = try_parse_xml_tool_call(content, None).expect("Should parse all three");
+        assert_eq!(tool_calls.len(), 3);
+        assert_eq!(tool_calls[0].function.name, "func1");
+        assert_eq!(tool_calls[1].function.name, "func2");
+        assert_eq!(tool_calls[2].function.name, "func3");
+        assert_eq!(tool_calls[2].function.arguments["c"], 3);
+
+        // Each call gets its own ID
+        assert_ne!(tool_calls[0].id, tool_calls[1].id);
+        assert_ne!(tool_calls[1].id, tool_calls[2].id);
+    }
+
+    #[test]
+    fn test_qwen_back_to_back_markdown_tool_calls() {
+        let content = concat!(
+            "<tool_call>```json\n{\"name\": \"func1\", \"arguments\": {}}\n```</tool_call>",
+            "<tool_call>```json\n{\"name\": \"func2\", \"arguments\": {}}\n```</tool_call>",
+            "<tool_call>```json\n{\"name\": \"func3\", \"arguments\": {}}\n```</tool_call>"
+        );
+        let tool_calls = try_parse_xml_tool_call(content, None).expect("Should parse all three");
+        let names: Vec<_> = tool_calls
+            .iter()
+            .map(|tc| tc.function.name.as_str())
+            .collect();
+        assert_eq!(names, ["func1", "func2", "func3"]);
+    }
+
+    #[test]
+    fn test_qwen_back_to_back_single_line_markdown_tool_calls() {
+        let content = concat!(
+            "<tool_call>```json{\"name\": \"func1\", \"arguments\": {}}```</tool_call>",
+            "<tool_call>```json{\"name\": \"func2\", \"arguments\": {}}```</tool_call>",
+            "<tool_call>```{\"name\": \"func3\", \"arguments\": {}}```</tool_call>"
+        );
+        let tool_calls = try_parse_xml_tool_call(content, None).expect("Should parse all three");
+        let names: Vec<_> = tool_calls
+            .iter()
+            .map(|tc| tc.function.name.as_str())
+            .collect();
+        assert_eq!(names, ["func1", "func2", "func3"]);
+    }
+
+    #[test]
+    fn test_qwen_tool_calls_inside_single_markdown_block() {
+        let content = concat!(
+            "```xml\n",
+            "<tool_call>{\"name\": \"func1\", \"arguments\": {}}</tool_call>",
+            "<tool_call>{\"name\": \"func2\", \"arguments\": {}}</tool_call>",
+            "<tool_call>{\"name\": \"func3\", \"arguments\": {}}</tool_call>\n",
+            "```"
+        );
+        let tool_calls = ModelProvider::Qwen
+            .parse_tool_calls(content)
+            .expect("Should parse all three");
+        assert_eq!(tool_calls.len(), 3);
+    }
+
+    #[test]
+    fn test_qwen_json_wrapper_is_a_fallback() {
+        let content =
+            r#"<tool_calls>[{"name": "get_weather", "arguments": {"city": "Paris"}}]</tool_calls>"#;
+        let (tool_calls, format) = ModelProvider::Qwen
+            .parse_tool_calls_with_format(content, None)
+            .expect("Should parse the JSON wrapper");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(format, ToolCallFormat::Json);
+        assert_ne!(format, ModelProvider::Qwen.tool_call_format());
+    }
+
     #[test]
     fn test_xml_custom_format_parsing() {
         let content = r#"<tool_call>read
 <arg_key>filePath</arg_key>
 <arg_value>/tmp/test_file.txt</arg_value>
 </tool_call>"#;
-        let tool_calls = try_parse_xml_tool_call(content).expect("Should parse XML custom format");
+        let tool_calls =
+            try_parse_xml_tool_call(content, None).expect("Should parse XML custom format");
         assert_eq!(tool_calls[0].function.name, "read");
         assert_eq!(
             tool_calls[0].function.arguments["filePath"],
@@ -279,6 +531,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_xml_arg_values_keep_their_json_types() {
+        let content = r#"<tool_call>search
+<arg_key>limit</arg_key>
+<arg_value>3</arg_value>
+<arg_key>paths</arg_key>
+<arg_value>["src", "docs"]</arg_value>
+<arg_key>zip</arg_key>
+<arg_value>02139</arg_value>
+<arg_key>quote</arg_key>
+<arg_value>"as said"</arg_value>
+</tool_call>"#;
+        let tool_calls = try_parse_xml_tool_call(content, None).unwrap();
+        assert_eq!(
+            tool_calls[0].function.arguments,
+            serde_json::json!({
+                "limit": 3,
+                "paths": ["src", "docs"],
+                "zip": "02139",
+                "quote": "\"as said\""
+            })
+        );
+    }
+
+    #[test]
+    fn test_xml_string_arg_values_are_kept_raw() {
+        struct Declared;
+        impl StringArgs for Declared {
+            fn is_string(&self, function: &str, key: &str) -> bool {
+                function == "lookup" && matches!(key, "code" | "flag")
+            }
+        }
+
+        let content = r#"<tool_call>lookup
+<arg_key>code</arg_key>
+<arg_value>1.50</arg_value>
+<arg_key>flag</arg_key>
+<arg_value>true</arg_value>
+<arg_key>limit</arg_key>
+<arg_value>3</arg_value>
+</tool_call>"#;
+        let tool_calls = ToolCallFormat::Xml
+            .parse_with(content, Some(&Declared))
+            .unwrap();
+        assert_eq!(
+            tool_calls[0].function.arguments,
+            serde_json::json!({"code": "1.50", "flag": "true", "limit": 3})
+        );
+    }
+
+    #[test]
+    fn test_xml_name_with_json_arguments_parsing() {
+        let content = "<tool_call>get_weather\n{\"location\":\"Boston\"}</tool_call>";
+        let tool_calls =
+            try_parse_xml_tool_call(content, None).expect("Should parse Z.ai hybrid format");
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments["location"], "Boston");
+    }
+
     #[test]
     fn test_moonshot_parsing() {
         let content = r#"<|tool_calls_section_begin|><|tool_call_begin|>functions.view:0<|tool_call_argument_begin|>{"file_path": "/tmp/random_file.txt"}<|tool_call_end|><|tool_calls_section_end|>"#;
@@ -290,4 +602,59 @@ mod tests {
             "/tmp/random_file.txt"
         );
     }
+
+    #[test]
+    fn test_mistral_parsing() {
+        let content = r#"[TOOL_CALLS][{"name": "get_weather", "arguments": {"city": "Paris", "unit": "celsius"}}, {"name": "list_files", "arguments": {}}]"#;
+        let tool_calls = ModelProvider::Mistral
+            .parse_tool_calls(content)
+            .expect("Should parse Mistral format");
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            tool_calls[0].function.arguments,
+            serde_json::json!({"city": "Paris", "unit": "celsius"})
+        );
+        assert_eq!(tool_calls[1].function.name, "list_files");
+        assert_eq!(tool_calls[1].function.arguments, serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_mistral_parsing_after_text_and_with_string_arguments() {
+        let content = "Let me look that up.\n[TOOL_CALLS] [{\"name\": \"search\", \"arguments\": \"{\\\"query\\\": \\\"rust\\\"}\"}]</s>";
+        let tool_calls = try_parse_mistral_tool_call(content).expect("Should parse Mistral format");
+        assert_eq!(tool_calls[0].function.name, "search");
+        assert_eq!(tool_calls[0].function.arguments["query"], "rust");
+
+        assert!(try_parse_mistral_tool_call("[TOOL_CALLS] not json").is_none());
+        assert!(try_parse_mistral_tool_call("No tool calls here").is_none());
+    }
+
+    #[test]
+    fn test_llama_python_tag_parsing() {
+        let content =
+            r#"<|python_tag|>{"name": "get_weather", "parameters": {"city": "Paris"}}<|eom_id|>"#;
+        let tool_calls = ModelProvider::Meta
+            .parse_tool_calls(content)
+            .expect("Should parse Llama format");
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            tool_calls[0].function.arguments,
+            serde_json::json!({"city": "Paris"})
+        );
+    }
+
+    #[test]
+    fn test_llama_semicolon_separated_calls() {
+        let content = r#"<|python_tag|>{"name": "search", "parameters": {"query": "a; b"}}; {"name": "list_files", "arguments": {}};"#;
+        let tool_calls = try_parse_llama_tool_call(content).expect("Should parse both calls");
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].function.name, "search");
+        assert_eq!(tool_calls[0].function.arguments["query"], "a; b");
+        assert_eq!(tool_calls[1].function.name, "list_files");
+        assert_eq!(tool_calls[1].function.arguments, serde_json::json!({}));
+
+        assert!(try_parse_llama_tool_call("<|python_tag|>print('hi')").is_none());
+    }
 }
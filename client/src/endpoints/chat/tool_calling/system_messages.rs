@@ -1,19 +1,77 @@
-use super::types::{ModelProvider, OpenAiTool};
+use super::types::{ModelProvider, OpenAiFunction, OpenAiTool, OpenAiToolChoice};
 use crate::endpoints::chat::common_types::ChatMessage;
 // Note: We use the re-exported error here to match what's expected in the main module
 // once we update the re-exports. For now, we use the local ToolCallingError where appropriate.
 use super::error::ToolCallingError;
 
+/// Heading that opens the injected tools system message.
+const TOOLS_HEADER: &str =
+    "# Tools\n\nYou may call one or more functions to assist with the user query.";
+
+/// Sentence introducing the `<tools>` block with the function signatures.
+const TOOLS_PREAMBLE: &str =
+    "You are provided with function signatures within <tools></tools> XML tags:";
+
 /// Shared preamble for all providers, standardizing the function definitions section.
 pub fn build_tools_preamble(
     functions: &[&super::types::OpenAiFunction],
 ) -> Result<String, ToolCallingError> {
     Ok(format!(
-        "You are provided with function signatures within <tools></tools> XML tags:\n<tools>\n{}\n</tools>",
+        "{}\n<tools>\n{}\n</tools>",
+        TOOLS_PREAMBLE,
         serde_json::to_string_pretty(&functions)?
     ))
 }
 
+/// Returns true if `content` already holds a `<tools>...</tools>` block, e.g. a client that
+/// embeds its own tool definitions in the system prompt.
+pub fn contains_tools_block(content: &str) -> bool {
+    content
+        .find("<tools>")
+        .is_some_and(|start| content[start..].contains("</tools>"))
+}
+
+/// Removes the first `<tools>...</tools>` block from `content`, returning the remaining text
+/// and the function definitions the block held.
+///
+/// Entries may be bare function objects or OpenAI-style `{"type": "function", "function":
+/// {...}}` tools. Returns `None` if there is no block or it is not a JSON array of either.
+pub fn take_tools_block(content: &str) -> Option<(String, Vec<OpenAiFunction>)> {
+    let start = content.find("<tools>")?;
+    let end = start + content[start..].find("</tools>")?;
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(content[start + "<tools>".len()..end].trim()).ok()?;
+    let functions = entries
+        .into_iter()
+        .map(|entry| {
+            serde_json::from_value::<OpenAiTool>(entry.clone())
+                .map(|OpenAiTool::Function(function)| function)
+                .or_else(|_| serde_json::from_value::<OpenAiFunction>(entry))
+                .ok()
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let before = content[..start].trim_end();
+    let after = content[end + "</tools>".len()..].trim_start();
+    let rest = if before.is_empty() || after.is_empty() {
+        format!("{before}{after}")
+    } else {
+        format!("{before}\n\n{after}")
+    };
+    Some((rest, functions))
+}
+
+/// Combines structured `tools` with `inline` definitions, keeping one definition per
+/// function name. Structured tools come first and win on clashes.
+pub fn merge_tools(tools: Vec<OpenAiTool>, inline: Vec<OpenAiFunction>) -> Vec<OpenAiTool> {
+    let mut seen = std::collections::HashSet::new();
+    tools
+        .into_iter()
+        .chain(inline.into_iter().map(OpenAiTool::Function))
+        .filter(|OpenAiTool::Function(function)| seen.insert(function.name.clone()))
+        .collect()
+}
+
 /// Returns tool calling format instructions for the Zai provider.
 ///
 /// Uses XML tags with function name and arg_key/arg_value pairs.
@@ -109,6 +167,31 @@ Example of multiple tool calls:
 <|tool_calls_section_begin|><|tool_call_begin|>search_web<|tool_call_argument_begin|>{"query": "latest AI news"}<|tool_call_end|><|tool_call_begin|>summarize_text<|tool_call_argument_begin|>{"text": "A long text to be summarized..."}<|tool_call_end|><|tool_calls_section_end|>"#.to_string()
 }
 
+/// Returns tool calling format instructions for the Mistral provider.
+///
+/// Uses Mistral's native `[TOOL_CALLS]` token followed by a JSON array.
+pub(super) fn mistral_calling_instructions() -> String {
+    r#"# Tool Call Format
+
+⚠️ CRITICAL: You MUST use the following exact wrapper syntax. This is not optional.
+
+[TOOL_CALLS][{"name": "function_name", "arguments": {"arg_name": "arg_value"}}]
+
+The [TOOL_CALLS] token must be followed by a JSON array where each object contains "name" and "arguments" fields.
+
+❌ DO NOT respond with tool calls in any other format. DO NOT omit the [TOOL_CALLS] token.
+
+## Examples
+
+Example of a single tool call:
+
+[TOOL_CALLS][{"name": "get_weather", "arguments": {"location": "Boston, MA"}}]
+
+Example of multiple tool calls:
+
+[TOOL_CALLS][{"name": "search_web", "arguments": {"query": "latest AI news"}}, {"name": "summarize_text", "arguments": {"text": "A long text to be summarized..."}}]"#.to_string()
+}
+
 /// Returns default JSON-based tool calling format instructions.
 ///
 /// Uses a JSON array wrapped in <tool_calls> XML tags.
@@ -170,16 +253,58 @@ pub fn build_tool_system_message(
     let calling_instructions = provider.calling_instructions();
 
     Ok(format!(
-        r###"# Tools
-
-You may call one or more functions to assist with the user query.
+        "{}\n\n{}\n\n{}\n",
+        TOOLS_HEADER, preamble, calling_instructions
+    ))
+}
 
-{}
+/// Removes a leaked copy of the injected tools system message from the start of `content`.
+///
+/// Models occasionally echo the tools instructions back before answering. The header, the
+/// `<tools>` block and the provider's calling instructions are stripped if they lead the
+/// content. Returns `None` when nothing was stripped.
+pub fn strip_leaked_tools_prompt(content: &str, provider: ModelProvider) -> Option<String> {
+    let original = content.trim_start();
+    let mut rest = original;
+
+    if let Some(after) = rest.strip_prefix(TOOLS_HEADER) {
+        rest = after.trim_start();
+    }
+    if let Some(after) = rest.strip_prefix(TOOLS_PREAMBLE) {
+        rest = after.trim_start();
+    }
+    if rest.starts_with("<tools>")
+        && let Some(end) = rest.find("</tools>")
+    {
+        rest = rest[end + "</tools>".len()..].trim_start();
+    }
+    if rest.len() == original.len() {
+        // Calling instructions alone are only stripped as part of a leaked tools prompt
+        return None;
+    }
+    if let Some(after) = rest.strip_prefix(provider.calling_instructions().as_str()) {
+        rest = after.trim_start();
+    }
+
+    Some(rest.to_string())
+}
 
-{}
-"###,
-        preamble, calling_instructions
-    ))
+/// Extra instructions for a `tool_choice` that forces a tool call: `"required"` or a
+/// named function. Returns `None` for choices that leave the decision to the model.
+pub fn tool_choice_instructions(tool_choice: &OpenAiToolChoice) -> Option<String> {
+    match tool_choice {
+        OpenAiToolChoice::String(choice) if choice == "required" => Some(
+            "# Tool Choice\n\nYou MUST call at least one of the functions above in your \
+             response. Do not answer with text alone."
+                .to_string(),
+        ),
+        OpenAiToolChoice::Object(OpenAiTool::Function(function)) => Some(format!(
+            "# Tool Choice\n\nYou MUST call the `{}` function in your response. Do not call any \
+             other function and do not answer with text alone.",
+            function.name
+        )),
+        OpenAiToolChoice::String(_) => None,
+    }
 }
 
 /// Generates a system message for tool calling based on the provided tools and model provider.
@@ -190,6 +315,8 @@ You may call one or more functions to assist with the user query.
 /// # Arguments
 /// * `tools` - Slice of OpenAI tool definitions (currently only Function tools are supported)
 /// * `provider` - The model provider to generate format instructions for
+/// * `tool_choice` - The request's `tool_choice`; forcing choices add instructions that a
+///   tool call is required
 ///
 /// # Returns
 /// A `ChatMessage::system` containing the formatted tool instructions
@@ -201,6 +328,7 @@ You may call one or more functions to assist with the user query.
 pub fn tools_system_message(
     tools: &[OpenAiTool],
     provider: ModelProvider,
+    tool_choice: Option<&OpenAiToolChoice>,
 ) -> Result<ChatMessage, ToolCallingError> {
     let functions = tools
         .iter()
@@ -209,7 +337,147 @@ pub fn tools_system_message(
         })
         .collect::<Vec<_>>();
 
-    let system_message = build_tool_system_message(provider, &functions)?;
+    let mut system_message = build_tool_system_message(provider, &functions)?;
+    if let Some(instructions) = tool_choice.and_then(tool_choice_instructions) {
+        system_message.push('\n');
+        system_message.push_str(&instructions);
+        system_message.push('\n');
+    }
 
     Ok(ChatMessage::system(system_message))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weather_tools_message(provider: ModelProvider) -> String {
+        let function = OpenAiFunction {
+            name: "get_weather".to_string(),
+            description: Some("Get the weather".to_string()),
+            parameters: Some(serde_json::json!({"type": "object"})),
+        };
+        build_tool_system_message(provider, &[&function]).unwrap()
+    }
+
+    #[test]
+    fn test_strip_leaked_full_tools_prompt() {
+        for provider in [
+            ModelProvider::Qwen,
+            ModelProvider::Zai,
+            ModelProvider::Mistral,
+            ModelProvider::Unknown,
+        ] {
+            let content = format!("{}\nThe weather is sunny.", weather_tools_message(provider));
+            assert_eq!(
+                strip_leaked_tools_prompt(&content, provider).as_deref(),
+                Some("The weather is sunny.")
+            );
+        }
+    }
+
+    #[test]
+    fn test_strip_leaked_tools_block_only() {
+        let content = format!("{}\n<tools>\n[]\n</tools>\n\nHello there", TOOLS_PREAMBLE);
+        assert_eq!(
+            strip_leaked_tools_prompt(&content, ModelProvider::Unknown).as_deref(),
+            Some("Hello there")
+        );
+    }
+
+    #[test]
+    fn test_strip_leaked_tools_prompt_leaves_regular_content() {
+        assert!(strip_leaked_tools_prompt("Hello there", ModelProvider::Unknown).is_none());
+        assert!(
+            strip_leaked_tools_prompt("I used <tools> earlier", ModelProvider::Unknown).is_none()
+        );
+    }
+
+    #[test]
+    fn test_contains_tools_block() {
+        assert!(contains_tools_block(&weather_tools_message(
+            ModelProvider::Unknown
+        )));
+        assert!(contains_tools_block("Use these:\n<tools>[]</tools>"));
+        assert!(!contains_tools_block(
+            "Mention of <tools> without a closing tag"
+        ));
+        assert!(!contains_tools_block("</tools> before <tools>"));
+    }
+
+    fn forced_tools_message(tool_choice: Option<OpenAiToolChoice>) -> String {
+        let tool = OpenAiTool::Function(OpenAiFunction {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters: None,
+        });
+        tools_system_message(&[tool], ModelProvider::OpenAI, tool_choice.as_ref())
+            .unwrap()
+            .content()
+            .to_string()
+    }
+
+    #[test]
+    fn test_tool_choice_required() {
+        let choice: OpenAiToolChoice =
+            serde_json::from_value(serde_json::json!("required")).unwrap();
+        let message = forced_tools_message(Some(choice));
+        assert!(message.contains("You MUST call at least one of the functions above"));
+    }
+
+    #[test]
+    fn test_tool_choice_named_function() {
+        let choice: OpenAiToolChoice = serde_json::from_value(serde_json::json!({
+            "type": "function",
+            "function": {"name": "get_weather"}
+        }))
+        .unwrap();
+        let message = forced_tools_message(Some(choice));
+        assert!(message.contains("You MUST call the `get_weather` function"));
+    }
+
+    #[test]
+    fn test_tool_choice_auto_adds_nothing() {
+        let plain = forced_tools_message(None);
+        assert!(!plain.contains("# Tool Choice"));
+        let auto = forced_tools_message(Some(OpenAiToolChoice::String("auto".to_string())));
+        assert_eq!(auto, plain);
+    }
+
+    #[test]
+    fn test_take_tools_block() {
+        let content = "Be brief.\n<tools>\n[{\"name\": \"get_time\"}, {\"type\": \"function\", \
+                       \"function\": {\"name\": \"get_weather\"}}]\n</tools>\nAnswer in French.";
+        let (rest, functions) = take_tools_block(content).unwrap();
+        assert_eq!(rest, "Be brief.\n\nAnswer in French.");
+        let names: Vec<&str> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["get_time", "get_weather"]);
+
+        assert!(take_tools_block("<tools>get_time, get_weather</tools>").is_none());
+        assert!(take_tools_block("No tools here").is_none());
+    }
+
+    #[test]
+    fn test_merge_tools_prefers_structured_definitions() {
+        let function = |name: &str, description: &str| OpenAiFunction {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+            parameters: None,
+        };
+        let merged = merge_tools(
+            vec![OpenAiTool::Function(function("get_weather", "structured"))],
+            vec![
+                function("get_weather", "inline"),
+                function("get_time", "inline"),
+                function("get_time", "inline duplicate"),
+            ],
+        );
+        assert_eq!(
+            merged,
+            [
+                OpenAiTool::Function(function("get_weather", "structured")),
+                OpenAiTool::Function(function("get_time", "inline")),
+            ]
+        );
+    }
+}
@@ -16,7 +16,10 @@ pub struct ChatFunctionCall {
     /// # Example
     /// - In memory: `serde_json::json!({"key": "value"})`
     /// - Serialized: `"{\"key\":\"value\"}"`
+    ///
+    /// Missing, `null` and empty-string arguments are normalized to `{}`.
     #[serde(
+        default = "empty_arguments",
         deserialize_with = "string_or_object_to_value_deserializer",
         serialize_with = "value_to_string_serializer"
     )]
@@ -37,11 +40,18 @@ where
     }
 
     match StringOrObject::deserialize(deserializer)? {
+        StringOrObject::String(s) if s.trim().is_empty() => Ok(empty_arguments()),
         StringOrObject::String(s) => serde_json::from_str(&s).map_err(serde::de::Error::custom),
+        StringOrObject::Object(Value::Null) => Ok(empty_arguments()),
         StringOrObject::Object(v) => Ok(v),
     }
 }
 
+/// Arguments of a call to a function that takes none: an empty JSON object.
+pub fn empty_arguments() -> Value {
+    Value::Object(serde_json::Map::new())
+}
+
 pub fn value_to_string_serializer<S>(
     value: &serde_json::Value,
     serializer: S,
@@ -61,15 +71,20 @@ pub struct ToolCall {
     /// The index of the tool call in the list of tool calls
     #[serde(default)]
     pub index: Option<usize>,
-    /// The type of the tool (typically "function")
-    #[serde(rename = "type")]
+    /// The type of the tool (typically "function"); defaults to "function" when omitted
+    #[serde(rename = "type", default = "default_tool_type")]
     pub tool_type: String,
     /// The function call details
     pub function: ChatFunctionCall,
 }
 
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
 /// High-level provider that produced or will consume a given model ID.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum ModelProvider {
     Anthropic,
     OpenAI,
@@ -77,6 +92,8 @@ pub enum ModelProvider {
     MoonshotAI,
     Qwen,
     Google,
+    Mistral,
+    Meta,
     Unknown,
 }
 
@@ -93,6 +110,9 @@ impl From<&str> for ModelProvider {
             "moonshotai" => ModelProvider::MoonshotAI,
             "qwen" => ModelProvider::Qwen,
             "google" => ModelProvider::Google,
+            "mistralai" | "mistral" => ModelProvider::Mistral,
+            // Llama models
+            "meta-llama" | "meta" => ModelProvider::Meta,
             _ => ModelProvider::Unknown,
         }
     }
@@ -104,6 +124,7 @@ impl ModelProvider {
             ModelProvider::Zai => super::system_messages::zai_calling_instructions(),
             ModelProvider::Qwen => super::system_messages::qwen_calling_instructions(),
             ModelProvider::MoonshotAI => super::system_messages::moonshot_calling_instructions(),
+            ModelProvider::Mistral => super::system_messages::mistral_calling_instructions(),
             _ => super::system_messages::json_calling_instructions(),
         }
     }
@@ -119,6 +140,21 @@ impl ModelProvider {
         super::parsers::parse_tool_calls(content, *self)
     }
 
+    /// The tool call format this provider's models are asked to use.
+    pub fn tool_call_format(&self) -> super::parsers::ToolCallFormat {
+        super::parsers::format_chain(*self)[0]
+    }
+
+    /// Like [`parse_tool_calls`](Self::parse_tool_calls), also reporting the format that
+    /// matched. A `preferred` format is tried before the provider's own formats.
+    pub fn parse_tool_calls_with_format(
+        &self,
+        content: &str,
+        preferred: Option<super::parsers::ToolCallFormat>,
+    ) -> Option<(Vec<ToolCall>, super::parsers::ToolCallFormat)> {
+        super::parsers::parse_tool_calls_with_format(content, *self, preferred, None)
+    }
+
     pub fn format_tool_response(&self, tool_call_id: &str, content: &str) -> String {
         match self {
             ModelProvider::Qwen | ModelProvider::Zai => {
@@ -162,8 +198,11 @@ pub enum OpenAiTool {
 }
 
 /// Represents a tool choice option.
+///
+/// An object without a function name, such as `{"type": "function"}`, asks for a call to
+/// any function and is read as `"required"`.
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
-#[serde(untagged)]
+#[serde(untagged, from = "RawToolChoice")]
 pub enum OpenAiToolChoice {
     /// A string value like "none", "auto", or "required"
     String(String),
@@ -171,11 +210,68 @@ pub enum OpenAiToolChoice {
     Object(OpenAiTool),
 }
 
+/// The `tool_choice` shapes accepted on the wire.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawToolChoice {
+    String(String),
+    Object(OpenAiTool),
+    AnyFunction {
+        #[serde(rename = "type")]
+        _tool_type: FunctionType,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FunctionType {
+    Function,
+}
+
+impl From<RawToolChoice> for OpenAiToolChoice {
+    fn from(raw: RawToolChoice) -> Self {
+        match raw {
+            RawToolChoice::Object(OpenAiTool::Function(function))
+                if !function.name.trim().is_empty() =>
+            {
+                OpenAiToolChoice::Object(OpenAiTool::Function(function))
+            }
+            RawToolChoice::String(choice) => OpenAiToolChoice::String(choice),
+            RawToolChoice::Object(_) | RawToolChoice::AnyFunction { .. } => {
+                OpenAiToolChoice::String("required".to_string())
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn test_tool_choice_without_function_name_is_required() {
+        let required = OpenAiToolChoice::String("required".to_string());
+        for partial in [
+            json!({"type": "function"}),
+            json!({"type": "function", "function": null}),
+            json!({"type": "function", "function": {}}),
+            json!({"type": "function", "function": {"name": ""}}),
+        ] {
+            let choice: OpenAiToolChoice = serde_json::from_value(partial.clone()).unwrap();
+            assert_eq!(choice, required, "{partial}");
+        }
+
+        let named: OpenAiToolChoice =
+            serde_json::from_value(json!({"type": "function", "function": {"name": "view"}}))
+                .unwrap();
+        assert!(matches!(
+            named,
+            OpenAiToolChoice::Object(OpenAiTool::Function(function)) if function.name == "view"
+        ));
+        assert!(serde_json::from_value::<OpenAiToolChoice>(json!({"type": "retrieval"})).is_err());
+    }
+
     #[test]
     fn test_provider_detection_anthropic() {
         assert_eq!(
@@ -215,6 +311,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_provider_detection_mistral() {
+        for model in [
+            "mistralai/mistral-large",
+            "mistral/codestral",
+            "MistralAI/Mixtral-8x7B",
+        ] {
+            assert_eq!(ModelProvider::from(model), ModelProvider::Mistral);
+        }
+    }
+
+    #[test]
+    fn test_provider_detection_meta() {
+        for model in ["meta-llama/llama-3.1-405b-instruct", "meta/llama-3.3-70b"] {
+            assert_eq!(ModelProvider::from(model), ModelProvider::Meta);
+        }
+    }
+
     #[test]
     fn test_chat_function_call_serialization() {
         let fc = ChatFunctionCall {
@@ -282,6 +396,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_typed_arguments_survive_round_trip() {
+        let arguments = json!({
+            "count": 3,
+            "offset": -12,
+            "ratio": 0.25,
+            "scale": 2.0,
+            "recursive": true,
+            "filter": {"extensions": ["rs", "toml"], "max_depth": 4, "hidden": null}
+        });
+        let fc: ChatFunctionCall =
+            serde_json::from_value(json!({"name": "search_files", "arguments": arguments.clone()}))
+                .unwrap();
+
+        // What an OpenAI client receives is a string it parses on its own
+        let serialized = serde_json::to_value(&fc).unwrap();
+        let client_side: Value =
+            serde_json::from_str(serialized["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(client_side, arguments);
+        assert!(client_side["count"].is_u64());
+        assert!(client_side["offset"].is_i64());
+        // A whole-number float is not turned into an integer
+        assert!(client_side["scale"].is_f64());
+        assert_eq!(client_side["ratio"].as_f64(), Some(0.25));
+        assert_eq!(client_side["recursive"], true);
+
+        let round_tripped: ChatFunctionCall = serde_json::from_value(serialized).unwrap();
+        assert_eq!(round_tripped, fc);
+    }
+
+    #[test]
+    fn test_tool_call_missing_type_defaults_to_function() {
+        let json_data = json!({
+            "id": "call_123",
+            "function": {
+                "name": "test_func",
+                "arguments": "{}"
+            }
+        });
+
+        let tool_call: ToolCall = serde_json::from_value(json_data).unwrap();
+        assert_eq!(tool_call.tool_type, "function");
+        assert_eq!(tool_call.function.name, "test_func");
+    }
+
+    #[test]
+    fn test_empty_arguments_are_normalized() {
+        for arguments in [json!({}), json!(""), json!("  "), json!(null), json!("{}")] {
+            let fc: ChatFunctionCall =
+                serde_json::from_value(json!({"name": "list_files", "arguments": arguments}))
+                    .unwrap();
+            assert_eq!(fc.arguments, json!({}));
+            assert_eq!(serde_json::to_value(&fc).unwrap()["arguments"], "{}");
+        }
+
+        let fc: ChatFunctionCall = serde_json::from_value(json!({"name": "list_files"})).unwrap();
+        assert_eq!(fc.arguments, json!({}));
+    }
+
     #[test]
     fn test_format_tool_response_qwen() {
         let provider = ModelProvider::Qwen;
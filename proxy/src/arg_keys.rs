@@ -0,0 +1,194 @@
+//! Tool call arguments checked against the tool's `parameters` schema.
+//!
+//! Models sometimes emit `Location` or `userId` where the schema declares `location` or
+//! `user_id`; with `--coerce-arg-keys` these keys are renamed. Keys are matched ignoring
+//! case and `_`/`-`/space separators; keys without a match, and calls to tools without a
+//! schema, are left untouched.
+//!
+//! Arguments declared as strings are also kept as written when parsing `<arg_value>`s,
+//! so `"1.50"` or `"true"` are not decoded as a number or a boolean.
+
+use crate::types::{OpenAiTool, ToolCall};
+use serde_json::Value;
+use std::collections::HashMap;
+use straico_client::endpoints::chat::StringArgs;
+
+/// Declared argument keys per tool name.
+#[derive(Clone, Debug, Default)]
+pub struct ArgKeySchemas {
+    keys: HashMap<String, Vec<String>>,
+    /// Keys whose schema type is `string` (optionally nullable)
+    string_keys: HashMap<String, Vec<String>>,
+}
+
+impl ArgKeySchemas {
+    /// Collects the `properties` keys of each tool's `parameters` schema.
+    pub fn from_tools(tools: &[OpenAiTool]) -> Self {
+        let mut schemas = Self::default();
+        for OpenAiTool::Function(function) in tools {
+            let Some(properties) = function
+                .parameters
+                .as_ref()
+                .and_then(|parameters| parameters.get("properties"))
+                .and_then(Value::as_object)
+            else {
+                continue;
+            };
+            let strings = properties
+                .iter()
+                .filter(|(_, schema)| is_string_schema(schema))
+                .map(|(key, _)| key.clone())
+                .collect();
+            schemas
+                .keys
+                .insert(function.name.clone(), properties.keys().cloned().collect());
+            schemas.string_keys.insert(function.name.clone(), strings);
+        }
+        schemas
+    }
+
+    /// Renames argument keys of `tool_calls` to the declared key they loosely match.
+    pub fn coerce(&self, tool_calls: &mut [ToolCall]) {
+        for call in tool_calls {
+            let Some(declared) = self.keys.get(&call.function.name) else {
+                continue;
+            };
+            let Some(arguments) = call.function.arguments.as_object_mut() else {
+                continue;
+            };
+
+            let renames: Vec<(String, String)> = arguments
+                .keys()
+                .filter(|key| !declared.contains(key))
+                .filter_map(|key| {
+                    let target = declared.iter().find(|declared| {
+                        normalize(declared) == normalize(key) && !arguments.contains_key(*declared)
+                    })?;
+                    Some((key.clone(), target.clone()))
+                })
+                .collect();
+            for (from, to) in renames {
+                if let Some(value) = arguments.remove(&from) {
+                    arguments.insert(to, value);
+                }
+            }
+        }
+    }
+}
+
+impl StringArgs for ArgKeySchemas {
+    /// Matched as loosely as [`coerce`](Self::coerce) renames, since parsing comes first.
+    fn is_string(&self, function: &str, key: &str) -> bool {
+        self.string_keys.get(function).is_some_and(|declared| {
+            declared
+                .iter()
+                .any(|declared| declared == key || normalize(declared) == normalize(key))
+        })
+    }
+}
+
+/// Whether a property schema's `type` is `string`, or `string` and `null`.
+fn is_string_schema(schema: &Value) -> bool {
+    match schema.get("type") {
+        Some(Value::String(kind)) => kind == "string",
+        Some(Value::Array(kinds)) => {
+            kinds.iter().any(|kind| kind == "string")
+                && kinds.iter().all(|kind| kind == "string" || kind == "null")
+        }
+        _ => false,
+    }
+}
+
+fn normalize(key: &str) -> String {
+    key.chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OpenAiFunction;
+    use serde_json::json;
+    use straico_client::endpoints::chat::ChatFunctionCall;
+
+    fn schemas() -> ArgKeySchemas {
+        ArgKeySchemas::from_tools(&[OpenAiTool::Function(OpenAiFunction {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {"location": {"type": "string"}, "unit_system": {"type": "string"}}
+            })),
+        })])
+    }
+
+    fn call(name: &str, arguments: serde_json::Value) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            index: Some(0),
+            tool_type: "function".to_string(),
+            function: ChatFunctionCall {
+                name: name.to_string(),
+                arguments,
+            },
+        }
+    }
+
+    #[test]
+    fn test_keys_are_coerced_to_schema() {
+        let mut calls = [call(
+            "get_weather",
+            json!({"Location": "Paris", "unitSystem": "metric", "extra": 1}),
+        )];
+        schemas().coerce(&mut calls);
+        assert_eq!(
+            calls[0].function.arguments,
+            json!({"location": "Paris", "unit_system": "metric", "extra": 1})
+        );
+    }
+
+    #[test]
+    fn test_existing_key_is_not_overwritten() {
+        let mut calls = [call(
+            "get_weather",
+            json!({"location": "Paris", "LOCATION": "Rome"}),
+        )];
+        schemas().coerce(&mut calls);
+        assert_eq!(
+            calls[0].function.arguments,
+            json!({"location": "Paris", "LOCATION": "Rome"})
+        );
+    }
+
+    #[test]
+    fn test_unknown_tool_is_untouched() {
+        let mut calls = [call("read_file", json!({"Path": "/tmp/a"}))];
+        schemas().coerce(&mut calls);
+        assert_eq!(calls[0].function.arguments, json!({"Path": "/tmp/a"}));
+    }
+
+    #[test]
+    fn test_string_typed_keys() {
+        let schemas = ArgKeySchemas::from_tools(&[OpenAiTool::Function(OpenAiFunction {
+            name: "lookup".to_string(),
+            description: None,
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "zip_code": {"type": "string"},
+                    "note": {"type": ["string", "null"]},
+                    "limit": {"type": "integer"},
+                    "id": {"type": ["string", "integer"]}
+                }
+            })),
+        })]);
+        assert!(schemas.is_string("lookup", "zip_code"));
+        assert!(schemas.is_string("lookup", "zipCode"));
+        assert!(schemas.is_string("lookup", "note"));
+        assert!(!schemas.is_string("lookup", "limit"));
+        assert!(!schemas.is_string("lookup", "id"));
+        assert!(!schemas.is_string("search", "zip_code"));
+    }
+}
@@ -0,0 +1,219 @@
+//! In-memory LRU cache of non-streaming chat responses (`--cache-size`, `--cache-ttl-seconds`).
+//!
+//! Repeating a deterministic prompt spends credits and latency on the answer the proxy
+//! already has. Only requests whose temperature is unset or 0 are cached, so sampled
+//! answers are never served twice.
+
+use crate::types::OpenAiChatRequest;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use straico_client::StraicoChatRequest;
+
+/// Entry lifetime when no `--cache-ttl-seconds` is given.
+pub const DEFAULT_CACHE_TTL_SECS: u64 = 300;
+
+/// Converted responses keyed by the serialized request, safe to share across workers.
+///
+/// Keys are the full serialized request rather than a hash of it, so two requests never
+/// share an entry.
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    /// Incremented on every access; the entry with the lowest `last_used` is evicted first
+    clock: u64,
+    stats: CacheStats,
+}
+
+/// Lookups since startup, reported by `GET /admin/stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Value,
+    stored_at: Instant,
+    last_used: u64,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Returns the cache key of `request`, or `None` if its answer must not be cached.
+    ///
+    /// `upstream` is the request as built for Straico, so the key covers everything the
+    /// proxy changes on the way: model mapping and transforms, system prompts (including
+    /// the `x-straico-system-override` header), the current date note, the tools prompt
+    /// and `extra_body`. The client's model ID, stop sequences, `n` and `prompt_cache_key`
+    /// shape the response without reaching Straico, so they are covered too.
+    ///
+    /// `default_temperature` is the temperature the request is sent with when it sets none.
+    pub fn key(
+        request: &OpenAiChatRequest,
+        upstream: &StraicoChatRequest,
+        default_temperature: Option<f32>,
+    ) -> Option<String> {
+        let temperature = request.chat_request.temperature.or(default_temperature);
+        if request.stream || temperature.is_some_and(|temperature| temperature != 0.0) {
+            return None;
+        }
+        serde_json::to_string(&(
+            upstream,
+            &request.chat_request.model,
+            &request.stop,
+            request.n,
+            &request.prompt_cache_key,
+        ))
+        .ok()
+    }
+
+    /// Returns the response stored under `key`, unless it has expired.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.clock += 1;
+        let clock = entries.clock;
+        let response = match entries.map.get_mut(key) {
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => {
+                entry.last_used = clock;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                entries.map.remove(key);
+                None
+            }
+            None => None,
+        };
+        if response.is_some() {
+            entries.stats.hits += 1;
+        } else {
+            entries.stats.misses += 1;
+        }
+        response
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).stats
+    }
+
+    /// Stores `response` under `key`, evicting the least recently used entry when full.
+    pub fn insert(&self, key: String, response: Value) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.clock += 1;
+        let clock = entries.clock;
+        if !entries.map.contains_key(&key) && entries.map.len() >= self.capacity {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        entries.map.insert(
+            key,
+            Entry {
+                response,
+                stored_at: Instant::now(),
+                last_used: clock,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(extra: Value) -> OpenAiChatRequest {
+        let mut body = json!({
+            "model": "openai/gpt-4o",
+            "messages": [{"role": "user", "content": "What is 2 + 2?"}]
+        });
+        body.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn key(request: &OpenAiChatRequest, default_temperature: Option<f32>) -> Option<String> {
+        let upstream = StraicoChatRequest::try_from(request.clone()).unwrap();
+        ResponseCache::key(request, &upstream, default_temperature)
+    }
+
+    #[test]
+    fn test_only_deterministic_requests_are_cached() {
+        assert!(key(&request(json!({})), None).is_some());
+        assert!(key(&request(json!({"temperature": 0.0})), None).is_some());
+        assert!(key(&request(json!({"temperature": 0.7})), None).is_none());
+        assert!(key(&request(json!({"stream": true})), None).is_none());
+        // An unset temperature is sent with the default, which may not be deterministic
+        assert!(key(&request(json!({})), Some(0.7)).is_none());
+    }
+
+    #[test]
+    fn test_prompt_cache_key_is_part_of_the_key() {
+        let key = |extra| key(&request(extra), None).unwrap();
+        assert_eq!(
+            key(json!({"prompt_cache_key": "user-1"})),
+            key(json!({"prompt_cache_key": "user-1"}))
+        );
+        assert_ne!(
+            key(json!({"prompt_cache_key": "user-1"})),
+            key(json!({"prompt_cache_key": "user-2"}))
+        );
+        assert_ne!(key(json!({})), key(json!({"max_tokens": 5})));
+    }
+
+    #[test]
+    fn test_upstream_changes_are_part_of_the_key() {
+        let request = request(json!({}));
+        let mut upstream = StraicoChatRequest::try_from(request.clone()).unwrap();
+        let plain = ResponseCache::key(&request, &upstream, None);
+        upstream.messages.insert(
+            0,
+            straico_client::endpoints::chat::ChatMessage::system("Answer in French."),
+        );
+        assert_ne!(ResponseCache::key(&request, &upstream, None), plain);
+    }
+
+    #[test]
+    fn test_least_recently_used_entry_is_evicted() {
+        let cache = ResponseCache::new(2, Duration::from_secs(60));
+        cache.insert("1".to_string(), json!(1));
+        cache.insert("2".to_string(), json!(2));
+        assert_eq!(cache.get("1"), Some(json!(1)));
+        cache.insert("3".to_string(), json!(3));
+
+        assert_eq!(cache.get("1"), Some(json!(1)));
+        assert_eq!(cache.get("2"), None);
+        assert_eq!(cache.get("3"), Some(json!(3)));
+        assert_eq!(cache.stats(), CacheStats { hits: 3, misses: 1 });
+    }
+
+    #[test]
+    fn test_expired_entries_are_not_returned() {
+        let cache = ResponseCache::new(2, Duration::ZERO);
+        cache.insert("1".to_string(), json!(1));
+        assert_eq!(cache.get("1"), None);
+    }
+}
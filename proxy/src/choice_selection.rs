@@ -0,0 +1,96 @@
+//! Picking a single choice when the upstream returns several (`--select-best`).
+//!
+//! An upstream that fans a request out to several models answers with one choice per
+//! model. A client that asked for one choice (`n` unset or 1) gets the one the selected
+//! strategy picks; a client asking for more keeps them all.
+
+use crate::types::ChatChoice;
+use straico_client::endpoints::chat::ChatMessage;
+
+/// How to pick the returned choice from a multi-choice upstream response.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum SelectBest {
+    /// The first choice, in upstream order
+    #[default]
+    First,
+    /// The choice with the longest content
+    Longest,
+    /// The choice with the shortest content
+    Shortest,
+}
+
+/// Reduces `choices` to the single choice picked by `strategy`, re-indexed as 0.
+///
+/// Ties go to the earlier choice.
+pub fn select_choice(choices: &mut Vec<ChatChoice<ChatMessage>>, strategy: SelectBest) {
+    if choices.len() < 2 {
+        return;
+    }
+    let length = |choice: &ChatChoice<ChatMessage>| choice.message.content().to_string().len();
+    let selected = match strategy {
+        SelectBest::First => 0,
+        SelectBest::Longest => choices
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, choice)| length(choice))
+            .map_or(0, |(position, _)| position),
+        SelectBest::Shortest => choices
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, choice)| length(choice))
+            .map_or(0, |(position, _)| position),
+    };
+    let mut choice = choices.swap_remove(selected);
+    choice.index = 0;
+    *choices = vec![choice];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choices() -> Vec<ChatChoice<ChatMessage>> {
+        [
+            "A medium answer",
+            "Short",
+            "A much longer answer than the others",
+            "Tiny!",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, text)| ChatChoice {
+            index: index as u8,
+            message: ChatMessage::assistant(text),
+            finish_reason: Some("stop".to_string()),
+            logprobs: None,
+        })
+        .collect()
+    }
+
+    fn selected(strategy: SelectBest) -> String {
+        let mut choices = choices();
+        select_choice(&mut choices, strategy);
+        assert_eq!(choices.len(), 1);
+        assert_eq!(choices[0].index, 0);
+        choices[0].message.content().to_string()
+    }
+
+    #[test]
+    fn test_select_first() {
+        assert_eq!(selected(SelectBest::First), "A medium answer");
+    }
+
+    #[test]
+    fn test_select_longest() {
+        assert_eq!(
+            selected(SelectBest::Longest),
+            "A much longer answer than the others"
+        );
+    }
+
+    #[test]
+    fn test_select_shortest_prefers_earlier_on_tie() {
+        assert_eq!(selected(SelectBest::Shortest), "Short");
+    }
+}
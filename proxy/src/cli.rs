@@ -1,5 +1,17 @@
-use crate::streaming::HeartbeatChar;
+use crate::cache::DEFAULT_CACHE_TTL_SECS;
+use crate::choice_selection::SelectBest;
+use crate::error::{parse_status_mapping, RateLimitStatus};
+use crate::headers::AcceptMismatch;
+use crate::latency::DEFAULT_LATENCY_WINDOW;
+use crate::streaming::{HeartbeatChar, SseDialect};
+use crate::system_prompt::{CurrentDateZone, SystemOverrideMode, SystemPromptMode};
+use crate::token_limits::MaxTokensPolicy;
+use crate::tool_policy::ToolsOverLimit;
+use actix_web::http::KeepAlive;
 use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
+use straico_client::client::{DEFAULT_BASE_URL, DEFAULT_CHAT_PATH};
 #[derive(Parser, Debug, Clone)]
 #[command(
     name = "straico-proxy",
@@ -31,7 +43,372 @@ pub struct Cli {
     #[arg(long, value_enum, default_value = "empty")]
     pub heartbeat_char: HeartbeatChar,
 
+    /// Seconds between heartbeat chunks while a streamed response waits for the upstream.
+    /// Keep it below the client's idle timeout: a client that gives up on a silent
+    /// connection retries, sending the request upstream twice
+    #[arg(long, default_value_t = 3, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub heartbeat_interval: u64,
+
+    /// End a stream with an error after this many heartbeats without an upstream response.
+    /// Must be at least 1; leave it unset for no limit
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_heartbeats: Option<u32>,
+
+    /// Retry an upstream request this many times after a 429, 5xx or connection error
+    /// (other 4xx responses are never retried). `x-straico-max-retries` overrides it per request
+    #[arg(long, default_value_t = 2, value_name = "N")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled for each further one. An upstream
+    /// `retry-after` header takes precedence
+    #[arg(long, default_value_t = 500, value_name = "MS")]
+    pub retry_base_delay_ms: u64,
+
     /// HTTPS port to reject TLS connections with helpful error (default: HTTP port + 1)
     #[arg(long, env = "STRAICO_PROXY_HTTPS_PORT")]
     pub https_port: Option<u16>,
+
+    /// Leading prefix to strip from model IDs before forwarding (e.g. "straico/")
+    #[arg(long)]
+    pub strip_model_prefix: Option<String>,
+
+    /// Include the raw Straico response under `_debug.raw_response` in non-streaming responses
+    #[arg(long)]
+    pub include_debug_info: bool,
+
+    /// Add each tool call's arguments as a JSON object under `_debug.parsed_arguments` in
+    /// non-streaming responses
+    #[arg(long)]
+    pub debug_tool_arguments: bool,
+
+    /// Repair common client mistakes (e.g. numeric message content) instead of rejecting them
+    #[arg(long)]
+    pub lenient_parsing: bool,
+
+    /// Ignore `tools`/`tool_choice` and forward requests as plain chat
+    #[arg(long)]
+    pub disable_tools: bool,
+
+    /// Prepend a system note with today's date to every request (UTC unless `local` is given)
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "utc")]
+    pub inject_current_date: Option<CurrentDateZone>,
+
+    /// System prompt added to incoming requests, as controlled by `--system-prompt-mode`
+    #[arg(long, env = "STRAICO_PROXY_SYSTEM_PROMPT", value_name = "TEXT")]
+    pub system_prompt: Option<String>,
+
+    /// Whether `--system-prompt` only fills in for requests without a system message, is
+    /// added after theirs, or replaces it
+    #[arg(long, value_enum, default_value = "fill", requires = "system_prompt")]
+    pub system_prompt_mode: SystemPromptMode,
+
+    /// Whether an `x-straico-system-override` header replaces the request's system prompt
+    /// or is prepended to it
+    #[arg(long, value_enum, default_value = "replace")]
+    pub system_override_mode: SystemOverrideMode,
+
+    /// Remove leaked copies of the injected tools instructions from response content
+    #[arg(long)]
+    pub strip_leaked_tools_prompt: bool,
+
+    /// Remove leaked chat template tokens (e.g. `<|im_end|>`, `</s>`) from response content;
+    /// give a comma-separated list to replace the default tokens
+    #[arg(long, num_args = 0.., value_delimiter = ',', value_name = "TOKENS")]
+    pub strip_special_tokens: Option<Vec<String>>,
+
+    /// Move a leading reasoning block (`<think>...</think>` and similar) out of response
+    /// content into `reasoning_content`; give a comma-separated list of opening tags to
+    /// replace the defaults (closing tags add a `/` after the first character)
+    #[arg(long, num_args = 0.., value_delimiter = ',', value_name = "TAGS")]
+    pub extract_reasoning: Option<Vec<String>>,
+
+    /// Remove leading and trailing whitespace (e.g. padding newlines) from response content
+    #[arg(long)]
+    pub trim_response: bool,
+
+    /// Return one choice, picked by this strategy, when the upstream answers with several
+    /// (e.g. one per model) and the request's `n` is unset or 1; `first` unless a strategy
+    /// is given
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "first")]
+    pub select_best: Option<SelectBest>,
+
+    /// Rename tool call argument keys to the schema's keys they loosely match (e.g. `Location`)
+    #[arg(long)]
+    pub coerce_arg_keys: bool,
+
+    /// Base URL of the upstream Straico API (or a compatible backend)
+    #[arg(long, default_value = DEFAULT_BASE_URL)]
+    pub upstream_url: String,
+
+    /// HTTP(S) proxy for upstream requests (default: `HTTP_PROXY`/`HTTPS_PROXY`, minus `NO_PROXY`)
+    #[arg(long, value_name = "URL")]
+    pub upstream_proxy: Option<String>,
+
+    /// PEM file of extra root certificates to trust for upstream TLS (e.g. a corporate
+    /// TLS interceptor or a self-signed mock server)
+    #[arg(long, value_name = "PATH")]
+    pub upstream_ca_cert: Option<PathBuf>,
+
+    /// Do not verify upstream TLS certificates. Exposes the API key to anyone on the network
+    /// path; for local testing only
+    #[arg(long)]
+    pub upstream_insecure: bool,
+
+    /// Upstream path of the chat completions endpoint
+    #[arg(long, default_value = DEFAULT_CHAT_PATH)]
+    pub chat_path: String,
+
+    /// Mount all routes under this path prefix, e.g. `/api` serves `/api/v1/chat/completions`
+    /// (for running behind a reverse proxy on a subpath)
+    #[arg(long, default_value = "", value_parser = parse_route_prefix, value_name = "PREFIX")]
+    pub route_prefix: String,
+
+    /// Worker threads serving requests (default: one per CPU core)
+    #[arg(long, value_name = "N", value_parser = parse_workers)]
+    pub workers: Option<usize>,
+
+    /// Idle time before a keep-alive connection is closed; 0 disables keep-alive
+    /// (default: 5)
+    #[arg(long, value_name = "SECS")]
+    pub keep_alive_secs: Option<u64>,
+
+    /// Time a client has to send the request headers before getting 408; 0 disables the
+    /// limit (default: 5)
+    #[arg(long, value_name = "SECS")]
+    pub client_request_timeout_secs: Option<u64>,
+
+    /// Longest wait for Straico to answer a request. Non-streaming requests fail with 504
+    /// after this long; for streaming requests it bounds the wait for the first byte
+    #[arg(long, default_value_t = 90, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub request_timeout: u64,
+
+    /// JSON file mapping model aliases to upstream model IDs (reload via `POST /admin/reload`)
+    #[arg(long)]
+    pub model_map: Option<PathBuf>,
+
+    /// JSON file of per-model request transformations (system prompt, temperature cap,
+    /// tool call format)
+    #[arg(long, value_name = "PATH")]
+    pub model_transforms: Option<PathBuf>,
+
+    /// Start streamed responses with an SSE `retry: <MS>` line (client reconnection delay)
+    #[arg(long, value_name = "MS")]
+    pub sse_retry_ms: Option<u64>,
+
+    /// Response framing for clients that expect a specific dialect (`azure` also adds
+    /// content-filter results to non-streaming responses)
+    #[arg(long, value_enum, default_value = "openai")]
+    pub sse_dialect: SseDialect,
+
+    /// Add a non-standard, increasing `chunk_index` to every streamed JSON chunk (for
+    /// verifying ordering and detecting dropped chunks)
+    #[arg(long)]
+    pub sse_chunk_index: bool,
+
+    /// Return errors for `stream: true` requests as an SSE error event plus `[DONE]`
+    #[arg(long)]
+    pub errors_as_sse: bool,
+
+    /// How to answer `stream: true` requests whose `Accept` header excludes `text/event-stream`
+    #[arg(long, value_enum, default_value = "stream")]
+    pub accept_mismatch: AcceptMismatch,
+
+    /// Only allow these tool names in requests (comma-separated)
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    pub allow_tools: Option<Vec<String>>,
+
+    /// Reject requests offering any of these tool names (comma-separated)
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    pub deny_tools: Vec<String>,
+
+    /// Reject requests where a single message's content exceeds this many characters
+    #[arg(long, value_name = "CHARS")]
+    pub max_message_length: Option<usize>,
+
+    /// Limit the tool definitions injected into the system prompt to this many characters
+    #[arg(long, value_name = "CHARS")]
+    pub max_tools_prompt_length: Option<usize>,
+
+    /// What to do when the tool definitions exceed `--max-tools-prompt-length`
+    #[arg(
+        long,
+        value_enum,
+        default_value = "reject",
+        requires = "max_tools_prompt_length"
+    )]
+    pub tools_prompt_over_limit: ToolsOverLimit,
+
+    /// Reject requests that contain no user or tool message (only system/assistant turns)
+    #[arg(long)]
+    pub require_user_message: bool,
+
+    /// Reject requests whose history holds more than this many assistant tool-call turns
+    /// (guards against runaway agent loops)
+    #[arg(long, value_name = "N")]
+    pub max_tool_turns: Option<usize>,
+
+    /// What to do with a `max_tokens` above the model's known output limit
+    #[arg(long, value_enum, default_value = "reject")]
+    pub max_tokens_over_limit: MaxTokensPolicy,
+
+    /// Send a tiny nonzero temperature instead of 0 to these model providers (comma-separated,
+    /// e.g. `anthropic`), for models that treat 0 as "use the default"
+    #[arg(long, value_delimiter = ',', value_name = "PROVIDERS")]
+    pub zero_temperature_epsilon: Vec<String>,
+
+    /// Temperature sent upstream when a request does not set one (0.0 to 2.0)
+    #[arg(long, value_parser = parse_temperature, value_name = "TEMPERATURE")]
+    pub default_temperature: Option<f32>,
+
+    /// HTTP status for upstream rate limits (503 suits clients that mishandle 429)
+    #[arg(long, value_enum, default_value = "429")]
+    pub rate_limit_status: RateLimitStatus,
+
+    /// Return upstream error statuses as different statuses, e.g. `403=401` (comma-separated)
+    #[arg(long, value_delimiter = ',', value_parser = parse_status_mapping, value_name = "UPSTREAM=CLIENT")]
+    pub remap_status: Vec<(u16, u16)>,
+
+    /// Add error sources, request id and detected provider under `_debug` in error responses
+    /// (development only: exposes internals)
+    #[arg(long)]
+    pub verbose_errors: bool,
+
+    /// Log raw request/response bodies for this fraction of requests (0.0 to 1.0)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_sample_rate, value_name = "RATE")]
+    pub debug_sample_rate: f64,
+
+    /// Maximum number of chat completions forwarded upstream at the same time
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_concurrent_requests: Option<u32>,
+
+    /// Requests that may wait for a free slot when `--max-concurrent-requests` is reached
+    /// (further requests get 503)
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub queue_depth: usize,
+
+    /// Longest time a queued request waits for a free slot before getting 503
+    #[arg(long, default_value_t = 30_000, value_name = "MS")]
+    pub queue_timeout_ms: u64,
+
+    /// Bearer token required by the `/admin` endpoints (disabled when unset)
+    #[arg(long, env = "STRAICO_PROXY_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Ping the upstream every SECS seconds to keep connections warm and notice an expired
+    /// API key early (disabled by default)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub warmup_interval_secs: Option<u64>,
+
+    /// Make `GET /readyz` fetch the upstream model list (fails after 5s). Off by default so
+    /// frequent probes don't hit the Straico API
+    #[arg(long)]
+    pub readiness_check_upstream: bool,
+
+    /// Try a model's last fallback tool call format first on its later responses, once its
+    /// tool calls stopped matching the format expected from its provider
+    #[arg(long)]
+    pub adapt_tool_format: bool,
+
+    /// Number of recent requests per model that `GET /admin/stats` latencies are computed from
+    #[arg(long, default_value_t = DEFAULT_LATENCY_WINDOW, value_name = "N")]
+    pub latency_window: usize,
+
+    /// Cache up to this many non-streaming responses to requests with temperature unset or 0,
+    /// answering repeats without calling the upstream. Hits are stored by `--persist-db` and
+    /// counted under `cache` in `GET /admin/stats`, but not in the per-model latency
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub cache_size: Option<u64>,
+
+    /// How long a cached response is served
+    #[arg(long, default_value_t = DEFAULT_CACHE_TTL_SECS, value_name = "SECS")]
+    pub cache_ttl_seconds: u64,
+
+    /// Store every completed request and its response in this SQLite database
+    #[arg(long, value_name = "PATH")]
+    pub persist_db: Option<PathBuf>,
+
+    /// OpenAI-compatible moderation endpoint that checks user content before forwarding
+    #[arg(long, value_name = "URL")]
+    pub moderation_url: Option<String>,
+
+    /// Model to request from the moderation endpoint
+    #[arg(long, requires = "moderation_url")]
+    pub moderation_model: Option<String>,
+
+    /// API key for the moderation endpoint
+    #[arg(long, env = "MODERATION_API_KEY", hide_env_values = true)]
+    pub moderation_key: Option<String>,
+}
+
+impl Cli {
+    /// Keep-alive setting for the HTTP server, when `--keep-alive-secs` is given.
+    pub fn keep_alive(&self) -> Option<KeepAlive> {
+        self.keep_alive_secs.map(|secs| match secs {
+            0 => KeepAlive::Disabled,
+            secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+        })
+    }
+
+    /// Client request timeout for the HTTP server, when `--client-request-timeout-secs`
+    /// is given.
+    pub fn client_request_timeout(&self) -> Option<Duration> {
+        self.client_request_timeout_secs.map(Duration::from_secs)
+    }
+}
+
+fn parse_workers(value: &str) -> Result<usize, String> {
+    let workers: usize = value.parse().map_err(|e| format!("{e}"))?;
+    if workers == 0 {
+        Err("at least one worker is required".to_string())
+    } else {
+        Ok(workers)
+    }
+}
+
+fn parse_sample_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("{rate} is not between 0.0 and 1.0"))
+    }
+}
+
+fn parse_temperature(value: &str) -> Result<f32, String> {
+    let temperature: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=2.0).contains(&temperature) {
+        Ok(temperature)
+    } else {
+        Err(format!("{temperature} is not between 0.0 and 2.0"))
+    }
+}
+
+/// Normalizes a route prefix to a leading slash and no trailing slash (`api/` -> `/api`).
+fn parse_route_prefix(value: &str) -> Result<String, String> {
+    let prefix = value.trim().trim_matches('/');
+    if prefix.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("/{prefix}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_heartbeats_must_be_positive() {
+        // A cap of 0 would fail every stream before the upstream could answer
+        assert!(Cli::try_parse_from(["straico-proxy", "--max-heartbeats", "0"]).is_err());
+        let cli = Cli::parse_from(["straico-proxy", "--max-heartbeats", "1"]);
+        assert_eq!(cli.max_heartbeats, Some(1));
+    }
+
+    #[test]
+    fn test_workers_must_be_positive() {
+        assert!(Cli::try_parse_from(["straico-proxy", "--workers", "0"]).is_err());
+        let cli = Cli::parse_from(["straico-proxy", "--workers", "2"]);
+        assert_eq!(cli.workers, Some(2));
+    }
 }
@@ -1,12 +1,71 @@
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
 use reqwest::Error as ReqwestError;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use straico_client::{ChatError, StraicoError};
 use thiserror::Error;
 
 use crate::streaming::create_error_chunk_with_type;
 
+/// HTTP status returned for rate-limit errors (`--rate-limit-status`).
+///
+/// Some clients mishandle `429` but back off correctly on `503` with `Retry-After`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum RateLimitStatus {
+    /// 429 Too Many Requests
+    #[default]
+    #[value(name = "429")]
+    TooManyRequests,
+    /// 503 Service Unavailable
+    #[value(name = "503")]
+    ServiceUnavailable,
+}
+
+impl RateLimitStatus {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            RateLimitStatus::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            RateLimitStatus::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Client-facing statuses for upstream error statuses (`--remap-status`), e.g. upstream
+/// `403` returned as `401`. Statuses without an entry are passed through unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatusRemap(HashMap<u16, u16>);
+
+impl StatusRemap {
+    pub fn new(pairs: impl IntoIterator<Item = (u16, u16)>) -> Self {
+        Self(pairs.into_iter().collect())
+    }
+
+    /// Returns the status to report for an upstream `status`, if it is remapped.
+    pub fn get(&self, status: u16) -> Option<u16> {
+        self.0.get(&status).copied()
+    }
+}
+
+/// Parses an `UPSTREAM=CLIENT` status pair, both error statuses (400-599).
+pub fn parse_status_mapping(value: &str) -> Result<(u16, u16), String> {
+    let (upstream, client) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected UPSTREAM=CLIENT, got {value:?}"))?;
+    let parse = |status: &str| -> Result<u16, String> {
+        let status: u16 = status.trim().parse().map_err(|e| format!("{e}"))?;
+        if (400..=599).contains(&status) {
+            Ok(status)
+        } else {
+            Err(format!("{status} is not an error status (400-599)"))
+        }
+    };
+    Ok((parse(upstream)?, parse(client)?))
+}
+
 #[derive(Error, Debug)]
 pub enum ProxyError {
     #[error("Failed to serialize or deserialize JSON")]
@@ -31,12 +90,17 @@ pub enum ProxyError {
     Unauthorized(String),
     #[error("Forbidden: {0}")]
     Forbidden(String),
+    #[error("Content filtered: {0}")]
+    ContentFiltered(String),
     #[error("Not found: {0}")]
     NotFound(String),
     #[error("Rate limited: {message}")]
     RateLimited {
         retry_after: Option<u64>,
         message: String,
+        /// Status the error is rendered with (`--rate-limit-status`, or the `--remap-status`
+        /// of 429)
+        status: StatusCode,
     },
     #[error("Service unavailable: {0}")]
     ServiceUnavailable(String),
@@ -57,6 +121,9 @@ impl ProxyError {
             }
             ProxyError::ToolEmbedding(e) => format!("Tool error: {e}"),
             ProxyError::SerdeJson(e) => format!("Invalid JSON: {e}"),
+            ProxyError::ReqwestClient(e) if e.is_timeout() => {
+                format!("Upstream did not respond in time: {e}")
+            }
             ProxyError::ReqwestClient(e) => format!("Network error: {e}"),
             ProxyError::Straico(e) => format!("Upstream API error: {e}"),
             ProxyError::ResponseParse(_) => {
@@ -66,10 +133,12 @@ impl ProxyError {
             ProxyError::BadRequest(e) => format!("Bad request: {e}"),
             ProxyError::Unauthorized(msg) => format!("Unauthorized: {msg}"),
             ProxyError::Forbidden(msg) => format!("Forbidden: {msg}"),
+            ProxyError::ContentFiltered(msg) => format!("Content filtered: {msg}"),
             ProxyError::NotFound(msg) => format!("Not found: {msg}"),
             ProxyError::RateLimited {
                 retry_after,
                 message,
+                ..
             } => {
                 format!(
                     "Rate limited: {message}{}",
@@ -88,7 +157,10 @@ impl ProxyError {
                 format!("Upstream error: {msg}")
             }
         };
-        create_error_chunk_with_type(&message, self.error_type(), self.error_code())
+        let mut chunk =
+            create_error_chunk_with_type(&message, self.error_type(), self.error_code());
+        chunk["error"]["param"] = self.error_param().into();
+        chunk
     }
 
     /// Maps the error to an appropriate OpenAI-compatible error type
@@ -105,6 +177,7 @@ impl ProxyError {
             ProxyError::BadRequest(_) => "invalid_request_error",
             ProxyError::Unauthorized(_) => "authentication_error",
             ProxyError::Forbidden(_) => "permission_error",
+            ProxyError::ContentFiltered(_) => "invalid_request_error",
             ProxyError::NotFound(_) => "invalid_request_error",
             ProxyError::RateLimited { .. } => "rate_limit_error",
             ProxyError::ServiceUnavailable(_) => "api_error",
@@ -113,6 +186,15 @@ impl ProxyError {
         }
     }
 
+    /// The request parameter the error is about, reported as OpenAI's `error.param`
+    pub fn error_param(&self) -> Option<&str> {
+        match self {
+            ProxyError::MissingRequiredField { field } => Some(field),
+            ProxyError::InvalidParameter { parameter, .. } => Some(parameter),
+            _ => None,
+        }
+    }
+
     /// Maps the error to an appropriate OpenAI-compatible error code
     pub fn error_code(&self) -> Option<&'static str> {
         match self {
@@ -127,6 +209,7 @@ impl ProxyError {
             ProxyError::BadRequest(_) => Some("bad_request"),
             ProxyError::Unauthorized(_) => Some("unauthorized"),
             ProxyError::Forbidden(_) => Some("forbidden"),
+            ProxyError::ContentFiltered(_) => Some("content_filter"),
             ProxyError::NotFound(_) => Some("not_found"),
             ProxyError::RateLimited { .. } => Some("rate_limit_exceeded"),
             ProxyError::ServiceUnavailable(_) => Some("service_unavailable"),
@@ -143,8 +226,9 @@ impl ResponseError for ProxyError {
             ProxyError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ProxyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             ProxyError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ProxyError::ContentFiltered(_) => StatusCode::BAD_REQUEST,
             ProxyError::NotFound(_) => StatusCode::NOT_FOUND,
-            ProxyError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ProxyError::RateLimited { status, .. } => *status,
             ProxyError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             ProxyError::ServerConfiguration(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ProxyError::UpstreamError(status, _) => {
@@ -191,6 +275,21 @@ impl ResponseError for ProxyError {
     }
 
     fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ProxyError::RateLimited {
+            retry_after: Some(seconds),
+            ..
+        } = self
+        {
+            response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(self.error_body())
+    }
+}
+
+impl ProxyError {
+    /// Builds the OpenAI-style `{"error": {...}}` body returned for this error.
+    pub fn error_body(&self) -> Value {
         let error_message = match self {
             ProxyError::MissingRequiredField { field } => {
                 format!("Missing required field: {field}")
@@ -200,6 +299,9 @@ impl ResponseError for ProxyError {
             }
             ProxyError::ToolEmbedding(e) => format!("Tool error: {e}"),
             ProxyError::SerdeJson(e) => format!("Invalid JSON: {e}"),
+            ProxyError::ReqwestClient(e) if e.is_timeout() => {
+                format!("Upstream did not respond in time: {e}")
+            }
             ProxyError::ReqwestClient(e) => format!("Network error: {e}"),
             ProxyError::Straico(e) => format!("Upstream API error: {e}"),
             ProxyError::ResponseParse(_) => {
@@ -209,10 +311,12 @@ impl ResponseError for ProxyError {
             ProxyError::BadRequest(e) => format!("Bad request: {e}"),
             ProxyError::Unauthorized(msg) => format!("Unauthorized: {msg}"),
             ProxyError::Forbidden(msg) => format!("Forbidden: {msg}"),
+            ProxyError::ContentFiltered(msg) => format!("Content filtered: {msg}"),
             ProxyError::NotFound(msg) => format!("Not found: {msg}"),
             ProxyError::RateLimited {
                 retry_after,
                 message,
+                ..
             } => {
                 format!(
                     "Rate limited: {message}{}",
@@ -232,12 +336,77 @@ impl ResponseError for ProxyError {
             }
         };
 
-        HttpResponse::build(self.status_code()).json(serde_json::json!({
+        serde_json::json!({
             "error": {
                 "message": error_message,
                 "type": self.error_type(),
+                "param": self.error_param(),
                 "code": self.error_code()
             }
-        }))
+        })
+    }
+
+    /// Diagnostic context attached under `_debug` when `--verbose-errors` is enabled.
+    ///
+    /// Exposes internals (error sources, provider detection), so it is for development only.
+    pub fn debug_context(&self, request_id: &str, provider: &str) -> Value {
+        let mut source_chain = vec![self.to_string()];
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            source_chain.push(error.to_string());
+            source = error.source();
+        }
+        serde_json::json!({
+            "request_id": request_id,
+            "provider": provider,
+            "source_chain": source_chain
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limited(status: RateLimitStatus) -> ProxyError {
+        ProxyError::RateLimited {
+            retry_after: Some(30),
+            message: "Rate limited by Straico API".to_string(),
+            status: status.status_code(),
+        }
+    }
+
+    #[test]
+    fn test_rate_limited_status_is_configurable() {
+        let error = rate_limited(RateLimitStatus::default());
+        assert_eq!(error.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+        let error = rate_limited(RateLimitStatus::ServiceUnavailable);
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+        // Still reported as a rate limit in the body
+        assert_eq!(error.error_type(), "rate_limit_error");
+    }
+
+    #[test]
+    fn test_parse_status_mapping() {
+        assert_eq!(parse_status_mapping("403=401"), Ok((403, 401)));
+        assert!(parse_status_mapping("403").is_err());
+        assert!(parse_status_mapping("403=200").is_err());
+        assert!(parse_status_mapping("abc=401").is_err());
+    }
+
+    #[test]
+    fn test_error_param() {
+        let missing = ProxyError::MissingRequiredField {
+            field: "messages".to_string(),
+        };
+        assert_eq!(missing.error_body()["error"]["param"], "messages");
+        assert_eq!(missing.to_streaming_chunk()["error"]["param"], "messages");
+
+        let unrelated = ProxyError::BadRequest("no".to_string());
+        assert!(unrelated.error_body()["error"]["param"].is_null());
     }
 }
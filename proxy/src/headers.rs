@@ -0,0 +1,235 @@
+//! Per-request overrides supplied through `x-straico-*` request headers.
+
+use crate::error::ProxyError;
+use actix_web::http::header::{self, HeaderMap};
+use std::time::Duration;
+
+/// Header overriding the number of upstream retries for a single request.
+pub const MAX_RETRIES_HEADER: &str = "x-straico-max-retries";
+
+/// Upper bound for [`MAX_RETRIES_HEADER`], so a client cannot make the proxy hammer upstream.
+pub const MAX_RETRIES_LIMIT: u32 = 5;
+
+/// Header overriding the upstream timeout for a single request, in (possibly fractional) seconds.
+pub const TIMEOUT_HEADER: &str = "x-straico-timeout-secs";
+
+/// Upper bound for [`TIMEOUT_HEADER`], so a client cannot tie up a connection indefinitely.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Header with a system prompt for a single request, applied per `--system-override-mode`.
+pub const SYSTEM_OVERRIDE_HEADER: &str = "x-straico-system-override";
+
+/// Response header with the number of assistant tool-call turns in the request history.
+pub const TOOL_TURNS_HEADER: &str = "x-straico-tool-turns";
+
+/// Header carrying a client-supplied request id.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// What to do with a `stream: true` request whose `Accept` header rules out
+/// `text/event-stream` (`--accept-mismatch`).
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum AcceptMismatch {
+    /// Stream anyway, as requested in the body
+    #[default]
+    Stream,
+    /// Answer with a single non-streaming JSON response
+    Json,
+}
+
+/// Returns false if the `Accept` header lists media types but none that matches
+/// `text/event-stream`. A missing or empty header accepts anything.
+pub fn accepts_event_stream(headers: &HeaderMap) -> bool {
+    let mut media_types = headers
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .filter(|media_type| !media_type.is_empty())
+        .peekable();
+    media_types.peek().is_none()
+        || media_types
+            .any(|media_type| matches!(media_type.as_str(), "text/event-stream" | "text/*" | "*/*"))
+}
+
+/// Returns the client's request id, or a freshly generated one.
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("req-{}", uuid::Uuid::new_v4()))
+}
+
+/// Overrides parsed from the request headers. `None` means "use the server setting".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestOverrides {
+    pub max_retries: Option<u32>,
+    pub timeout: Option<Duration>,
+    pub system_override: Option<String>,
+}
+
+impl RequestOverrides {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ProxyError> {
+        let max_retries = header_str(headers, MAX_RETRIES_HEADER)?
+            .map(|value| {
+                value
+                    .parse::<u32>()
+                    .map(|retries| retries.min(MAX_RETRIES_LIMIT))
+                    .map_err(|_| ProxyError::InvalidParameter {
+                        parameter: MAX_RETRIES_HEADER.to_string(),
+                        reason: format!("expected a non-negative integer, got {value:?}"),
+                    })
+            })
+            .transpose()?;
+
+        let timeout = header_str(headers, TIMEOUT_HEADER)?
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| *secs > 0.0)
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .map(|timeout| timeout.min(MAX_TIMEOUT))
+                    .ok_or_else(|| ProxyError::InvalidParameter {
+                        parameter: TIMEOUT_HEADER.to_string(),
+                        reason: format!("expected a positive number of seconds, got {value:?}"),
+                    })
+            })
+            .transpose()?;
+
+        // Prompts are free text, so any UTF-8 is accepted rather than only visible ASCII
+        let system_override = headers
+            .get(SYSTEM_OVERRIDE_HEADER)
+            .map(|value| {
+                std::str::from_utf8(value.as_bytes())
+                    .map(|text| text.trim().to_string())
+                    .map_err(|_| ProxyError::InvalidParameter {
+                        parameter: SYSTEM_OVERRIDE_HEADER.to_string(),
+                        reason: "header value must be valid UTF-8".to_string(),
+                    })
+            })
+            .transpose()?
+            .filter(|text| !text.is_empty());
+
+        Ok(Self {
+            max_retries,
+            timeout,
+            system_override,
+        })
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>, ProxyError> {
+    headers
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .map(str::trim)
+                .map_err(|_| ProxyError::InvalidParameter {
+                    parameter: name.to_string(),
+                    reason: "header value must be visible ASCII".to_string(),
+                })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        map
+    }
+
+    #[test]
+    fn test_no_headers() {
+        let overrides = RequestOverrides::from_headers(&HeaderMap::new()).unwrap();
+        assert_eq!(overrides, RequestOverrides::default());
+    }
+
+    #[test]
+    fn test_max_retries_is_parsed_and_capped() {
+        let overrides =
+            RequestOverrides::from_headers(&headers(&[(MAX_RETRIES_HEADER, "0")])).unwrap();
+        assert_eq!(overrides.max_retries, Some(0));
+
+        let overrides =
+            RequestOverrides::from_headers(&headers(&[(MAX_RETRIES_HEADER, "100")])).unwrap();
+        assert_eq!(overrides.max_retries, Some(MAX_RETRIES_LIMIT));
+    }
+
+    #[test]
+    fn test_invalid_max_retries_is_rejected() {
+        let err =
+            RequestOverrides::from_headers(&headers(&[(MAX_RETRIES_HEADER, "-1")])).unwrap_err();
+        assert!(matches!(
+            err,
+            ProxyError::InvalidParameter { parameter, .. } if parameter == MAX_RETRIES_HEADER
+        ));
+    }
+
+    #[test]
+    fn test_timeout_is_parsed_and_capped() {
+        let overrides =
+            RequestOverrides::from_headers(&headers(&[(TIMEOUT_HEADER, "2.5")])).unwrap();
+        assert_eq!(overrides.timeout, Some(Duration::from_millis(2500)));
+
+        let overrides =
+            RequestOverrides::from_headers(&headers(&[(TIMEOUT_HEADER, "86400")])).unwrap();
+        assert_eq!(overrides.timeout, Some(MAX_TIMEOUT));
+
+        for invalid in ["0", "-1", "soon"] {
+            let mut map = HeaderMap::new();
+            map.insert(
+                HeaderName::from_static(TIMEOUT_HEADER),
+                HeaderValue::from_static(invalid),
+            );
+            assert!(RequestOverrides::from_headers(&map).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_system_override_is_parsed() {
+        let overrides =
+            RequestOverrides::from_headers(&headers(&[(SYSTEM_OVERRIDE_HEADER, " Be terse. ")]))
+                .unwrap();
+        assert_eq!(overrides.system_override.as_deref(), Some("Be terse."));
+
+        let overrides =
+            RequestOverrides::from_headers(&headers(&[(SYSTEM_OVERRIDE_HEADER, "  ")])).unwrap();
+        assert_eq!(overrides.system_override, None);
+    }
+
+    #[test]
+    fn test_accepts_event_stream() {
+        assert!(accepts_event_stream(&HeaderMap::new()));
+        assert!(accepts_event_stream(&headers(&[(
+            "accept",
+            "text/event-stream"
+        )])));
+        assert!(accepts_event_stream(&headers(&[(
+            "accept",
+            "application/json, */*;q=0.1"
+        )])));
+        assert!(!accepts_event_stream(&headers(&[(
+            "accept",
+            "application/json"
+        )])));
+    }
+}
@@ -29,12 +29,8 @@ pub fn create_self_signed_cert() -> io::Result<ServerConfig> {
     // Use rcgen to generate a self-signed certificate
     let subject_alt_names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
 
-    let cert = rcgen::generate_simple_self_signed(subject_alt_names).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("Failed to generate cert: {}", e),
-        )
-    })?;
+    let cert = rcgen::generate_simple_self_signed(subject_alt_names)
+        .map_err(|e| io::Error::other(format!("Failed to generate cert: {}", e)))?;
 
     let cert_pem = cert.cert.pem();
     let key_pem = cert.key_pair.serialize_pem();
@@ -0,0 +1,126 @@
+//! Rolling per-model latency statistics, reported by `GET /admin/stats`.
+//!
+//! Each completed request records the time from sending it upstream to having the full
+//! response converted. Only the most recent `window` samples per model are kept, so the
+//! numbers follow current upstream behaviour rather than the proxy's whole lifetime.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Samples kept per model when no `--latency-window` is given.
+pub const DEFAULT_LATENCY_WINDOW: usize = 100;
+
+/// Recent request latencies per upstream model, safe to share across workers.
+#[derive(Debug)]
+pub struct LatencyTracker {
+    window: usize,
+    samples: Mutex<HashMap<String, VecDeque<Duration>>>,
+}
+
+/// Latency summary of one model's recent requests.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LatencyStats {
+    /// Number of samples the figures are computed from
+    pub samples: usize,
+    pub avg_ms: f64,
+    pub p95_ms: f64,
+}
+
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_LATENCY_WINDOW)
+    }
+}
+
+impl LatencyTracker {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, model: &str, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let model_samples = samples.entry(model.to_string()).or_default();
+        if model_samples.len() == self.window {
+            model_samples.pop_front();
+        }
+        model_samples.push_back(latency);
+    }
+
+    /// Summarizes the recorded samples, keyed by model.
+    pub fn stats(&self) -> BTreeMap<String, LatencyStats> {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples
+            .iter()
+            .map(|(model, latencies)| {
+                let mut millis: Vec<f64> = latencies
+                    .iter()
+                    .map(|latency| latency.as_secs_f64() * 1000.0)
+                    .collect();
+                millis.sort_by(f64::total_cmp);
+                // Nearest-rank percentile
+                let p95_rank = (millis.len() as f64 * 0.95).ceil() as usize;
+                let stats = LatencyStats {
+                    samples: millis.len(),
+                    avg_ms: millis.iter().sum::<f64>() / millis.len() as f64,
+                    p95_ms: millis[p95_rank.saturating_sub(1)],
+                };
+                (model.clone(), stats)
+            })
+            .collect()
+    }
+}
+
+/// Measures one request; the latency is recorded when [`LatencyProbe::finish`] is called.
+#[derive(Clone, Debug)]
+pub struct LatencyProbe {
+    tracker: Arc<LatencyTracker>,
+    model: String,
+    started: Instant,
+}
+
+impl LatencyProbe {
+    pub fn start(tracker: Arc<LatencyTracker>, model: impl Into<String>) -> Self {
+        Self {
+            tracker,
+            model: model.into(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn finish(&self) {
+        self.tracker.record(&self.model, self.started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_average_and_p95() {
+        let tracker = LatencyTracker::default();
+        for ms in 1..=100 {
+            tracker.record("openai/gpt-4", Duration::from_millis(ms));
+        }
+        let stats = &tracker.stats()["openai/gpt-4"];
+        assert_eq!(stats.samples, 100);
+        assert!((stats.avg_ms - 50.5).abs() < 1e-6);
+        assert!((stats.p95_ms - 95.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_window_keeps_recent_samples() {
+        let tracker = LatencyTracker::new(2);
+        for ms in [1000, 10, 20] {
+            tracker.record("openai/gpt-4", Duration::from_millis(ms));
+        }
+        let stats = &tracker.stats()["openai/gpt-4"];
+        assert_eq!(stats.samples, 2);
+        assert!((stats.avg_ms - 15.0).abs() < 1e-6);
+    }
+}
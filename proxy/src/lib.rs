@@ -1,12 +1,37 @@
+pub mod arg_keys;
+pub mod cache;
+pub mod choice_selection;
 pub mod cli;
 pub mod debug_middleware;
 pub mod error;
+pub mod headers;
 pub mod https_rejector;
+pub mod latency;
+pub mod model_map;
+pub mod model_transforms;
+pub mod models;
+pub mod moderation;
+pub mod persistence;
+pub mod preprocess;
 pub mod provider;
+pub mod queue;
+pub mod reasoning;
+pub mod sampling;
 pub mod server;
+pub mod special_tokens;
 pub mod streaming;
+pub mod system_prompt;
+#[cfg(test)]
+mod test_support;
+pub mod text_completion;
 pub mod tls_detector;
+pub mod token_limits;
+pub mod tool_format;
+pub mod tool_policy;
+pub mod transport;
 pub mod types;
+pub mod upstream_tls;
+pub mod warmup;
 
 pub use error::ProxyError;
 pub use server::AppState;
@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use actix_web::{middleware, web, App, HttpResponse, HttpServer};
@@ -6,7 +7,23 @@ use clap::Parser;
 use flexi_logger::{Logger, WriteMode};
 use log::{error, info};
 use straico_client::client::StraicoClient;
-use straico_proxy::{cli::Cli, server};
+use straico_proxy::{
+    cache::ResponseCache,
+    cli::Cli,
+    error::StatusRemap,
+    latency::LatencyTracker,
+    model_map::ModelMap,
+    model_transforms::ModelTransforms,
+    models::ModelCatalog,
+    moderation::{ModerationBackend, OpenAiModeration},
+    persistence::ConversationStore,
+    queue::RequestQueue,
+    reasoning, server,
+    special_tokens::DEFAULT_SPECIAL_TOKENS,
+    tool_format::ToolFormatTracker,
+    tool_policy::ToolPolicy,
+    upstream_tls, warmup,
+};
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
@@ -62,6 +79,10 @@ async fn main() -> anyhow::Result<()> {
 
     logger.start()?;
 
+    let workers = cli.workers;
+    let keep_alive = cli.keep_alive();
+    let client_request_timeout = cli.client_request_timeout();
+
     // Ensure API key is present
     let api_key = match cli.api_key {
         Some(key) => key,
@@ -72,7 +93,12 @@ async fn main() -> anyhow::Result<()> {
     };
 
     if cli.list_models {
-        let client = StraicoClient::new();
+        let client = upstream_tls::configure(
+            StraicoClient::builder().base_url(cli.upstream_url.as_str()),
+            cli.upstream_ca_cert.as_deref(),
+            cli.upstream_insecure,
+        )?
+        .build()?;
         let response = client
             .models()
             .bearer_auth(&api_key)
@@ -97,6 +123,102 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    // Validate the model map up front so a broken file fails startup, not requests
+    let model_map = match &cli.model_map {
+        Some(path) => {
+            let map = ModelMap::load(path)?;
+            info!("Loaded {} model aliases from {}", map.len(), path.display());
+            Arc::new(map)
+        }
+        None => Arc::new(ModelMap::default()),
+    };
+    let model_transforms = match &cli.model_transforms {
+        Some(path) => {
+            let transforms = ModelTransforms::load(path)?;
+            info!(
+                "Loaded {} model transform rules from {}",
+                transforms.len(),
+                path.display()
+            );
+            Arc::new(transforms)
+        }
+        None => Arc::new(ModelTransforms::default()),
+    };
+
+    let persist_db = match &cli.persist_db {
+        Some(path) => {
+            let store = ConversationStore::open(path)?;
+            info!("Persisting conversations to {}", path.display());
+            Some(Arc::new(store))
+        }
+        None => None,
+    };
+
+    let moderation = cli.moderation_url.as_ref().map(|url| {
+        info!("Moderating user content with {}", url);
+        Arc::new(OpenAiModeration::new(
+            url.as_str(),
+            cli.moderation_model.clone(),
+            cli.moderation_key.clone(),
+        )) as Arc<dyn ModerationBackend>
+    });
+
+    let thinking_tags = cli.extract_reasoning.as_ref().map(|tags| {
+        let tags = reasoning::thinking_tags(tags);
+        info!("Extracting reasoning delimited by: {:?}", tags);
+        Arc::new(tags)
+    });
+
+    let special_tokens = cli.strip_special_tokens.as_ref().map(|tokens| {
+        let tokens = if tokens.is_empty() {
+            DEFAULT_SPECIAL_TOKENS
+                .iter()
+                .map(|t| t.to_string())
+                .collect()
+        } else {
+            tokens.clone()
+        };
+        info!(
+            "Stripping special tokens from responses: {}",
+            tokens.join(" ")
+        );
+        Arc::new(tokens)
+    });
+
+    let request_queue = cli.max_concurrent_requests.map(|max| {
+        info!(
+            "Limiting to {} concurrent requests, queueing up to {} for {} ms",
+            max, cli.queue_depth, cli.queue_timeout_ms
+        );
+        Arc::new(RequestQueue::new(
+            max as usize,
+            cli.queue_depth,
+            Duration::from_millis(cli.queue_timeout_ms),
+        ))
+    });
+
+    let latency = Arc::new(LatencyTracker::new(cli.latency_window));
+
+    let response_cache = cli.cache_size.map(|size| {
+        Arc::new(ResponseCache::new(
+            size as usize,
+            Duration::from_secs(cli.cache_ttl_seconds),
+        ))
+    });
+
+    let tool_formats = Arc::new(ToolFormatTracker::new(cli.adapt_tool_format));
+
+    let model_catalog = Arc::new(ModelCatalog::default());
+
+    let zero_temperature_epsilon = Arc::new(cli.zero_temperature_epsilon.clone());
+
+    let status_remap = Arc::new(StatusRemap::new(cli.remap_status.iter().copied()));
+
+    let tool_policy = Arc::new(ToolPolicy::new(
+        cli.allow_tools.clone(),
+        cli.deny_tools.clone(),
+    ));
+
     let http_addr = format!("{}:{}", cli.host, cli.port);
     let https_port = cli.https_port.unwrap_or(cli.port + 1);
     let https_addr = format!("{}:{}", cli.host, https_port);
@@ -104,7 +226,10 @@ async fn main() -> anyhow::Result<()> {
     info!("Starting Straico proxy server...");
     info!("HTTP server running at http://{}", http_addr);
     info!("HTTPS rejection server running at https://{}", https_addr);
-    info!("Completions endpoint: /v1/chat/completions");
+    info!(
+        "Completions endpoint: {}/v1/chat/completions",
+        cli.route_prefix
+    );
     info!("\n┌─────────────────────────────────────────────────────────────────┐");
     info!("│ ✅ HTTPS connections now handled gracefully                      │");
     info!("│                                                                 │");
@@ -121,12 +246,35 @@ async fn main() -> anyhow::Result<()> {
     );
     info!("└─────────────────────────────────────────────────────────────────┘");
 
-    let client = StraicoClient::builder()
+    let mut client_builder = StraicoClient::builder()
         .pool_max_idle_per_host(25)
         .pool_idle_timeout(Duration::from_secs(90))
         .tcp_keepalive(Duration::from_secs(90))
-        .timeout(Duration::from_secs(90))
-        .build()?;
+        .read_timeout(Duration::from_secs(cli.request_timeout))
+        .base_url(cli.upstream_url.as_str())
+        .chat_path(cli.chat_path.as_str());
+    if let Some(proxy_url) = &cli.upstream_proxy {
+        let proxy = reqwest::Proxy::all(proxy_url.as_str())
+            .with_context(|| format!("Invalid upstream proxy URL: {proxy_url}"))?
+            .no_proxy(reqwest::NoProxy::from_env());
+        info!("Routing upstream requests through proxy {}", proxy_url);
+        client_builder = client_builder.proxy(proxy);
+    }
+    let client_builder = upstream_tls::configure(
+        client_builder,
+        cli.upstream_ca_cert.as_deref(),
+        cli.upstream_insecure,
+    )
+    .context("Invalid upstream TLS configuration")?;
+    let client = client_builder.build()?;
+
+    if let Some(secs) = cli.warmup_interval_secs {
+        info!(
+            "Pinging the upstream every {} seconds to keep connections warm",
+            secs
+        );
+        warmup::spawn(client.clone(), api_key.clone(), Duration::from_secs(secs));
+    }
 
     // Create TLS config for HTTPS rejection
     let tls_config = straico_proxy::https_rejector::create_self_signed_cert()?;
@@ -135,18 +283,81 @@ async fn main() -> anyhow::Result<()> {
         let app_state = server::AppState {
             client: client.clone(),
             key: api_key.clone(),
+            request_timeout: Some(Duration::from_secs(cli.request_timeout)),
             heartbeat_char: cli.heartbeat_char,
+            heartbeat_interval: Some(Duration::from_secs(cli.heartbeat_interval)),
+            max_heartbeats: cli.max_heartbeats,
+            max_retries: cli.max_retries,
+            retry_base_delay: Duration::from_millis(cli.retry_base_delay_ms),
+            strip_model_prefix: cli.strip_model_prefix.clone(),
+            include_debug_info: cli.include_debug_info,
+            debug_tool_arguments: cli.debug_tool_arguments,
+            lenient_parsing: cli.lenient_parsing,
+            disable_tools: cli.disable_tools,
+            inject_current_date: cli.inject_current_date,
+            system_prompt: cli.system_prompt.clone(),
+            system_prompt_mode: cli.system_prompt_mode,
+            system_override_mode: cli.system_override_mode,
+            strip_leaked_tools_prompt: cli.strip_leaked_tools_prompt,
+            strip_special_tokens: special_tokens.clone(),
+            thinking_tags: thinking_tags.clone(),
+            trim_response: cli.trim_response,
+            select_best: cli.select_best,
+            coerce_arg_keys: cli.coerce_arg_keys,
+            model_map: model_map.clone(),
+            model_transforms: model_transforms.clone(),
+            sse_retry_ms: cli.sse_retry_ms,
+            sse_dialect: cli.sse_dialect,
+            sse_chunk_index: cli.sse_chunk_index,
+            errors_as_sse: cli.errors_as_sse,
+            accept_mismatch: cli.accept_mismatch,
+            tool_policy: tool_policy.clone(),
+            max_message_length: cli.max_message_length,
+            max_tools_prompt_length: cli.max_tools_prompt_length,
+            tools_prompt_over_limit: cli.tools_prompt_over_limit,
+            require_user_message: cli.require_user_message,
+            max_tool_turns: cli.max_tool_turns,
+            max_tokens_policy: cli.max_tokens_over_limit,
+            zero_temperature_epsilon: zero_temperature_epsilon.clone(),
+            default_temperature: cli.default_temperature,
+            rate_limit_status: cli.rate_limit_status,
+            status_remap: status_remap.clone(),
+            verbose_errors: cli.verbose_errors,
+            debug_sample_rate: cli.debug_sample_rate,
+            persist_db: persist_db.clone(),
+            moderation: moderation.clone(),
+            admin_token: cli.admin_token.clone(),
+            latency: latency.clone(),
+            tool_formats: tool_formats.clone(),
+            model_catalog: model_catalog.clone(),
+            readiness_check_upstream: cli.readiness_check_upstream,
+            response_cache: response_cache.clone(),
+            request_queue: request_queue.clone(),
+            transport: None,
         };
 
         App::new()
             .wrap(middleware::Logger::default())
             .app_data(web::Data::new(app_state))
-            .service(server::openai_chat_completion)
-            .service(server::model_handler)
-            .service(server::models_handler)
+            .service(server::health_check)
+            .service(server::readiness_check)
+            .service(web::scope(&cli.route_prefix).configure(server::configure_routes))
             .default_service(web::to(HttpResponse::NotFound))
     });
 
+    let http_server = match workers {
+        Some(workers) => http_server.workers(workers),
+        None => http_server,
+    };
+    let http_server = match keep_alive {
+        Some(keep_alive) => http_server.keep_alive(keep_alive),
+        None => http_server,
+    };
+    let http_server = match client_request_timeout {
+        Some(timeout) => http_server.client_request_timeout(timeout),
+        None => http_server,
+    };
+
     // Bind HTTP server
     let http_server = http_server
         .bind(&http_addr)
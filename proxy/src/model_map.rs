@@ -0,0 +1,152 @@
+//! Alias → upstream model mappings loaded from a JSON file (`--model-map`).
+//!
+//! The file is a flat object, e.g. `{"gpt-4": "openai/gpt-4o", "claude": "anthropic/claude-3"}`.
+//! It is validated at startup and can be re-read at runtime via `POST /admin/reload`.
+
+use crate::error::ProxyError;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Shared, reloadable set of model aliases.
+#[derive(Debug, Default)]
+pub struct ModelMap {
+    /// File the aliases were loaded from; `None` for an empty or in-memory map
+    path: Option<PathBuf>,
+    aliases: RwLock<HashMap<String, String>>,
+}
+
+impl ModelMap {
+    /// Loads and validates the alias file at `path`.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, ProxyError> {
+        let path = path.into();
+        let aliases = read_aliases(&path)?;
+        Ok(Self {
+            path: Some(path),
+            aliases: RwLock::new(aliases),
+        })
+    }
+
+    /// Returns the upstream model the alias maps to, if any.
+    pub fn resolve(&self, model: &str) -> Option<String> {
+        self.aliases
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(model)
+            .cloned()
+    }
+
+    /// Re-reads the file the map was loaded from and returns the number of aliases.
+    ///
+    /// On error the previously loaded aliases are kept.
+    pub fn reload(&self) -> Result<usize, ProxyError> {
+        let Some(path) = &self.path else {
+            return Err(ProxyError::BadRequest(
+                "No model map file configured (use --model-map)".to_string(),
+            ));
+        };
+        let aliases = read_aliases(path)?;
+        let count = aliases.len();
+        *self.aliases.write().unwrap_or_else(|e| e.into_inner()) = aliases;
+        Ok(count)
+    }
+
+    /// Number of aliases currently loaded.
+    pub fn len(&self) -> usize {
+        self.aliases.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn read_aliases(path: &Path) -> Result<HashMap<String, String>, ProxyError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        ProxyError::ServerConfiguration(format!("Failed to read model map {}: {e}", path.display()))
+    })?;
+    let aliases: HashMap<String, String> = serde_json::from_str(&contents).map_err(|e| {
+        ProxyError::ServerConfiguration(format!(
+            "Invalid model map {} (expected a JSON object of alias to model ID): {e}",
+            path.display()
+        ))
+    })?;
+
+    if let Some((alias, _)) = aliases
+        .iter()
+        .find(|(alias, target)| alias.trim().is_empty() || target.trim().is_empty())
+    {
+        return Err(ProxyError::ServerConfiguration(format!(
+            "Invalid model map {}: empty alias or target for entry {alias:?}",
+            path.display()
+        )));
+    }
+
+    Ok(aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_map(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("straico-proxy-{name}-{}.json", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_and_resolve() {
+        let path = write_map(
+            "model-map-load",
+            r#"{"gpt-4": "openai/gpt-4o", "claude": "anthropic/claude-3"}"#,
+        );
+        let map = ModelMap::load(&path).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.resolve("gpt-4").as_deref(), Some("openai/gpt-4o"));
+        assert_eq!(map.resolve("claude").as_deref(), Some("anthropic/claude-3"));
+        assert_eq!(map.resolve("unknown"), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_file_is_rejected() {
+        let path = write_map("model-map-invalid", r#"["not", "an", "object"]"#);
+        assert!(matches!(
+            ModelMap::load(&path),
+            Err(ProxyError::ServerConfiguration(_))
+        ));
+
+        std::fs::write(&path, r#"{"gpt-4": ""}"#).unwrap();
+        assert!(ModelMap::load(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(ModelMap::load("/nonexistent/model-map.json").is_err());
+    }
+
+    #[test]
+    fn test_reload_picks_up_changes() {
+        let path = write_map("model-map-reload", r#"{"gpt-4": "openai/gpt-4o"}"#);
+        let map = ModelMap::load(&path).unwrap();
+
+        std::fs::write(
+            &path,
+            r#"{"gpt-4": "openai/gpt-4.1", "fast": "openai/gpt-4o-mini"}"#,
+        )
+        .unwrap();
+        assert_eq!(map.reload().unwrap(), 2);
+        assert_eq!(map.resolve("gpt-4").as_deref(), Some("openai/gpt-4.1"));
+
+        // A broken file keeps the previous aliases
+        std::fs::write(&path, "{").unwrap();
+        assert!(map.reload().is_err());
+        assert_eq!(map.resolve("fast").as_deref(), Some("openai/gpt-4o-mini"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_without_file() {
+        assert!(ModelMap::default().reload().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Per-model request transformations loaded from a JSON file (`--model-transforms`).
+//!
+//! The file is a list of rules, each naming an upstream model ID (or a prefix ending in
+//! `*`) and the transformations to apply to its requests, e.g.
+//!
+//! ```json
+//! [
+//!   {"model": "anthropic/*", "system_prompt": "Answer concisely.", "max_temperature": 1.0},
+//!   {"model": "moonshotai/kimi-k2", "tool_format": "qwen"}
+//! ]
+//! ```
+//!
+//! Every matching rule applies; where two set the same field, the later one wins.
+
+use crate::error::ProxyError;
+use crate::system_prompt::SystemPromptMode;
+use serde::Deserialize;
+use std::path::Path;
+use straico_client::endpoints::chat::ModelProvider;
+
+/// The transformations for one model, merged from all matching rules.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelTransform {
+    /// System prompt added to the model's requests
+    pub system_prompt: Option<String>,
+    /// How `system_prompt` combines with the request's own system messages
+    pub system_prompt_mode: SystemPromptMode,
+    /// Upper bound on the temperature sent upstream
+    pub max_temperature: Option<f32>,
+    /// Provider whose tool call format is used instead of the model's own
+    pub tool_format: Option<ModelProvider>,
+}
+
+/// One entry of the transformations file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    model: String,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    system_prompt_mode: Option<SystemPromptMode>,
+    #[serde(default)]
+    max_temperature: Option<f32>,
+    #[serde(default)]
+    tool_format: Option<ModelProvider>,
+}
+
+impl Rule {
+    fn matches(&self, model: &str) -> bool {
+        match self.model.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => self.model == model,
+        }
+    }
+}
+
+/// Transformation rules, in file order.
+#[derive(Debug, Default)]
+pub struct ModelTransforms {
+    rules: Vec<Rule>,
+}
+
+impl ModelTransforms {
+    /// Loads and validates the rules file at `path`.
+    pub fn load(path: &Path) -> Result<Self, ProxyError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            ProxyError::ServerConfiguration(format!(
+                "Failed to read model transforms {}: {e}",
+                path.display()
+            ))
+        })?;
+        Self::parse(&contents).map_err(|reason| {
+            ProxyError::ServerConfiguration(format!(
+                "Invalid model transforms {}: {reason}",
+                path.display()
+            ))
+        })
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let rules: Vec<Rule> = serde_json::from_str(contents)
+            .map_err(|e| format!("expected a JSON array of rules: {e}"))?;
+        for (index, rule) in rules.iter().enumerate() {
+            if rule.model.trim().is_empty() {
+                return Err(format!("rule {index} has an empty model"));
+            }
+            if rule
+                .max_temperature
+                .is_some_and(|max| !(0.0..=2.0).contains(&max))
+            {
+                return Err(format!(
+                    "rule {index} has a max_temperature outside 0.0 to 2.0"
+                ));
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    /// Number of rules loaded.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the transformations for the upstream `model`.
+    pub fn for_model(&self, model: &str) -> ModelTransform {
+        let mut transform = ModelTransform::default();
+        for rule in self.rules.iter().filter(|rule| rule.matches(model)) {
+            if let Some(prompt) = &rule.system_prompt {
+                transform.system_prompt = Some(prompt.clone());
+            }
+            if let Some(mode) = rule.system_prompt_mode {
+                transform.system_prompt_mode = mode;
+            }
+            transform.max_temperature = rule.max_temperature.or(transform.max_temperature);
+            transform.tool_format = rule.tool_format.or(transform.tool_format);
+        }
+        transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transforms() -> ModelTransforms {
+        ModelTransforms::parse(
+            r#"[
+                {"model": "anthropic/*", "system_prompt": "Answer concisely.", "max_temperature": 1.0},
+                {"model": "anthropic/claude-3-opus", "max_temperature": 0.5, "system_prompt_mode": "replace"},
+                {"model": "moonshotai/kimi-k2", "tool_format": "qwen"}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_matching_rules_are_merged_in_order() {
+        let transforms = transforms();
+        assert_eq!(
+            transforms.for_model("anthropic/claude-3-opus"),
+            ModelTransform {
+                system_prompt: Some("Answer concisely.".to_string()),
+                system_prompt_mode: SystemPromptMode::Replace,
+                max_temperature: Some(0.5),
+                tool_format: None,
+            }
+        );
+        assert_eq!(
+            transforms
+                .for_model("anthropic/claude-3-haiku")
+                .max_temperature,
+            Some(1.0)
+        );
+        assert_eq!(
+            transforms.for_model("moonshotai/kimi-k2").tool_format,
+            Some(ModelProvider::Qwen)
+        );
+    }
+
+    #[test]
+    fn test_other_models_are_untouched() {
+        let transforms = transforms();
+        assert_eq!(
+            transforms.for_model("openai/gpt-4o"),
+            ModelTransform::default()
+        );
+        // Exact rules do not match longer IDs
+        assert_eq!(
+            transforms
+                .for_model("moonshotai/kimi-k2-instruct")
+                .tool_format,
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        assert!(ModelTransforms::parse(r#"{"model": "openai/gpt-4o"}"#).is_err());
+        assert!(ModelTransforms::parse(r#"[{"model": ""}]"#).is_err());
+        assert!(ModelTransforms::parse(r#"[{"model": "a", "max_temperature": 3.0}]"#).is_err());
+        assert!(ModelTransforms::parse(r#"[{"model": "a", "temprature": 1.0}]"#).is_err());
+        assert!(ModelTransforms::parse(r#"[{"model": "a", "tool_format": "xml"}]"#).is_err());
+        assert!(ModelTransforms::load(Path::new("/nonexistent/transforms.json")).is_err());
+    }
+}
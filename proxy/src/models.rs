@@ -0,0 +1,153 @@
+//! The OpenAI-style model list served by `GET /v1/models`.
+//!
+//! Clients call the endpoint on startup to fill their model pickers, so it must not fail
+//! just because Straico is briefly unreachable. The last list fetched successfully is
+//! kept and served when the upstream call fails; before the first success, a short static
+//! list of well-known models is served instead.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use straico_client::endpoints::models::ModelsResponse;
+
+/// Models listed when the upstream catalog has never been fetched successfully.
+const FALLBACK_MODELS: &[&str] = &[
+    "openai/gpt-4o",
+    "openai/gpt-4o-mini",
+    "openai/gpt-4.1",
+    "anthropic/claude-sonnet-4",
+    "anthropic/claude-3.5-haiku",
+    "google/gemini-2.5-pro",
+    "google/gemini-2.5-flash",
+];
+
+/// One entry of OpenAI's `GET /v1/models` list.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OpenAiModel {
+    /// Upstream model ID, verbatim, so it can be sent back as a chat request's `model`
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub owned_by: String,
+}
+
+/// OpenAI's list envelope around [`OpenAiModel`]s.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OpenAiModelList {
+    pub object: &'static str,
+    pub data: Vec<OpenAiModel>,
+}
+
+impl OpenAiModel {
+    fn new(id: String, created: Option<i64>, owned_by: Option<String>) -> Self {
+        // Model IDs are typically "provider/model-name"
+        let owned_by = owned_by.unwrap_or_else(|| match id.split_once('/') {
+            Some((provider, _)) => provider.to_string(),
+            None => "straico".to_string(),
+        });
+        Self {
+            id,
+            object: "model",
+            created: created.unwrap_or(0),
+            owned_by,
+        }
+    }
+}
+
+impl From<ModelsResponse> for OpenAiModelList {
+    fn from(response: ModelsResponse) -> Self {
+        Self::new(
+            response
+                .data
+                .into_iter()
+                .map(|model| OpenAiModel::new(model.id, model.created, model.owned_by))
+                .collect(),
+        )
+    }
+}
+
+impl OpenAiModelList {
+    pub fn new(data: Vec<OpenAiModel>) -> Self {
+        Self {
+            object: "list",
+            data,
+        }
+    }
+}
+
+/// The last model list fetched from the upstream, safe to share across workers.
+#[derive(Debug, Default)]
+pub struct ModelCatalog {
+    last_fetched: Mutex<Option<OpenAiModelList>>,
+}
+
+impl ModelCatalog {
+    /// Remembers a freshly fetched list.
+    pub fn update(&self, list: OpenAiModelList) {
+        *self.last_fetched.lock().unwrap_or_else(|e| e.into_inner()) = Some(list);
+    }
+
+    /// The last fetched list, or the static fallback list if none was fetched yet.
+    pub fn fallback(&self) -> OpenAiModelList {
+        let last_fetched = self.last_fetched.lock().unwrap_or_else(|e| e.into_inner());
+        last_fetched.clone().unwrap_or_else(|| {
+            OpenAiModelList::new(
+                FALLBACK_MODELS
+                    .iter()
+                    .map(|id| OpenAiModel::new(id.to_string(), None, None))
+                    .collect(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_straico_models_to_openai_list() {
+        let response: ModelsResponse = serde_json::from_value(serde_json::json!({
+            "data": [
+                {"name": "Nova Lite", "id": "amazon/nova-lite-v1", "pricing": {}},
+                {"name": "Custom", "model": "custom-model", "pricing": {}, "created": 1700000000,
+                 "owned_by": "acme"}
+            ],
+            "success": true
+        }))
+        .unwrap();
+        let list = OpenAiModelList::from(response);
+
+        assert_eq!(list.object, "list");
+        assert_eq!(
+            list.data,
+            [
+                OpenAiModel {
+                    id: "amazon/nova-lite-v1".to_string(),
+                    object: "model",
+                    created: 0,
+                    owned_by: "amazon".to_string(),
+                },
+                OpenAiModel {
+                    id: "custom-model".to_string(),
+                    object: "model",
+                    created: 1700000000,
+                    owned_by: "acme".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fallback_prefers_last_fetched_list() {
+        let catalog = ModelCatalog::default();
+        assert_eq!(catalog.fallback().data.len(), FALLBACK_MODELS.len());
+
+        let fetched = OpenAiModelList::new(vec![OpenAiModel::new(
+            "amazon/nova-lite-v1".to_string(),
+            None,
+            None,
+        )]);
+        catalog.update(fetched.clone());
+        assert_eq!(catalog.fallback(), fetched);
+    }
+}
@@ -0,0 +1,130 @@
+//! Optional moderation of user content before a request is forwarded upstream
+//! (`--moderation-url`).
+//!
+//! Backends implement [`ModerationBackend`]; the built-in one speaks the OpenAI
+//! `/v1/moderations` format. Flagged requests fail with [`ProxyError::ContentFiltered`]
+//! and never reach the main model. If the moderation call itself fails, so does the request.
+
+use crate::error::ProxyError;
+use crate::types::OpenAiChatRequest;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use straico_client::endpoints::chat::OpenAiChatMessage;
+
+/// Outcome of moderating a request's user content.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModerationVerdict {
+    pub flagged: bool,
+    /// Names of the categories the content was flagged for
+    pub categories: Vec<String>,
+}
+
+/// A pluggable moderation service.
+#[async_trait]
+pub trait ModerationBackend: Send + Sync {
+    /// Classifies `input`, one entry per user message.
+    async fn moderate(&self, input: &[String]) -> Result<ModerationVerdict, ProxyError>;
+}
+
+/// Rejects `request` if `backend` flags any of its user messages.
+pub async fn check_request(
+    backend: &dyn ModerationBackend,
+    request: &OpenAiChatRequest,
+) -> Result<(), ProxyError> {
+    let input: Vec<String> = request
+        .chat_request
+        .messages
+        .iter()
+        .filter_map(|message| match message {
+            OpenAiChatMessage::User { content, .. } => Some(content.to_string()),
+            _ => None,
+        })
+        .collect();
+    if input.is_empty() {
+        return Ok(());
+    }
+
+    let verdict = backend.moderate(&input).await?;
+    if !verdict.flagged {
+        return Ok(());
+    }
+    let reason = if verdict.categories.is_empty() {
+        "request was flagged by the moderation service".to_string()
+    } else {
+        format!(
+            "request was flagged by the moderation service ({})",
+            verdict.categories.join(", ")
+        )
+    };
+    Err(ProxyError::ContentFiltered(reason))
+}
+
+/// Moderation backend for an OpenAI-compatible `/v1/moderations` endpoint.
+pub struct OpenAiModeration {
+    client: reqwest::Client,
+    url: String,
+    model: Option<String>,
+    key: Option<String>,
+}
+
+impl OpenAiModeration {
+    pub fn new(url: impl Into<String>, model: Option<String>, key: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            model,
+            key,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: HashMap<String, bool>,
+}
+
+#[async_trait]
+impl ModerationBackend for OpenAiModeration {
+    async fn moderate(&self, input: &[String]) -> Result<ModerationVerdict, ProxyError> {
+        let mut body = serde_json::json!({ "input": input });
+        if let Some(model) = &self.model {
+            body["model"] = model.clone().into();
+        }
+        let mut request = self.client.post(&self.url).json(&body);
+        if let Some(key) = &self.key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ProxyError::UpstreamError(
+                status.as_u16(),
+                format!("Moderation endpoint returned {status}"),
+            ));
+        }
+        let response: ModerationResponse = response.json().await?;
+
+        let mut verdict = ModerationVerdict::default();
+        for result in response.results {
+            verdict.flagged |= result.flagged;
+            verdict.categories.extend(
+                result
+                    .categories
+                    .into_iter()
+                    .filter_map(|(category, flagged)| flagged.then_some(category)),
+            );
+        }
+        verdict.categories.sort();
+        verdict.categories.dedup();
+        Ok(verdict)
+    }
+}
@@ -0,0 +1,235 @@
+//! Optional local conversation history stored in SQLite (`--persist-db`).
+//!
+//! Every completed request is written to the `conversations` table together with the
+//! response the client received. Streamed responses are recorded once fully assembled.
+//! Request headers (and with them the API key) are never stored, and key-like fields
+//! are dropped from the request body at any depth (e.g. inside `extra_body`).
+//!
+//! Inserts run on Tokio's blocking thread pool, so a slow disk never stalls a worker.
+
+use crate::error::ProxyError;
+use log::warn;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// Request body fields that may carry credentials and are never stored, wherever they appear.
+const SECRET_FIELDS: &[&str] = &["api_key", "apiKey", "key", "authorization"];
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS conversations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    request_id TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    stream INTEGER NOT NULL,
+    request TEXT NOT NULL,
+    response TEXT NOT NULL
+)";
+
+/// SQLite database holding the conversation history.
+#[derive(Debug)]
+pub struct ConversationStore {
+    conn: Mutex<Connection>,
+}
+
+impl ConversationStore {
+    /// Opens (or creates) the database at `path` and ensures the schema exists.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ProxyError> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .and_then(|conn| conn.execute_batch(SCHEMA).map(|_| conn))
+            .map_err(|e| {
+                ProxyError::ServerConfiguration(format!(
+                    "Failed to open conversation database {}: {e}",
+                    path.display()
+                ))
+            })?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn insert(
+        &self,
+        conversation: &PendingConversation,
+        response: &serde_json::Value,
+    ) -> Result<(), ProxyError> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        self.conn
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .execute(
+                "INSERT INTO conversations (request_id, created_at, model, stream, request, response)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    conversation.request_id,
+                    created_at,
+                    conversation.model,
+                    conversation.stream,
+                    conversation.request.to_string(),
+                    response.to_string(),
+                ],
+            )
+            .map_err(|e| {
+                ProxyError::ServerConfiguration(format!("Failed to persist conversation: {e}"))
+            })?;
+        Ok(())
+    }
+}
+
+/// A request waiting for its response before being written to the store.
+#[derive(Clone, Debug)]
+pub struct PendingConversation {
+    store: Arc<ConversationStore>,
+    request_id: String,
+    model: String,
+    stream: bool,
+    request: serde_json::Value,
+}
+
+impl PendingConversation {
+    /// Captures the client's raw request body, minus any key-like fields.
+    pub fn new(
+        store: Arc<ConversationStore>,
+        request_id: &str,
+        mut request: serde_json::Value,
+    ) -> Self {
+        remove_secrets(&mut request);
+        let field = |name: &str| request.get(name).cloned().unwrap_or_default();
+        Self {
+            model: field("model").as_str().unwrap_or_default().to_string(),
+            stream: field("stream").as_bool().unwrap_or_default(),
+            request_id: request_id.to_string(),
+            store,
+            request,
+        }
+    }
+
+    /// Stores the request with the `response` the client received, on the blocking thread
+    /// pool. Failures are logged, never surfaced to the client, since the response itself
+    /// succeeded. The returned handle resolves once the row is written.
+    pub fn complete(self, response: serde_json::Value) -> JoinHandle<()> {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = self.store.insert(&self, &response) {
+                warn!("[{}] {}", self.request_id, e);
+            }
+        })
+    }
+}
+
+/// Drops every [`SECRET_FIELDS`] key from the objects nested anywhere in `value`.
+fn remove_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for field in SECRET_FIELDS {
+                fields.remove(*field);
+            }
+            fields.values_mut().for_each(remove_secrets);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(remove_secrets),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_fields_are_not_stored() {
+        let path = std::env::temp_dir().join(format!(
+            "straico-proxy-persist-secrets-{}.db",
+            std::process::id()
+        ));
+        let store = Arc::new(ConversationStore::open(&path).unwrap());
+        let pending = PendingConversation::new(
+            store,
+            "req-1",
+            serde_json::json!({
+                "model": "openai/gpt-4",
+                "api_key": "sk-secret",
+                "messages": [],
+                "extra_body": {"provider": {"apiKey": "sk-nested"}, "metadata": [{"key": "k"}]}
+            }),
+        );
+
+        assert_eq!(pending.model, "openai/gpt-4");
+        assert!(!pending.stream);
+        assert!(pending.request.get("api_key").is_none());
+        assert!(pending.request.get("messages").is_some());
+        assert_eq!(
+            pending.request["extra_body"],
+            serde_json::json!({"provider": {}, "metadata": [{}]})
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_completed_conversation_is_stored() {
+        let path = std::env::temp_dir().join(format!(
+            "straico-proxy-persist-row-{}.db",
+            std::process::id()
+        ));
+        let store = Arc::new(ConversationStore::open(&path).unwrap());
+        let request = serde_json::json!({
+            "model": "openai/gpt-4",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+        let response = serde_json::json!({"choices": [{"message": {"content": "Hello"}}]});
+        PendingConversation::new(store, "req-7", request.clone())
+            .complete(response.clone())
+            .await
+            .unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let (request_id, created_at, model, stream, stored_request, stored_response): (
+            String,
+            i64,
+            String,
+            bool,
+            String,
+            String,
+        ) = conn
+            .query_row(
+                "SELECT request_id, created_at, model, stream, request, response FROM conversations",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(request_id, "req-7");
+        assert!(created_at > 0);
+        assert_eq!(model, "openai/gpt-4");
+        assert!(stream);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&stored_request).unwrap(),
+            request
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&stored_response).unwrap(),
+            response
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_open_invalid_path() {
+        assert!(matches!(
+            ConversationStore::open("/nonexistent/dir/history.db"),
+            Err(ProxyError::ServerConfiguration(_))
+        ));
+    }
+}
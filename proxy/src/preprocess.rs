@@ -0,0 +1,229 @@
+//! Fixups applied to the raw JSON request body before it is deserialized into
+//! [`OpenAiChatRequest`](crate::types::OpenAiChatRequest).
+//!
+//! Typed deserialization reports malformed fields with generic serde messages. Checking
+//! the raw body first lets us either repair common client mistakes (in lenient mode) or
+//! reject them with an error that names the offending field.
+
+use crate::error::ProxyError;
+use serde_json::Value;
+
+/// Handles message `content` values that are numbers or booleans.
+///
+/// In lenient mode the scalar is replaced by its string form. Otherwise the request is
+/// rejected with `InvalidParameter` naming the message index.
+pub fn coerce_scalar_content(body: &mut Value, lenient: bool) -> Result<(), ProxyError> {
+    let Some(messages) = body.get_mut("messages").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
+
+    for (index, message) in messages.iter_mut().enumerate() {
+        let Some(content) = message.get_mut("content") else {
+            continue;
+        };
+
+        let kind = match content {
+            Value::Number(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            _ => continue,
+        };
+
+        if lenient {
+            *content = Value::String(content.to_string());
+        } else {
+            return Err(ProxyError::InvalidParameter {
+                parameter: format!("messages[{index}].content"),
+                reason: format!(
+                    "content must be a string or an array of content parts, got {kind}"
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a `stream` flag sent as the string `"true"` or `"false"`.
+///
+/// In lenient mode the string is replaced by the boolean it spells (case-insensitively).
+/// Otherwise, and for any other string, the request is rejected with `InvalidParameter`.
+pub fn coerce_string_stream(body: &mut Value, lenient: bool) -> Result<(), ProxyError> {
+    let Some(Value::String(stream)) = body.get("stream") else {
+        return Ok(());
+    };
+
+    match parse_stream_string(stream) {
+        Some(stream) if lenient => {
+            body["stream"] = Value::Bool(stream);
+            Ok(())
+        }
+        _ => Err(ProxyError::InvalidParameter {
+            parameter: "stream".to_string(),
+            reason: format!("stream must be a boolean, got the string {stream:?}"),
+        }),
+    }
+}
+
+/// Whether the raw `body` asks for a streamed response, reading the `stream` flag the way
+/// [`coerce_string_stream`] will: as a boolean, or in lenient mode also as a string.
+pub fn requests_stream(body: &Value, lenient: bool) -> bool {
+    match body.get("stream") {
+        Some(Value::Bool(stream)) => *stream,
+        Some(Value::String(stream)) => lenient && parse_stream_string(stream) == Some(true),
+        _ => false,
+    }
+}
+
+fn parse_stream_string(stream: &str) -> Option<bool> {
+    match stream.trim().to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Rejects `input_audio`/`audio` content parts, which Straico cannot take and which would
+/// otherwise fail deserialization with a generic message.
+pub fn reject_audio_content(body: &Value) -> Result<(), ProxyError> {
+    let Some(messages) = body.get("messages").and_then(Value::as_array) else {
+        return Ok(());
+    };
+
+    for (index, message) in messages.iter().enumerate() {
+        let Some(parts) = message.get("content").and_then(Value::as_array) else {
+            continue;
+        };
+        for (part_index, part) in parts.iter().enumerate() {
+            let Some(kind @ ("input_audio" | "audio")) = part.get("type").and_then(Value::as_str)
+            else {
+                continue;
+            };
+            return Err(ProxyError::InvalidParameter {
+                parameter: format!("messages[{index}].content[{part_index}]"),
+                reason: format!(
+                    "audio input is not supported (message {index} has an {kind:?} content \
+                     part); transcribe the audio and send it as text"
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request_with_content(content: Value) -> Value {
+        json!({
+            "model": "openai/gpt-4",
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": content}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_numeric_content_lenient() {
+        let mut body = request_with_content(json!(123));
+        coerce_scalar_content(&mut body, true).unwrap();
+        assert_eq!(body["messages"][1]["content"], "123");
+    }
+
+    #[test]
+    fn test_boolean_content_lenient() {
+        let mut body = request_with_content(json!(true));
+        coerce_scalar_content(&mut body, true).unwrap();
+        assert_eq!(body["messages"][1]["content"], "true");
+    }
+
+    #[test]
+    fn test_numeric_content_strict() {
+        let mut body = request_with_content(json!(1.5));
+        let err = coerce_scalar_content(&mut body, false).unwrap_err();
+        match err {
+            ProxyError::InvalidParameter { parameter, reason } => {
+                assert_eq!(parameter, "messages[1].content");
+                assert!(reason.contains("a number"));
+            }
+            other => panic!("Unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_boolean_content_strict() {
+        let mut body = request_with_content(json!(false));
+        let err = coerce_scalar_content(&mut body, false).unwrap_err();
+        match err {
+            ProxyError::InvalidParameter { parameter, reason } => {
+                assert_eq!(parameter, "messages[1].content");
+                assert!(reason.contains("a boolean"));
+            }
+            other => panic!("Unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_string_content_untouched() {
+        let mut body = request_with_content(json!("Hello"));
+        let expected = body.clone();
+        coerce_scalar_content(&mut body, false).unwrap();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_string_stream_lenient() {
+        for (value, expected) in [("true", true), ("False", false)] {
+            let mut body = json!({"model": "openai/gpt-4", "stream": value, "messages": []});
+            coerce_string_stream(&mut body, true).unwrap();
+            assert_eq!(body["stream"], expected);
+        }
+    }
+
+    #[test]
+    fn test_requests_stream() {
+        let body = |stream: Value| json!({"model": "openai/gpt-4", "stream": stream});
+        assert!(requests_stream(&body(json!(true)), false));
+        assert!(requests_stream(&body(json!(" TRUE ")), true));
+        assert!(!requests_stream(&body(json!("true")), false));
+        assert!(!requests_stream(&body(json!("false")), true));
+        assert!(!requests_stream(&json!({"model": "openai/gpt-4"}), true));
+    }
+
+    #[test]
+    fn test_string_stream_strict() {
+        let mut body = json!({"model": "openai/gpt-4", "stream": "true", "messages": []});
+        let err = coerce_string_stream(&mut body, false).unwrap_err();
+        assert!(matches!(
+            err,
+            ProxyError::InvalidParameter { ref parameter, .. } if parameter == "stream"
+        ));
+
+        // Not a boolean spelling, even in lenient mode
+        let mut body = json!({"model": "openai/gpt-4", "stream": "yes", "messages": []});
+        assert!(coerce_string_stream(&mut body, true).is_err());
+    }
+
+    #[test]
+    fn test_input_audio_content_is_rejected() {
+        let body = request_with_content(json!([
+            {"type": "text", "text": "What is said here?"},
+            {"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}}
+        ]));
+        let err = reject_audio_content(&body).unwrap_err();
+        match err {
+            ProxyError::InvalidParameter { parameter, reason } => {
+                assert_eq!(parameter, "messages[1].content[1]");
+                assert!(reason.contains("audio input is not supported"));
+                assert!(reason.contains("message 1"));
+            }
+            other => panic!("Unexpected error: {other:?}"),
+        }
+
+        let text_only = request_with_content(json!([{"type": "text", "text": "Hi"}]));
+        reject_audio_content(&text_only).unwrap();
+    }
+}
@@ -1,68 +1,504 @@
 use crate::{
-    error::ProxyError,
-    streaming::{CompletionStream, HeartbeatChar, SseChunk},
+    arg_keys::ArgKeySchemas,
+    choice_selection::{self, SelectBest},
+    error::{ProxyError, RateLimitStatus, StatusRemap},
+    latency::{LatencyProbe, LatencyTracker},
+    model_map::ModelMap,
+    model_transforms::ModelTransforms,
+    persistence::PendingConversation,
+    queue::RequestPermit,
+    reasoning::{self, ThinkingTag},
+    special_tokens,
+    streaming::{
+        self, azure_prompt_filter_chunk, retry_line, CompletionStream, HeartbeatChar, SseChunk,
+        SseDialect, SseEncoder,
+    },
+    system_prompt::{self, CurrentDateZone, SystemOverrideMode, SystemPromptMode},
+    token_limits::{self, MaxTokensPolicy},
+    tool_format::ToolFormatTracker,
+    tool_policy::{self, ToolPolicy, ToolsOverLimit},
+    transport::HttpTransport,
     types::{OpenAiChatRequest, OpenAiChatResponse, StraicoChatResponse},
 };
 use actix_web::HttpResponse;
 use bytes::Bytes;
+use either::Either::{Left, Right};
+use futures::channel::oneshot;
 use futures::{future, stream, FutureExt, StreamExt, TryFutureExt};
+use log::{info, warn};
+use serde::Deserialize;
+use std::borrow::Cow;
 use std::future::Future;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use straico_client::client::StraicoClient;
-use straico_client::StraicoChatRequest;
+use straico_client::endpoints::chat::{
+    conversions, tool_calling, ChatContent, ChatMessage, ModelProvider, OpenAiChatMessage,
+    StringArgs, ToolCallFormat,
+};
+use straico_client::{StraicoChatRequest, StraicoError};
 use tokio::time::Duration;
 use uuid::Uuid;
 
+/// Time between heartbeat chunks while waiting for the upstream response, unless
+/// `--heartbeat-interval` says otherwise.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Temperature sent instead of exactly 0 to providers listed in `--zero-temperature-epsilon`.
+const ZERO_TEMPERATURE_EPSILON: f32 = 0.01;
+
 /// Provider implementation for the native Straico backend.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct StraicoProvider {
     pub client: StraicoClient,
     pub key: String,
     pub heartbeat_char: HeartbeatChar,
+    /// Time between streaming heartbeats (`None`: every 3 seconds)
+    pub heartbeat_interval: Option<Duration>,
+    /// Client-side namespace removed from model IDs before forwarding
+    pub strip_model_prefix: Option<String>,
+    /// Attach the raw upstream response under `_debug.raw_response` (non-streaming only)
+    pub include_debug_info: bool,
+    /// Attach each tool call's arguments as an object under `_debug.parsed_arguments`
+    /// (non-streaming only)
+    pub debug_tool_arguments: bool,
+    /// Forward requests as plain chat, ignoring `tools`/`tool_choice`
+    pub disable_tools: bool,
+    /// Prepend a system note with today's date in the given zone
+    pub inject_current_date: Option<CurrentDateZone>,
+    /// Operator's default system prompt (`--system-prompt`)
+    pub system_prompt: Option<String>,
+    /// How `system_prompt` combines with the request's own system messages
+    pub system_prompt_mode: SystemPromptMode,
+    /// System prompt from the `x-straico-system-override` header
+    pub system_override: Option<String>,
+    /// How `system_override` combines with the request's own system prompt
+    pub system_override_mode: SystemOverrideMode,
+    /// Remove leaked copies of the injected tools instructions from response content
+    pub strip_leaked_tools_prompt: bool,
+    /// Chat template tokens to remove from response content
+    pub strip_special_tokens: Option<Arc<Vec<String>>>,
+    /// Reasoning delimiters whose leading block is moved into `reasoning_content`
+    pub thinking_tags: Option<Arc<Vec<ThinkingTag>>>,
+    /// Remove leading and trailing whitespace from response content
+    pub trim_response: bool,
+    /// Strategy for reducing a multi-choice upstream response to one choice
+    pub select_best: Option<SelectBest>,
+    /// Rename tool call argument keys to the matching keys of the tool's schema
+    pub coerce_arg_keys: bool,
+    /// Alias → upstream model mappings from `--model-map`
+    pub model_map: Arc<ModelMap>,
+    /// Per-model request transformations from `--model-transforms`
+    pub model_transforms: Arc<ModelTransforms>,
+    /// Status returned to clients when the upstream rate-limits a request
+    pub rate_limit_status: RateLimitStatus,
+    /// Client-facing statuses for upstream error statuses (`--remap-status`)
+    pub status_remap: Arc<StatusRemap>,
+    /// Extra upstream attempts after a connection error, timeout, 429 or 5xx response
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one; an upstream
+    /// `retry-after` takes precedence
+    pub retry_base_delay: Duration,
+    /// Upstream timeout for this request, instead of the client's default
+    pub request_timeout: Option<Duration>,
+    /// Reconnection delay advertised to clients with an initial SSE `retry:` line
+    pub sse_retry_ms: Option<u64>,
+    /// SSE framing of streamed responses (`--sse-dialect`)
+    pub sse_dialect: SseDialect,
+    /// Number streamed chunks with a `chunk_index` field (`--sse-chunk-index`)
+    pub sse_chunk_index: bool,
+    /// Heartbeats to send while waiting for the upstream before giving up (`None`: no cap)
+    pub max_heartbeats: Option<u32>,
+    /// Tool names clients may or may not offer the model
+    pub tool_policy: Arc<ToolPolicy>,
+    /// Maximum content length of a single message, in characters
+    pub max_message_length: Option<usize>,
+    /// Maximum length of the injected tool definitions, in characters
+    pub max_tools_prompt_length: Option<usize>,
+    /// Whether tool definitions over `max_tools_prompt_length` are rejected or truncated
+    pub tools_prompt_over_limit: ToolsOverLimit,
+    /// Reject requests without any user or tool-result message
+    pub require_user_message: bool,
+    /// Reject requests whose history holds more assistant tool-call turns than this
+    pub max_tool_turns: Option<usize>,
+    /// Handling of `max_tokens` above the model's known output limit
+    pub max_tokens_policy: MaxTokensPolicy,
+    /// Model providers (`anthropic`, ...) that get a tiny nonzero temperature instead of 0
+    pub zero_temperature_epsilon: Arc<Vec<String>>,
+    /// Temperature used when the request does not set one
+    pub default_temperature: Option<f32>,
+    /// Request id to log raw upstream traffic under, when this request was debug-sampled
+    pub sampled_request_id: Option<String>,
+    /// Pending conversation history entry, when `--persist-db` is configured
+    pub conversation: Option<PendingConversation>,
+    /// Per-model latency statistics updated by completed requests
+    pub latency: Arc<LatencyTracker>,
+    /// Per-model tool call format fallbacks updated by completed requests
+    pub tool_formats: Arc<ToolFormatTracker>,
+    /// Concurrency slot of this request, when `--max-concurrent-requests` is set
+    pub permit: Option<RequestPermit>,
+    /// Sends the chat request upstream; `client` is used when unset
+    pub transport: Option<Arc<dyn HttpTransport>>,
+}
+
+/// Per-request settings applied when converting a Straico response to OpenAI format.
+///
+/// Shared by the streaming and non-streaming paths so both shape responses identically.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseOptions {
+    /// Client-facing model ID to report instead of the upstream one
+    pub client_model: Option<String>,
+    /// Attach the raw upstream response under `_debug.raw_response`
+    pub include_debug_info: bool,
+    /// Attach each tool call's arguments as an object under `_debug.parsed_arguments`
+    pub debug_tool_arguments: bool,
+    /// Return assistant content verbatim instead of extracting tool calls from it
+    pub skip_tool_calls: bool,
+    /// Remove a leaked copy of the tools system message from the start of the content
+    pub strip_leaked_tools_prompt: bool,
+    /// Provider whose tool call format the request was converted with, when it is not
+    /// the model's own (`--model-transforms`)
+    pub tool_format: Option<ModelProvider>,
+    /// Remove these leaked chat template tokens from the content
+    pub strip_special_tokens: Option<Arc<Vec<String>>>,
+    /// Move a leading reasoning block delimited by one of these tags into `reasoning_content`
+    pub thinking_tags: Option<Arc<Vec<ThinkingTag>>>,
+    /// Remove leading and trailing whitespace from the content
+    pub trim_response: bool,
+    /// Return a single choice, picked with this strategy, when the upstream returns several
+    /// and the client asked for one (`n` unset or 1)
+    pub select_best: Option<SelectBest>,
+    /// Service tier requested by the client, echoed back as-is
+    pub service_tier: Option<String>,
+    /// End streamed responses with a usage-only chunk (`stream_options.include_usage`)
+    pub include_usage: bool,
+    /// Add Azure OpenAI's `prompt_filter_results` and per-choice `content_filter_results`
+    /// to non-streaming responses (`--sse-dialect azure`)
+    pub azure_filter_results: bool,
+    /// Cut the content at the first of these sequences (the request's `stop`)
+    pub stop: Vec<String>,
+    /// Declared argument keys and types of the request's tools
+    pub arg_key_schemas: Option<ArgKeySchemas>,
+    /// Rename tool call argument keys to the declared ones (`--coerce-arg-keys`)
+    pub coerce_arg_keys: bool,
+    /// Log the raw upstream response under this request id (debug sampling)
+    pub sampled_request_id: Option<String>,
+    /// Record the assembled streamed response in the conversation history (`--persist-db`);
+    /// non-streaming responses are recorded by the handler once a fallback model is chosen
+    pub conversation: Option<PendingConversation>,
+    /// Record the request's latency once the response is returned (see [`ResponseStats`])
+    pub latency: Option<LatencyProbe>,
+    /// Record tool calls that only parse with a fallback format, and apply adapted formats
+    pub tool_formats: Option<Arc<ToolFormatTracker>>,
+    /// Concurrency slot, held until the response (or stream) is dropped
+    pub permit: Option<RequestPermit>,
+}
+
+/// Statistics gathered while converting a response, recorded with [`ResponseStats::record`]
+/// only once it is the response returned to the client. A fallback model's discarded
+/// empty completion therefore never counts.
+#[derive(Debug, Default)]
+pub struct ResponseStats {
+    latency: Option<LatencyProbe>,
+    tool_formats: Option<Arc<ToolFormatTracker>>,
+    model: String,
+    /// Assistant contents that were parsed for tool calls
+    tool_call_contents: Vec<String>,
+    /// Format tried first on `tool_call_contents`
+    preferred_format: Option<ToolCallFormat>,
+}
+
+impl ResponseStats {
+    pub fn record(self) {
+        if let Some(tracker) = &self.tool_formats {
+            for content in &self.tool_call_contents {
+                tracker.observe(&self.model, content, self.preferred_format);
+            }
+        }
+        if let Some(probe) = &self.latency {
+            probe.finish();
+        }
+    }
+}
+
+/// Provider-level settings for the framing of streamed responses.
+#[derive(Clone, Debug)]
+pub struct StreamOptions {
+    pub heartbeat_char: HeartbeatChar,
+    pub heartbeat_interval: Duration,
+    /// Give up with an error chunk after this many heartbeats without an upstream response
+    pub max_heartbeats: Option<u32>,
+    /// Emit `retry: <ms>` before the first chunk
+    pub retry_ms: Option<u64>,
+    pub dialect: SseDialect,
+    /// Number JSON chunks with a non-standard `chunk_index` field
+    pub chunk_index: bool,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            heartbeat_char: HeartbeatChar::default(),
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+            max_heartbeats: None,
+            retry_ms: None,
+            dialect: SseDialect::default(),
+            chunk_index: false,
+        }
+    }
 }
 
 impl StraicoProvider {
+    /// Returns the model ID to forward upstream, with the configured prefix removed.
+    ///
+    /// The prefix only matches a whole path segment, with or without its trailing `/`
+    /// configured: prefix `straico` turns `straico/anthropic/claude-3` into
+    /// `anthropic/claude-3` but leaves `straicomodel` alone.
+    /// Aliases from the model map are then resolved, matching either the ID as sent or
+    /// the ID with the prefix removed.
+    pub fn upstream_model<'a>(&self, model: &'a str) -> Cow<'a, str> {
+        let stripped = self
+            .strip_model_prefix
+            .as_deref()
+            .and_then(|prefix| model.strip_prefix(&format!("{}/", prefix.trim_end_matches('/'))))
+            .unwrap_or(model);
+        self.model_map
+            .resolve(model)
+            .or_else(|| self.model_map.resolve(stripped))
+            .map_or(Cow::Borrowed(stripped), Cow::Owned)
+    }
+
+    /// Builds the response conversion settings for a request made with the client-facing `model`.
+    pub fn response_options(&self, model: &str) -> ResponseOptions {
+        ResponseOptions {
+            // Only report the client-facing ID back when it differs from the upstream one
+            client_model: (self.upstream_model(model) != model).then(|| model.to_string()),
+            include_debug_info: self.include_debug_info,
+            debug_tool_arguments: self.debug_tool_arguments,
+            skip_tool_calls: self.disable_tools,
+            strip_leaked_tools_prompt: self.strip_leaked_tools_prompt,
+            tool_format: self
+                .model_transforms
+                .for_model(&self.upstream_model(model))
+                .tool_format,
+            strip_special_tokens: self.strip_special_tokens.clone(),
+            thinking_tags: self.thinking_tags.clone(),
+            trim_response: self.trim_response,
+            select_best: self.select_best,
+            service_tier: None,
+            include_usage: false,
+            azure_filter_results: self.sse_dialect == SseDialect::Azure,
+            stop: Vec::new(),
+            arg_key_schemas: None,
+            coerce_arg_keys: self.coerce_arg_keys,
+            sampled_request_id: self.sampled_request_id.clone(),
+            conversation: self.conversation.clone(),
+            latency: Some(LatencyProbe::start(
+                self.latency.clone(),
+                self.upstream_model(model),
+            )),
+            tool_formats: Some(self.tool_formats.clone()),
+            permit: self.permit.clone(),
+        }
+    }
+
+    /// Builds the response conversion settings for `request`, including the per-request
+    /// fields that are echoed back to the client.
+    pub fn request_options(&self, request: &OpenAiChatRequest) -> ResponseOptions {
+        ResponseOptions {
+            service_tier: request.service_tier.clone(),
+            include_usage: request
+                .stream_options
+                .as_ref()
+                .is_some_and(|options| options.include_usage),
+            stop: request
+                .stop
+                .as_ref()
+                .map(|stop| stop.as_slice().to_vec())
+                .unwrap_or_default(),
+            arg_key_schemas: request.tools.as_deref().map(ArgKeySchemas::from_tools),
+            // A client asking for several choices gets every choice the upstream returns
+            select_best: self.select_best.filter(|_| request.n.unwrap_or(1) <= 1),
+            ..self.response_options(&request.chat_request.model)
+        }
+    }
+
+    pub fn stream_options(&self) -> StreamOptions {
+        StreamOptions {
+            heartbeat_char: self.heartbeat_char,
+            heartbeat_interval: self.heartbeat_interval.unwrap_or(HEARTBEAT_INTERVAL),
+            max_heartbeats: self.max_heartbeats,
+            retry_ms: self.sse_retry_ms,
+            dialect: self.sse_dialect,
+            chunk_index: self.sse_chunk_index,
+        }
+    }
+
+    /// Whether `model`'s provider treats `temperature: 0` as "use the default" rather than
+    /// as fully deterministic, so 0 must be replaced by a tiny epsilon.
+    fn needs_temperature_epsilon(&self, model: &str) -> bool {
+        let provider = model.split('/').next().unwrap_or_default();
+        self.zero_temperature_epsilon
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(provider))
+    }
+
+    /// Applies the proxy's request policies and converts the request to Straico format.
+    pub fn build_chat_request(
+        &self,
+        mut request: OpenAiChatRequest,
+    ) -> Result<StraicoChatRequest, ProxyError> {
+        let upstream_model = self
+            .upstream_model(&request.chat_request.model)
+            .into_owned();
+        token_limits::check_max_tokens(
+            &upstream_model,
+            &mut request.chat_request.max_tokens,
+            self.max_tokens_policy,
+        )?;
+        check_temperature(request.chat_request.temperature)?;
+        let transform = self.model_transforms.for_model(&upstream_model);
+        // An explicit temperature, 0 included, always wins over the default
+        request.chat_request.temperature = request
+            .chat_request
+            .temperature
+            .or(self.default_temperature)
+            .map(|temperature| {
+                transform
+                    .max_temperature
+                    .map_or(temperature, |max| temperature.min(max))
+            });
+        if request.chat_request.temperature == Some(0.0)
+            && self.needs_temperature_epsilon(&upstream_model)
+        {
+            request.chat_request.temperature = Some(ZERO_TEMPERATURE_EPSILON);
+        }
+        request.chat_request.model = upstream_model;
+        if self.disable_tools {
+            request.tools = None;
+            request.tool_choice = None;
+        }
+        self.tool_policy
+            .check(request.tools.as_deref(), request.tool_choice.as_ref())?;
+        if let Some(max) = self.max_tools_prompt_length {
+            tool_policy::check_tools_length(
+                &mut request.tools,
+                &mut request.tool_choice,
+                max,
+                self.tools_prompt_over_limit,
+            )?;
+        }
+        if let Some(max) = self.max_message_length {
+            check_message_lengths(&request, max)?;
+        }
+        if self.require_user_message {
+            check_has_user_message(&request)?;
+        }
+        if let Some(max) = self.max_tool_turns {
+            let turns = count_tool_turns(&request);
+            if turns > max {
+                return Err(ProxyError::BadRequest(format!(
+                    "conversation has {turns} tool-call turns, exceeding the limit of {max}"
+                )));
+            }
+        }
+        // The model's own prompt goes first, so with `fill` it takes the place of the default
+        if let Some(text) = &transform.system_prompt {
+            system_prompt::apply_default_system_prompt(
+                &mut request,
+                text,
+                transform.system_prompt_mode,
+            );
+        }
+        if let Some(text) = &self.system_prompt {
+            system_prompt::apply_default_system_prompt(&mut request, text, self.system_prompt_mode);
+        }
+        // A per-request override takes precedence over the operator's default
+        if let Some(text) = &self.system_override {
+            system_prompt::apply_system_override(
+                &mut request,
+                text.clone(),
+                self.system_override_mode,
+            );
+        }
+        // Injected system notes go at the front; the tools system message is appended last
+        // by the conversion, so the date always precedes the tools instructions.
+        if let Some(zone) = self.inject_current_date {
+            system_prompt::prepend_system_message(
+                &mut request,
+                system_prompt::current_date_note(zone),
+            );
+        }
+        let tool_provider = transform
+            .tool_format
+            .unwrap_or_else(|| ModelProvider::from(request.chat_request.model.as_str()));
+        Ok(conversions::convert_request_with_provider(
+            request,
+            tool_provider,
+        )?)
+    }
+
     pub fn send_request(
         &self,
         request: OpenAiChatRequest,
     ) -> Result<impl Future<Output = Result<reqwest::Response, reqwest::Error>> + 'static, ProxyError>
     {
-        let chat_request = StraicoChatRequest::try_from(request)?;
-        Ok(self
-            .client
+        let chat_request = self.build_chat_request(request)?;
+        if let Some(request_id) = &self.sampled_request_id {
+            info!(
+                "[{}] Sampled upstream request: {}",
+                request_id,
+                serde_json::to_string(&chat_request)?
+            );
+        }
+        let transport = self
+            .transport
             .clone()
-            .chat()
-            .bearer_auth(&self.key)
-            .json(chat_request)
-            .send())
+            .unwrap_or_else(|| Arc::new(self.client.clone()));
+        let key = self.key.clone();
+        let timeout = self.request_timeout;
+        let chat_request = Arc::new(chat_request);
+        let send = move || {
+            let transport = transport.clone();
+            let key = key.clone();
+            let chat_request = chat_request.clone();
+            async move { transport.send_chat(&key, &chat_request, timeout).await }
+        };
+        Ok(send_with_retries(
+            send,
+            self.max_retries,
+            self.retry_base_delay,
+        ))
     }
 
+    /// Converts a non-streaming upstream response. Its statistics are returned alongside, to
+    /// be recorded if the response is the one sent to the client.
     pub fn parse_non_streaming(
         &self,
         response: reqwest::Response,
-    ) -> impl Future<Output = Result<serde_json::Value, ProxyError>> {
+        options: ResponseOptions,
+    ) -> impl Future<Output = Result<(serde_json::Value, ResponseStats), ProxyError>> {
         // Chain the asynchronous operations using future combinators instead of `async/await`.
         // This avoids heap allocation (`Box`) and the `async` keyword.
-        map_common_non_streaming_errors(response)
+        map_common_non_streaming_errors(response, self.rate_limit_status, self.status_remap.clone())
             .and_then(|response| {
                 // `response.json()` is an asynchronous call, so we chain it with `and_then`.
                 // We use `map_err` to convert its `reqwest::Error` into our `ProxyError`
                 // to match the error type of the chain.
+                // The body is kept as raw JSON so it can be echoed back for debugging.
                 response
-                    .json::<StraicoChatResponse>()
+                    .json::<serde_json::Value>()
                     .map_err(ProxyError::from)
             })
-            .then(|result| {
+            .then(move |result| {
                 // `.then` is used because we need to perform synchronous operations
                 // on the final `Result`. It receives the `Result` directly.
                 //
                 // The original `async` block used the `?` operator to chain these
                 // final, synchronous transformations. We replicate that logic here.
                 // The `and_then` on the `Result` type mirrors the `?` operator.
-                let final_result = result.and_then(|straico_response| {
-                    let openai_response = OpenAiChatResponse::try_from(straico_response)?;
-                    serde_json::to_value(openai_response).map_err(ProxyError::from)
-                });
+                let final_result = result
+                    .and_then(|raw_response| build_non_streaming_json(raw_response, &options));
 
                 // The `then` combinator requires a `Future` to be returned.
                 // Since our transformations were synchronous, we wrap the final `Result`
@@ -75,9 +511,517 @@ impl StraicoProvider {
         &self,
         model: &str,
         response_future: impl Future<Output = Result<reqwest::Response, reqwest::Error>> + 'static,
+        options: ResponseOptions,
     ) -> Result<HttpResponse, ProxyError> {
-        create_straico_streaming_response(model, response_future, self.heartbeat_char)
+        create_straico_streaming_response(model, response_future, self.stream_options(), options)
+    }
+}
+
+/// Rejects temperatures outside OpenAI's accepted range of 0 to 2.
+fn check_temperature(temperature: Option<f32>) -> Result<(), ProxyError> {
+    match temperature {
+        Some(temperature) if !(0.0..=2.0).contains(&temperature) => {
+            Err(ProxyError::InvalidParameter {
+                parameter: "temperature".to_string(),
+                reason: format!("{temperature} is not between 0 and 2"),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Rejects the request if any client message's content is longer than `max` characters.
+fn check_message_lengths(request: &OpenAiChatRequest, max: usize) -> Result<(), ProxyError> {
+    for (index, message) in request.chat_request.messages.iter().enumerate() {
+        let length = match message {
+            OpenAiChatMessage::System { content, .. }
+            | OpenAiChatMessage::Developer { content, .. }
+            | OpenAiChatMessage::User { content, .. }
+            | OpenAiChatMessage::Tool { content, .. } => content.to_string().chars().count(),
+            OpenAiChatMessage::Assistant { content, .. } => content
+                .as_ref()
+                .map_or(0, |content| content.to_string().chars().count()),
+        };
+        if length > max {
+            return Err(ProxyError::InvalidParameter {
+                parameter: format!("messages[{index}].content"),
+                reason: format!(
+                    "message content is {length} characters, exceeding the limit of {max}"
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Rejects requests made only of system, developer and assistant messages, which leave the
+/// model nothing to respond to.
+fn check_has_user_message(request: &OpenAiChatRequest) -> Result<(), ProxyError> {
+    let has_input = request.chat_request.messages.iter().any(|message| {
+        matches!(
+            message,
+            OpenAiChatMessage::User { .. } | OpenAiChatMessage::Tool { .. }
+        )
+    });
+    if has_input {
+        Ok(())
+    } else {
+        Err(ProxyError::BadRequest(
+            "messages must include at least one user or tool message".to_string(),
+        ))
+    }
+}
+
+/// Counts the assistant turns in the request history that made tool calls, i.e. how many
+/// rounds an agent loop has gone through.
+pub fn count_tool_turns(request: &OpenAiChatRequest) -> usize {
+    request
+        .chat_request
+        .messages
+        .iter()
+        .filter(|message| {
+            matches!(
+                message,
+                OpenAiChatMessage::Assistant {
+                    tool_calls: Some(calls),
+                    ..
+                } if !calls.is_empty()
+            )
+        })
+        .count()
+}
+
+/// Most models tried for one request with a comma-separated `model`.
+pub const MAX_FALLBACK_MODELS: usize = 4;
+
+/// Splits a comma-separated `model` (e.g. `anthropic/claude-3,openai/gpt-4`) into the
+/// models to try in order, trimmed and capped at [`MAX_FALLBACK_MODELS`]. A single model
+/// is returned as-is.
+pub fn fallback_models(model: &str) -> Vec<String> {
+    let models: Vec<String> = model
+        .split(',')
+        .map(str::trim)
+        .filter(|model| !model.is_empty())
+        .take(MAX_FALLBACK_MODELS)
+        .map(str::to_string)
+        .collect();
+    if models.is_empty() {
+        vec![model.to_string()]
+    } else {
+        models
+    }
+}
+
+/// Whether a converted response has neither content nor tool calls in its first choice.
+pub fn is_empty_completion(response: &serde_json::Value) -> bool {
+    let message = &response["choices"][0]["message"];
+    message["content"]
+        .as_str()
+        .is_none_or(|content| content.trim().is_empty())
+        && message["tool_calls"]
+            .as_array()
+            .is_none_or(|calls| calls.is_empty())
+}
+
+/// Longest wait between retries, whatever the backoff or the upstream's `retry-after` says.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Sends the request, repeating it up to `max_retries` times while the outcome is retryable.
+///
+/// Retry `n` waits `base_delay * 2^(n-1)`, or the upstream's `retry-after` if it sent one.
+/// Streamed requests go through here too; heartbeats keep flowing while it waits.
+async fn send_with_retries<F, Fut>(
+    send: F,
+    max_retries: u32,
+    base_delay: Duration,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        let result = send().await;
+        if attempt >= max_retries || !is_retryable(&result) {
+            return result;
+        }
+        let delay = result
+            .as_ref()
+            .ok()
+            .and_then(retry_after)
+            .unwrap_or_else(|| base_delay.saturating_mul(1 << attempt.min(16)))
+            .min(MAX_RETRY_DELAY);
+        attempt += 1;
+        match &result {
+            Ok(response) => warn!(
+                "Upstream returned {}, retrying in {:?} ({}/{})",
+                response.status(),
+                delay,
+                attempt,
+                max_retries
+            ),
+            Err(e) => warn!(
+                "Upstream request failed: {}, retrying in {:?} ({}/{})",
+                e, delay, attempt, max_retries
+            ),
+        }
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// The upstream's `retry-after` header, in seconds (HTTP dates are not supported).
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn is_retryable(result: &Result<reqwest::Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => {
+            response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || response.status().is_server_error()
+        }
+        Err(e) => e.is_connect() || e.is_timeout(),
+    }
+}
+
+/// Upstream body as read by the streaming path.
+enum UpstreamBody {
+    Complete(serde_json::Value),
+    /// The read timed out; holds the assistant content recovered from the partial body
+    TimedOut(String),
+}
+
+/// Reads the whole upstream body, keeping what arrived if the read times out part-way.
+async fn read_upstream_body(response: reqwest::Response) -> Result<UpstreamBody, ProxyError> {
+    let mut body = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        match chunk {
+            Ok(bytes) => body.extend_from_slice(&bytes),
+            Err(e) if e.is_timeout() => {
+                return match partial_content(&body) {
+                    Some(partial) => Ok(UpstreamBody::TimedOut(partial)),
+                    None => Err(e.into()),
+                };
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    serde_json::from_slice(&body)
+        .map(UpstreamBody::Complete)
+        .map_err(|_| {
+            ProxyError::ResponseParse(serde_json::Value::String(
+                String::from_utf8_lossy(&body).into_owned(),
+            ))
+        })
+}
+
+/// Recovers the (possibly cut-off) first `"content"` string from a truncated JSON body.
+fn partial_content(body: &[u8]) -> Option<String> {
+    let body = String::from_utf8_lossy(body);
+    let after_key = &body[body.find("\"content\"")? + "\"content\"".len()..];
+    let value = after_key.trim_start().strip_prefix(':')?.trim_start();
+    let mut chars = value.strip_prefix('"')?.chars();
+
+    let mut content = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => content.push('\n'),
+                Some('t') => content.push('\t'),
+                Some('r') => content.push('\r'),
+                Some('b') => content.push('\u{8}'),
+                Some('f') => content.push('\u{c}'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(decoded) => content.push(decoded),
+                        // Cut off mid-escape (or a surrogate pair); stop at what we have
+                        None => break,
+                    }
+                }
+                Some(other) => content.push(other),
+                None => break,
+            },
+            c => content.push(c),
+        }
+    }
+    (!content.is_empty()).then_some(content)
+}
+
+/// Deserializes a raw Straico response body, surfacing error payloads sent with HTTP 200.
+fn parse_straico_response(
+    raw_response: &serde_json::Value,
+    options: &ResponseOptions,
+) -> Result<StraicoChatResponse, ProxyError> {
+    if let Some(request_id) = &options.sampled_request_id {
+        info!(
+            "[{}] Sampled upstream response: {}",
+            request_id, raw_response
+        );
+    }
+    if let Some(message) = embedded_error_message(raw_response) {
+        return Err(ProxyError::Straico(StraicoError::Api(format!(
+            "Straico returned an error with a successful status: {message}"
+        ))));
+    }
+    let response = StraicoChatResponse::deserialize(raw_response)
+        .map_err(|_| ProxyError::ResponseParse(raw_response.clone()))?;
+    // A successful but empty answer would otherwise reach clients as a valid-looking response
+    if response.response.choices.is_empty() {
+        return Err(ProxyError::ServiceUnavailable(
+            "Straico returned a successful response with no choices".to_string(),
+        ));
+    }
+    Ok(response)
+}
+
+/// Extracts the message from an error payload such as `{"error": "..."}`,
+/// `{"error": {"message": "..."}}` or `{"success": false, "message": "..."}`.
+fn embedded_error_message(raw_response: &serde_json::Value) -> Option<String> {
+    let as_message = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Object(obj) => obj
+            .get("message")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string),
+        _ => None,
+    };
+
+    if let Some(message) = raw_response.get("error").and_then(as_message) {
+        return Some(message);
+    }
+    if raw_response.get("success") == Some(&serde_json::Value::Bool(false)) {
+        return Some(
+            raw_response
+                .get("message")
+                .and_then(as_message)
+                .unwrap_or_else(|| "request was not successful".to_string()),
+        );
+    }
+    None
+}
+
+/// Builds the OpenAI-format JSON body for a raw Straico response.
+///
+/// When `include_debug_info` is set, the untouched upstream JSON is attached under the
+/// non-standard `_debug.raw_response` key. With `debug_tool_arguments`, every tool call gets
+/// its arguments as a JSON object (rather than the standard string) under
+/// `_debug.parsed_arguments`.
+fn build_non_streaming_json(
+    raw_response: serde_json::Value,
+    options: &ResponseOptions,
+) -> Result<(serde_json::Value, ResponseStats), ProxyError> {
+    let straico_response = parse_straico_response(&raw_response, options)?;
+    let (openai_response, stats) = convert_straico_response(straico_response, options)?;
+    let mut json = serde_json::to_value(openai_response)?;
+
+    if options.include_debug_info {
+        json["_debug"] = serde_json::json!({ "raw_response": raw_response });
+    }
+
+    if options.azure_filter_results {
+        json["prompt_filter_results"] = streaming::prompt_filter_results();
+        for choice in json["choices"].as_array_mut().into_iter().flatten() {
+            choice["content_filter_results"] = streaming::content_filter_results();
+        }
+    }
+
+    if options.debug_tool_arguments {
+        let tool_calls = json["choices"]
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .filter_map(|choice| choice["message"]["tool_calls"].as_array_mut())
+            .flatten();
+        for tool_call in tool_calls {
+            let parsed = tool_call["function"]["arguments"]
+                .as_str()
+                .and_then(|arguments| serde_json::from_str::<serde_json::Value>(arguments).ok())
+                .unwrap_or(serde_json::Value::Null);
+            tool_call["_debug"] = serde_json::json!({ "parsed_arguments": parsed });
+        }
+    }
+
+    Ok((json, stats))
+}
+
+/// Converts a Straico response into OpenAI format according to `options`.
+fn convert_straico_response(
+    mut straico_response: StraicoChatResponse,
+    options: &ResponseOptions,
+) -> Result<(OpenAiChatResponse, ResponseStats), ProxyError> {
+    if let Some(strategy) = options
+        .select_best
+        .filter(|_| straico_response.response.choices.len() > 1)
+    {
+        choice_selection::select_choice(&mut straico_response.response.choices, strategy);
+    }
+
+    if options.strip_leaked_tools_prompt {
+        // Must run before tool-call extraction: the leaked instructions contain example calls
+        let provider = options
+            .tool_format
+            .unwrap_or_else(|| ModelProvider::from(straico_response.response.model.as_str()));
+        for choice in &mut straico_response.response.choices {
+            if let ChatMessage::Assistant { content, .. } = &mut choice.message {
+                if let Some(stripped) =
+                    tool_calling::strip_leaked_tools_prompt(&content.to_string(), provider)
+                {
+                    *content = ChatContent::String(stripped);
+                }
+            }
+        }
+    }
+
+    if let Some(tokens) = &options.strip_special_tokens {
+        for choice in &mut straico_response.response.choices {
+            if let ChatMessage::Assistant { content, .. } = &mut choice.message {
+                let text = content.to_string();
+                let stripped = special_tokens::strip_special_tokens(&text, tokens);
+                if stripped != text {
+                    *content = ChatContent::String(stripped);
+                }
+            }
+        }
+    }
+
+    // Taken out before tool-call extraction: reasoning often drafts the calls it will make
+    let mut reasoning = vec![None; straico_response.response.choices.len()];
+    if let Some(tags) = &options.thinking_tags {
+        for (choice, reasoning) in straico_response
+            .response
+            .choices
+            .iter_mut()
+            .zip(&mut reasoning)
+        {
+            if let ChatMessage::Assistant { content, .. } = &mut choice.message {
+                if let Some((thought, answer)) =
+                    reasoning::extract_reasoning(&content.to_string(), tags)
+                {
+                    *content = ChatContent::String(answer);
+                    *reasoning = Some(thought);
+                }
+            }
+        }
+    }
+
+    if !options.stop.is_empty() {
+        // Straico cannot stop generation early, so the content is cut afterwards
+        for choice in &mut straico_response.response.choices {
+            if let ChatMessage::Assistant { content, .. } = &mut choice.message {
+                let text = content.to_string();
+                if let Some(end) = stop_position(&text, &options.stop) {
+                    *content = ChatContent::String(text[..end].to_string());
+                    choice.finish_reason = Some("stop".to_string());
+                }
+            }
+        }
+    }
+
+    if options.trim_response {
+        // Applied to the complete content only, so whitespace inside it is left alone
+        for choice in &mut straico_response.response.choices {
+            if let ChatMessage::Assistant { content, .. } = &mut choice.message {
+                let text = content.to_string();
+                if text.trim().len() != text.len() {
+                    *content = ChatContent::String(text.trim().to_string());
+                }
+            }
+        }
+    }
+
+    // A forced format is tried first; the model's own formats remain as fallbacks
+    let mut preferred_format = options
+        .tool_format
+        .map(|provider| provider.tool_call_format());
+    let mut stats = ResponseStats {
+        latency: options.latency.clone(),
+        ..Default::default()
+    };
+    if let Some(tracker) = options
+        .tool_formats
+        .as_ref()
+        .filter(|_| !options.skip_tool_calls)
+    {
+        let model = straico_response.response.model.as_str();
+        preferred_format = preferred_format.or_else(|| tracker.preferred(model));
+        stats.tool_formats = Some(tracker.clone());
+        stats.model = model.to_string();
+        stats.preferred_format = preferred_format;
+        // Structured tool calls are kept as-is, so their content is never parsed
+        stats.tool_call_contents = straico_response
+            .response
+            .choices
+            .iter()
+            .filter_map(|choice| match &choice.message {
+                ChatMessage::Assistant {
+                    content,
+                    tool_calls,
+                } if tool_calls.as_ref().is_none_or(Vec::is_empty) => Some(content.to_string()),
+                _ => None,
+            })
+            .collect();
+    }
+
+    let mut openai_response = OpenAiChatResponse::from_straico_with_format(
+        straico_response,
+        !options.skip_tool_calls,
+        preferred_format,
+        options
+            .arg_key_schemas
+            .as_ref()
+            .map(|schemas| schemas as &dyn StringArgs),
+    )?;
+    for (choice, thought) in openai_response.choices.iter_mut().zip(reasoning) {
+        if let OpenAiChatMessage::Assistant {
+            reasoning_content, ..
+        } = &mut choice.message
+        {
+            *reasoning_content = thought;
+        }
+    }
+    if let Some(model) = &options.client_model {
+        openai_response.model = model.clone();
+    }
+    if let Some(tier) = &options.service_tier {
+        openai_response.service_tier = Some(tier.clone());
+    }
+    if let Some(schemas) = options
+        .arg_key_schemas
+        .as_ref()
+        .filter(|_| options.coerce_arg_keys)
+    {
+        for choice in &mut openai_response.choices {
+            if let OpenAiChatMessage::Assistant {
+                tool_calls: Some(tool_calls),
+                ..
+            } = &mut choice.message
+            {
+                schemas.coerce(tool_calls);
+            }
+        }
     }
+    Ok((openai_response, stats))
+}
+
+/// Byte offset of the earliest match of any of the `stop` sequences in `content`.
+fn stop_position(content: &str, stop: &[String]) -> Option<usize> {
+    stop.iter()
+        .filter(|sequence| !sequence.is_empty())
+        .filter_map(|sequence| content.find(sequence.as_str()))
+        .min()
 }
 
 /// Safely gets the current Unix timestamp, with fallback for edge cases.
@@ -91,48 +1035,165 @@ fn get_current_timestamp() -> u64 {
 fn create_straico_streaming_response(
     model: &str,
     future_response: impl Future<Output = Result<reqwest::Response, reqwest::Error>> + 'static,
-    heartbeat_char: HeartbeatChar,
+    stream_options: StreamOptions,
+    options: ResponseOptions,
 ) -> Result<HttpResponse, ProxyError> {
     let id = format!("chatcmpl-{}", Uuid::new_v4());
     let created = get_current_timestamp();
 
-    let initial_chunk = stream::once(future::ready(
-        SseChunk::from(CompletionStream::initial_chunk(model, &id, created)).try_into(),
-    ));
+    // Azure clients also expect filter results on every choice
+    let azure = stream_options.dialect == SseDialect::Azure;
+    let with_filter_results = move |chunk: CompletionStream| {
+        if azure {
+            chunk.with_content_filter_results()
+        } else {
+            chunk
+        }
+    };
 
-    let (remote, remote_handle) = future_response.remote_handle();
+    let initial_chunk = SseChunk::from(with_filter_results(CompletionStream::initial_chunk(
+        model, &id, created,
+    )));
+    let heartbeat_chunk = SseChunk::from(CompletionStream::heartbeat_chunk(
+        &stream_options.heartbeat_char,
+    ));
 
-    let heartbeat_chunk: Bytes =
-        SseChunk::from(CompletionStream::heartbeat_chunk(&heartbeat_char)).try_into()?;
+    let client_model = model.to_string();
+    let include_usage = options.include_usage;
+    let body = move |response: Result<Result<reqwest::Response, reqwest::Error>, ProxyError>| async move {
+        let result = match response.and_then(|response| response.map_err(ProxyError::from)) {
+            Ok(response) => read_upstream_body(response).await,
+            Err(e) => Err(e),
+        };
+        let result = result.and_then(|body| match body {
+            UpstreamBody::Complete(raw_response) => parse_straico_response(&raw_response, &options)
+                .and_then(|response| convert_straico_response(response, &options))
+                .map(|(response, stats)| {
+                    // A stream has no fallbacks, so its one response is the final one
+                    stats.record();
+                    // Detached: the stream does not wait for the row to be written
+                    if let Some(conversation) = &options.conversation {
+                        conversation.clone().complete(serde_json::json!(response));
+                    }
+                    CompletionStream::from(response)
+                }),
+            UpstreamBody::TimedOut(partial) => Ok(CompletionStream::timeout_chunk(
+                &client_model,
+                &id,
+                created,
+                &partial,
+            )),
+        });
+        match result {
+            Ok(chunk) => chunk
+                .into_choice_chunks(include_usage)
+                .into_iter()
+                .map(|chunk| SseChunk::from(with_filter_results(chunk)))
+                .collect(),
+            Err(e) => vec![SseChunk::from(e)],
+        }
+    };
 
-    let heartbeat = tokio_stream::StreamExt::throttle(
-        stream::repeat(heartbeat_chunk).map(Ok::<Bytes, ProxyError>),
-        Duration::from_secs(3),
+    sse_response(
+        stream_options,
+        Some(initial_chunk),
+        heartbeat_chunk,
+        future_response,
+        body,
     )
-    .take_until(remote);
-
-    let straico_stream = remote_handle
-        .and_then(reqwest::Response::json::<StraicoChatResponse>)
-        .map(|result| {
-            result
-                .map_err(ProxyError::from)
-                .and_then(CompletionStream::try_from)
+}
+
+/// Frames a streamed response as set by `stream_options`: the `retry:` line, Azure's
+/// prompt filter chunk, the `opening` chunk, then heartbeats until `upstream` is ready,
+/// the chunks `body` makes of its output, and `[DONE]`.
+///
+/// Once `max_heartbeats` heartbeats were sent, `upstream` is dropped unfinished and
+/// `body` gets a 504 error instead.
+pub fn sse_response<T, B>(
+    stream_options: StreamOptions,
+    opening: Option<SseChunk>,
+    heartbeat_chunk: SseChunk,
+    upstream: impl Future<Output = T> + 'static,
+    body: impl FnOnce(Result<T, ProxyError>) -> B + 'static,
+) -> Result<HttpResponse, ProxyError>
+where
+    T: 'static,
+    B: Future<Output = Vec<SseChunk>> + 'static,
+{
+    // Frames are either pre-encoded bytes (`Left`) or chunks still to be serialized
+    // (`Right`), which all go through one `SseEncoder` and its reused buffer
+    let retry = stream::iter(stream_options.retry_ms.map(|ms| Left(retry_line(ms))));
+
+    // Azure clients expect the prompt's content-filter results before the role chunk
+    let preamble = stream::iter(
+        (stream_options.dialect == SseDialect::Azure)
+            .then(|| Right(SseChunk::Event(azure_prompt_filter_chunk()))),
+    );
+    let opening = stream::iter(opening.map(Right));
+
+    // The upstream output is handed over through a channel so the upstream can be driven
+    // (and dropped) by the heartbeat stream
+    let (upstream_tx, upstream_rx) = oneshot::channel();
+    let remote = upstream.map(move |output| {
+        let _ = upstream_tx.send(output);
+    });
+
+    // Heartbeats are encoded once, unless each one needs its own chunk index
+    let heartbeat_frame = if stream_options.chunk_index {
+        Right(heartbeat_chunk)
+    } else {
+        Left(Bytes::try_from(heartbeat_chunk)?)
+    };
+
+    // Each heartbeat waits a full interval *before* it is emitted, so an upstream that
+    // answers within the first interval produces no heartbeats at all. `take_until` polls
+    // `remote` before the heartbeat stream, so a ready response always wins a tie.
+    let interval = stream_options.heartbeat_interval;
+    let max_heartbeats = stream_options.max_heartbeats;
+    let heartbeat = stream::repeat(heartbeat_frame)
+        .then(move |frame| async move {
+            tokio::time::sleep(interval).await;
+            frame
         })
-        .map_ok(SseChunk::from)
-        .map(|result| match result {
-            Ok(chunk) => chunk.try_into(),
-            Err(e) => SseChunk::from(e).try_into(),
+        .take_until(remote)
+        .take(max_heartbeats.map_or(usize::MAX, |max| max as usize));
+
+    // The upstream is only dropped unfinished when the heartbeat cap was reached
+    let output = upstream_rx
+        .map(move |output| {
+            output.map_err(|oneshot::Canceled| {
+                ProxyError::UpstreamError(
+                    504,
+                    format!(
+                        "No response from upstream after {} heartbeats",
+                        max_heartbeats.unwrap_or_default()
+                    ),
+                )
+            })
         })
-        .into_stream();
+        .then(body)
+        .into_stream()
+        .flat_map(|chunks| stream::iter(chunks.into_iter().map(Right)));
 
-    let done = stream::once(future::ready(
-        SseChunk::from("[DONE]".to_string()).try_into(),
-    ));
+    let done = stream::once(future::ready(Right(SseChunk::from("[DONE]".to_string()))));
 
-    let response_stream = initial_chunk
+    // Chunk indices span every JSON chunk (initial, heartbeats, content, errors), so
+    // clients can spot gaps
+    let mut encoder = if stream_options.chunk_index {
+        SseEncoder::numbered()
+    } else {
+        SseEncoder::default()
+    };
+    let response_stream = retry
+        .chain(preamble)
+        .chain(opening)
         .chain(heartbeat)
-        .chain(straico_stream)
-        .chain(done);
+        .chain(output)
+        .chain(done)
+        .map(move |frame| match frame {
+            Left(encoded) => Ok(encoded),
+            Right(chunk) => encoder.encode(chunk),
+        });
 
     Ok(HttpResponse::Ok()
         .content_type("text/event-stream")
@@ -141,22 +1202,39 @@ fn create_straico_streaming_response(
 
 async fn map_common_non_streaming_errors(
     response: reqwest::Response,
+    rate_limit_status: RateLimitStatus,
+    status_remap: Arc<StatusRemap>,
 ) -> Result<reqwest::Response, ProxyError> {
     let status = response.status();
 
     let provider_name = "Straico";
 
     // Map upstream 429 responses into a structured rate-limit error
-    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-        let retry_after = response
-            .headers()
-            .get("retry-after")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| s.parse::<u64>().ok());
+    let rate_limited = status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    let retry_after = retry_after(&response)
+        .filter(|_| rate_limited)
+        .map(|wait| wait.as_secs());
+
+    // Remapped statuses take precedence; the message still names the upstream status, and
+    // a remapped 429 keeps its `Retry-After`
+    if let Some(client_status) = status_remap.get(status.as_u16()) {
+        let body = response.text().await.unwrap_or_default();
+        let message = upstream_error_message(provider_name, status, &body);
+        return Err(match actix_web::http::StatusCode::from_u16(client_status) {
+            Ok(client_status) if rate_limited => ProxyError::RateLimited {
+                retry_after,
+                message,
+                status: client_status,
+            },
+            _ => ProxyError::UpstreamError(client_status, message),
+        });
+    }
 
+    if rate_limited {
         return Err(ProxyError::RateLimited {
             retry_after,
             message: format!("Rate limited by {} API", provider_name),
+            status: rate_limit_status.status_code(),
         });
     }
 
@@ -167,19 +1245,7 @@ async fn map_common_non_streaming_errors(
         || status == reqwest::StatusCode::SERVICE_UNAVAILABLE
     {
         let body = response.text().await.unwrap_or_default();
-
-        let base_message = format!(
-            "{} API returned {} {}",
-            provider_name,
-            status.as_u16(),
-            status.canonical_reason().unwrap_or(""),
-        );
-
-        let message = if body.is_empty() {
-            base_message
-        } else {
-            format!("{}: {}", base_message, body)
-        };
+        let message = upstream_error_message(provider_name, status, &body);
 
         let error = if status == reqwest::StatusCode::UNAUTHORIZED {
             ProxyError::Unauthorized(message)
@@ -197,22 +1263,1667 @@ async fn map_common_non_streaming_errors(
     // Catch-all for other 4xx/5xx errors
     if status.is_client_error() || status.is_server_error() {
         let body = response.text().await.unwrap_or_default();
-
-        let base_message = format!(
-            "{} API returned {} {}",
-            provider_name,
+        return Err(ProxyError::UpstreamError(
             status.as_u16(),
-            status.canonical_reason().unwrap_or(""),
+            upstream_error_message(provider_name, status, &body),
+        ));
+    }
+
+    Ok(response)
+}
+
+/// Describes an upstream error response, e.g. "Straico API returned 403 Forbidden: <body>".
+fn upstream_error_message(provider_name: &str, status: reqwest::StatusCode, body: &str) -> String {
+    let base_message = format!(
+        "{} API returned {} {}",
+        provider_name,
+        status.as_u16(),
+        status.canonical_reason().unwrap_or(""),
+    );
+
+    if body.is_empty() {
+        base_message
+    } else {
+        format!("{}: {}", base_message, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{scripted_upstream, sse_events, straico_json, MockUpstream};
+
+    fn provider_with_prefix(prefix: Option<&str>) -> StraicoProvider {
+        StraicoProvider {
+            strip_model_prefix: prefix.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    /// The JSON body built by `build_non_streaming_json`, without its statistics.
+    fn non_streaming_json(
+        raw_response: serde_json::Value,
+        options: &ResponseOptions,
+    ) -> Result<serde_json::Value, ProxyError> {
+        build_non_streaming_json(raw_response, options).map(|(json, _)| json)
+    }
+
+    fn straico_response(model: &str) -> StraicoChatResponse {
+        serde_json::from_value(raw_straico_response(model)).unwrap()
+    }
+
+    fn raw_straico_response(model: &str) -> serde_json::Value {
+        straico_json(model, "Hello")
+    }
+
+    #[test]
+    fn test_upstream_model_strips_prefix() {
+        let provider = provider_with_prefix(Some("straico/"));
+        assert_eq!(
+            provider.upstream_model("straico/anthropic/claude-3"),
+            "anthropic/claude-3"
         );
 
-        let message = if body.is_empty() {
-            base_message
-        } else {
-            format!("{}: {}", base_message, body)
+        // A prefix configured without the trailing slash behaves the same
+        let provider = provider_with_prefix(Some("straico"));
+        assert_eq!(
+            provider.upstream_model("straico/anthropic/claude-3"),
+            "anthropic/claude-3"
+        );
+    }
+
+    #[test]
+    fn test_upstream_model_without_prefix_is_unchanged() {
+        let provider = provider_with_prefix(Some("straico/"));
+        assert_eq!(provider.upstream_model("openai/gpt-4"), "openai/gpt-4");
+
+        // Only a whole leading segment is stripped
+        let provider = provider_with_prefix(Some("straico"));
+        assert_eq!(provider.upstream_model("straicomodel"), "straicomodel");
+        assert_eq!(
+            provider.upstream_model("straicomodel/gpt-4"),
+            "straicomodel/gpt-4"
+        );
+
+        let provider = provider_with_prefix(None);
+        assert_eq!(
+            provider.upstream_model("straico/anthropic/claude-3"),
+            "straico/anthropic/claude-3"
+        );
+    }
+
+    #[test]
+    fn test_provider_detection_after_prefix_strip() {
+        let provider = provider_with_prefix(Some("straico/"));
+        let model = provider.upstream_model("straico/anthropic/claude-3");
+        assert_eq!(ModelProvider::from(&*model), ModelProvider::Anthropic);
+    }
+
+    #[test]
+    fn test_response_reports_client_facing_model() {
+        let provider = provider_with_prefix(Some("straico/"));
+        let options = provider.response_options("straico/anthropic/claude-3");
+        assert_eq!(
+            options.client_model.as_deref(),
+            Some("straico/anthropic/claude-3")
+        );
+
+        let (response, _) =
+            convert_straico_response(straico_response("anthropic/claude-3"), &options).unwrap();
+        assert_eq!(response.model, "straico/anthropic/claude-3");
+    }
+
+    #[test]
+    fn test_response_keeps_upstream_model_when_not_stripped() {
+        let provider = provider_with_prefix(Some("straico/"));
+        let options = provider.response_options("openai/gpt-4");
+        assert!(options.client_model.is_none());
+
+        let (response, _) =
+            convert_straico_response(straico_response("openai/gpt-4-0613"), &options).unwrap();
+        assert_eq!(response.model, "openai/gpt-4-0613");
+    }
+
+    #[test]
+    fn test_debug_info_absent_by_default() {
+        let options = StraicoProvider::default().response_options("openai/gpt-4");
+        let json = non_streaming_json(raw_straico_response("openai/gpt-4"), &options).unwrap();
+        assert!(json.get("_debug").is_none());
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello");
+    }
+
+    #[test]
+    fn test_debug_info_includes_raw_response() {
+        let options = StraicoProvider {
+            include_debug_info: true,
+            ..Default::default()
+        }
+        .response_options("openai/gpt-4");
+        let raw = raw_straico_response("openai/gpt-4");
+        let json = non_streaming_json(raw.clone(), &options).unwrap();
+        assert_eq!(json["_debug"]["raw_response"], raw);
+        // Straico-only fields are visible in the raw copy but not in the OpenAI body
+        assert!(json.get("price").is_none());
+        assert_eq!(json["_debug"]["raw_response"]["price"]["total"], 0.0);
+    }
+
+    #[test]
+    fn test_debug_tool_arguments() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = "<tool_calls>\n[{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\", \"days\": 3}}]\n</tool_calls>".into();
+
+        let options = StraicoProvider {
+            debug_tool_arguments: true,
+            ..Default::default()
+        }
+        .response_options("openai/gpt-4");
+        let json = non_streaming_json(raw.clone(), &options).unwrap();
+        let tool_call = &json["choices"][0]["message"]["tool_calls"][0];
+        assert!(tool_call["function"]["arguments"].is_string());
+        assert_eq!(
+            tool_call["_debug"]["parsed_arguments"],
+            serde_json::json!({"city": "Paris", "days": 3})
+        );
+
+        let json = non_streaming_json(raw, &ResponseOptions::default()).unwrap();
+        assert!(json["choices"][0]["message"]["tool_calls"][0]
+            .get("_debug")
+            .is_none());
+    }
+
+    #[test]
+    fn test_empty_choices_is_an_error() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"] = serde_json::json!([]);
+        let err = non_streaming_json(raw, &ResponseOptions::default()).unwrap_err();
+        assert!(matches!(err, ProxyError::ServiceUnavailable(_)), "{err:?}");
+        assert!(err.to_string().contains("no choices"), "{err}");
+    }
+
+    #[test]
+    fn test_unparseable_response_maps_to_response_parse_error() {
+        let raw = serde_json::json!({"unexpected": true});
+        let err = non_streaming_json(raw.clone(), &ResponseOptions::default()).unwrap_err();
+        assert!(matches!(err, ProxyError::ResponseParse(value) if value == raw));
+    }
+
+    fn request_with_tools() -> OpenAiChatRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "What's the weather?"}],
+            "tools": [{
+                "type": "function",
+                "function": {"name": "get_weather", "parameters": {"type": "object"}}
+            }],
+            "tool_choice": "auto"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tools_injected_by_default() {
+        let request = StraicoProvider::default()
+            .build_chat_request(request_with_tools())
+            .unwrap();
+        assert_eq!(request.messages.len(), 2);
+        assert!(request.messages[1]
+            .content()
+            .to_string()
+            .contains("get_weather"));
+    }
+
+    #[test]
+    fn test_disable_tools_skips_tools_system_message() {
+        let provider = StraicoProvider {
+            disable_tools: true,
+            ..Default::default()
+        };
+        let request = provider.build_chat_request(request_with_tools()).unwrap();
+        assert_eq!(request.messages.len(), 1);
+        assert!(!request.messages[0]
+            .content()
+            .to_string()
+            .contains("<tools>"));
+    }
+
+    #[test]
+    fn test_disable_tools_skips_response_tool_parsing() {
+        let provider = StraicoProvider {
+            disable_tools: true,
+            ..Default::default()
         };
+        let content =
+            "<tool_calls>\n[{\"name\": \"get_weather\", \"arguments\": {}}]\n</tool_calls>";
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = content.into();
 
-        return Err(ProxyError::UpstreamError(status.as_u16(), message));
+        let json = non_streaming_json(raw, &provider.response_options("openai/gpt-4")).unwrap();
+        let message = &json["choices"][0]["message"];
+        assert_eq!(message["content"], content);
+        assert!(message.get("tool_calls").is_none());
+        assert_eq!(json["choices"][0]["finish_reason"], "stop");
     }
 
-    Ok(response)
+    #[test]
+    fn test_inject_current_date_adds_system_note() {
+        let provider = StraicoProvider {
+            inject_current_date: Some(CurrentDateZone::Utc),
+            ..Default::default()
+        };
+        let request = provider.build_chat_request(request_with_tools()).unwrap();
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+
+        assert_eq!(request.messages.len(), 3);
+        assert!(matches!(request.messages[0], ChatMessage::System { .. }));
+        assert!(request.messages[0].content().to_string().contains(&today));
+        // The tools instructions still come after the date note
+        assert!(request.messages[2]
+            .content()
+            .to_string()
+            .contains("<tools>"));
+    }
+
+    #[test]
+    fn test_system_override_header_becomes_system_message() {
+        use crate::headers::{RequestOverrides, SYSTEM_OVERRIDE_HEADER};
+
+        let http_request = actix_web::test::TestRequest::default()
+            .insert_header((SYSTEM_OVERRIDE_HEADER, "Answer in French."))
+            .to_http_request();
+        let overrides = RequestOverrides::from_headers(http_request.headers()).unwrap();
+        let provider = StraicoProvider {
+            system_override: overrides.system_override,
+            ..Default::default()
+        };
+        let mut request = request_with_tools();
+        system_prompt::prepend_system_message(&mut request, "You are helpful.".to_string());
+        let request = provider.build_chat_request(request).unwrap();
+
+        assert!(matches!(request.messages[0], ChatMessage::System { .. }));
+        assert_eq!(
+            request.messages[0].content().to_string(),
+            "Answer in French."
+        );
+        assert!(!request
+            .messages
+            .iter()
+            .any(|message| message.content().to_string() == "You are helpful."));
+    }
+
+    #[test]
+    fn test_current_date_not_injected_by_default() {
+        let request = StraicoProvider::default()
+            .build_chat_request(request_with_tools())
+            .unwrap();
+        assert!(!request.messages[0]
+            .content()
+            .to_string()
+            .contains("Current date"));
+    }
+
+    #[test]
+    fn test_zero_temperature_epsilon_per_provider() {
+        let provider = StraicoProvider {
+            zero_temperature_epsilon: Arc::new(vec!["anthropic".to_string()]),
+            ..Default::default()
+        };
+        let temperature = |model: &str| {
+            let mut request = request_with_tools();
+            request.chat_request.model = model.to_string();
+            request.chat_request.temperature = Some(0.0);
+            provider.build_chat_request(request).unwrap().temperature
+        };
+
+        let epsilon = temperature("anthropic/claude-3-5-sonnet").unwrap();
+        assert!(epsilon > 0.0 && epsilon < 0.1);
+        assert_eq!(temperature("openai/gpt-4"), Some(0.0));
+    }
+
+    #[test]
+    fn test_fallback_models_are_split_trimmed_and_capped() {
+        assert_eq!(fallback_models("openai/gpt-4"), ["openai/gpt-4"]);
+        assert_eq!(
+            fallback_models(" anthropic/claude-3 , openai/gpt-4,"),
+            ["anthropic/claude-3", "openai/gpt-4"]
+        );
+        assert_eq!(fallback_models("a, b, c, d, e"), ["a", "b", "c", "d"]);
+        assert_eq!(fallback_models(""), [""]);
+    }
+
+    #[test]
+    fn test_default_temperature_only_applies_when_unset() {
+        let provider = StraicoProvider {
+            default_temperature: Some(0.3),
+            ..Default::default()
+        };
+        let temperature = |requested: Option<f32>| {
+            let mut request = request_with_tools();
+            request.chat_request.temperature = requested;
+            provider.build_chat_request(request).unwrap().temperature
+        };
+
+        assert_eq!(temperature(None), Some(0.3));
+        assert_eq!(temperature(Some(0.0)), Some(0.0));
+        assert_eq!(temperature(Some(1.2)), Some(1.2));
+        assert_eq!(
+            StraicoProvider::default()
+                .build_chat_request(request_with_tools())
+                .unwrap()
+                .temperature,
+            None
+        );
+    }
+
+    #[test]
+    fn test_strip_leaked_tools_prompt_from_response() {
+        let provider = StraicoProvider {
+            strip_leaked_tools_prompt: true,
+            ..Default::default()
+        };
+        let request = provider.build_chat_request(request_with_tools()).unwrap();
+        let tools_prompt = request.messages[1].content().to_string();
+
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] =
+            format!("{tools_prompt}\nIt is sunny in Boston.").into();
+
+        let json = non_streaming_json(raw, &provider.response_options("openai/gpt-4")).unwrap();
+        let message = &json["choices"][0]["message"];
+        assert_eq!(message["content"], "It is sunny in Boston.");
+        // The example calls inside the leaked instructions must not become tool calls
+        assert!(message.get("tool_calls").is_none());
+    }
+
+    #[test]
+    fn test_strip_special_tokens_from_response() {
+        let provider = StraicoProvider {
+            strip_special_tokens: Some(Arc::new(vec!["<|im_end|>".to_string()])),
+            ..Default::default()
+        };
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = "It is sunny in Boston.<|im_end|>".into();
+
+        let json = non_streaming_json(raw, &provider.response_options("openai/gpt-4")).unwrap();
+        assert_eq!(
+            json["choices"][0]["message"]["content"],
+            "It is sunny in Boston."
+        );
+    }
+
+    #[test]
+    fn test_extract_reasoning_before_tool_calls() {
+        let provider = StraicoProvider {
+            thinking_tags: Some(Arc::new(reasoning::thinking_tags(&[]))),
+            ..Default::default()
+        };
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = concat!(
+            "<think>I could call <tool_calls>[{\"name\": \"get_weather\", ",
+            "\"arguments\": {}}]</tool_calls> but no need.</think>\n\nIt is sunny."
+        )
+        .into();
+
+        let json = non_streaming_json(raw, &provider.response_options("openai/gpt-4")).unwrap();
+        let message = &json["choices"][0]["message"];
+        assert_eq!(message["content"], "It is sunny.");
+        assert!(message["reasoning_content"]
+            .as_str()
+            .unwrap()
+            .ends_with("but no need."));
+        assert!(message.get("tool_calls").is_none());
+        assert_eq!(json["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[test]
+    fn test_trim_response() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = "\n\n  Line one\n\n  Line two \n\n".into();
+
+        let trimming = StraicoProvider {
+            trim_response: true,
+            ..Default::default()
+        };
+        let json =
+            non_streaming_json(raw.clone(), &trimming.response_options("openai/gpt-4")).unwrap();
+        assert_eq!(
+            json["choices"][0]["message"]["content"],
+            "Line one\n\n  Line two"
+        );
+
+        let json = non_streaming_json(
+            raw,
+            &StraicoProvider::default().response_options("openai/gpt-4"),
+        )
+        .unwrap();
+        assert_eq!(
+            json["choices"][0]["message"]["content"],
+            "\n\n  Line one\n\n  Line two \n\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_chat_path_override_hits_mock_server() {
+        use actix_web::{web, HttpResponse};
+
+        let upstream = MockUpstream::start(|cfg| {
+            cfg.route(
+                "/custom/chat",
+                web::post()
+                    .to(|| async { HttpResponse::Ok().json(raw_straico_response("openai/gpt-4")) }),
+            );
+        });
+
+        let client = StraicoClient::builder()
+            .base_url(upstream.base_url.clone())
+            .chat_path("/custom/chat")
+            .build()
+            .unwrap();
+        let provider = StraicoProvider {
+            client,
+            ..Default::default()
+        };
+
+        let response = provider
+            .send_request(request_with_tools())
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(response.url().path(), "/custom/chat");
+        let (json, _) = provider
+            .parse_non_streaming(response, provider.response_options("openai/gpt-4"))
+            .await
+            .unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello");
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_requests_go_through_upstream_proxy() {
+        use actix_web::{web, HttpRequest, HttpResponse};
+        use std::sync::Mutex;
+
+        // Plain-HTTP forward proxy: requests arrive with the absolute upstream URI
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let forward_proxy = MockUpstream::start(move |cfg| {
+            let recorded = recorded.clone();
+            cfg.default_service(web::to(move |req: HttpRequest| {
+                recorded.lock().unwrap().push(req.uri().to_string());
+                async { HttpResponse::Ok().json(raw_straico_response("openai/gpt-4")) }
+            }));
+        });
+
+        let provider = StraicoProvider {
+            client: StraicoClient::builder()
+                .base_url("http://straico.invalid")
+                .proxy(reqwest::Proxy::all(forward_proxy.base_url.clone()).unwrap())
+                .build()
+                .unwrap(),
+            ..Default::default()
+        };
+        let response = provider
+            .send_request(request_with_tools())
+            .unwrap()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["http://straico.invalid/v2/chat/completions".to_string()]
+        );
+
+        forward_proxy.stop().await;
+    }
+
+    #[test]
+    fn test_model_transforms_apply_to_matching_model_only() {
+        let path = std::env::temp_dir().join(format!(
+            "straico-proxy-provider-model-transforms-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"[{
+                "model": "openai/gpt-4",
+                "system_prompt": "Answer in French.",
+                "max_temperature": 0.5,
+                "tool_format": "mistral"
+            }]"#,
+        )
+        .unwrap();
+        let provider = StraicoProvider {
+            model_transforms: Arc::new(ModelTransforms::load(&path).unwrap()),
+            ..Default::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+        let build = |model: &str| {
+            let mut request = request_with_tools();
+            request.chat_request.model = model.to_string();
+            request.chat_request.temperature = Some(1.5);
+            provider.build_chat_request(request).unwrap()
+        };
+
+        let transformed = build("openai/gpt-4");
+        assert_eq!(transformed.temperature, Some(0.5));
+        assert_eq!(
+            transformed.messages[0].content().to_string(),
+            "Answer in French."
+        );
+        assert!(transformed.messages[2]
+            .content()
+            .to_string()
+            .contains("[TOOL_CALLS]"));
+        assert_eq!(
+            provider.response_options("openai/gpt-4").tool_format,
+            Some(ModelProvider::Mistral)
+        );
+
+        let untouched = build("openai/gpt-4o");
+        assert_eq!(untouched.temperature, Some(1.5));
+        assert_eq!(untouched.messages.len(), 2);
+        assert!(!untouched.messages[1]
+            .content()
+            .to_string()
+            .contains("[TOOL_CALLS]"));
+        assert_eq!(provider.response_options("openai/gpt-4o").tool_format, None);
+    }
+
+    #[test]
+    fn test_model_map_aliases_resolve_in_conversion() {
+        let path = std::env::temp_dir().join(format!(
+            "straico-proxy-provider-model-map-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{
+                "gpt-4": "openai/gpt-4o",
+                "claude": "anthropic/claude-3-5-sonnet",
+                "fast": "openai/gpt-4o-mini"
+            }"#,
+        )
+        .unwrap();
+        let provider = StraicoProvider {
+            strip_model_prefix: Some("straico/".to_string()),
+            model_map: Arc::new(ModelMap::load(&path).unwrap()),
+            ..Default::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        for (alias, target) in [
+            ("gpt-4", "openai/gpt-4o"),
+            ("claude", "anthropic/claude-3-5-sonnet"),
+            ("fast", "openai/gpt-4o-mini"),
+            ("straico/fast", "openai/gpt-4o-mini"),
+            ("openai/gpt-4", "openai/gpt-4"),
+        ] {
+            let mut request = request_with_tools();
+            request.chat_request.model = alias.to_string();
+            let request = provider.build_chat_request(request).unwrap();
+            assert_eq!(request.model, target, "alias {alias}");
+        }
+
+        // Clients see the alias they asked for
+        let options = provider.response_options("claude");
+        assert_eq!(options.client_model.as_deref(), Some("claude"));
+    }
+
+    #[test]
+    fn test_prompt_cache_key_is_accepted_but_not_forwarded() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "prompt_cache_key": "user-42"
+        }))
+        .unwrap();
+        assert_eq!(request.prompt_cache_key.as_deref(), Some("user-42"));
+
+        let upstream = serde_json::to_value(
+            StraicoProvider::default()
+                .build_chat_request(request)
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(upstream.get("prompt_cache_key").is_none());
+    }
+
+    #[test]
+    fn test_service_tier_round_trips() {
+        let mut request = request_with_tools();
+        request.service_tier = Some("auto".to_string());
+        let provider = StraicoProvider::default();
+        let options = provider.request_options(&request);
+
+        // Not forwarded upstream
+        let upstream = serde_json::to_value(provider.build_chat_request(request).unwrap()).unwrap();
+        assert!(upstream.get("service_tier").is_none());
+
+        let json = non_streaming_json(raw_straico_response("openai/gpt-4"), &options).unwrap();
+        assert_eq!(json["service_tier"], "auto");
+
+        let (response, _) =
+            convert_straico_response(straico_response("openai/gpt-4"), &options).unwrap();
+        let stream = CompletionStream::from(response);
+        assert_eq!(stream.service_tier.as_deref(), Some("auto"));
+
+        // Omitted when the client did not ask for a tier
+        let json = non_streaming_json(
+            raw_straico_response("openai/gpt-4"),
+            &provider.request_options(&request_with_tools()),
+        )
+        .unwrap();
+        assert!(json.get("service_tier").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_is_retried_after_retry_after() {
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = scripted_upstream(&[429, 200], Some("0"));
+        let provider = StraicoProvider {
+            client: upstream.client(),
+            max_retries: 2,
+            // `retry-after: 0` takes precedence over the backoff
+            retry_base_delay: Duration::from_secs(20),
+            ..Default::default()
+        };
+
+        let started = std::time::Instant::now();
+        let response = provider
+            .send_request(request_with_tools())
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_retries_back_off_exponentially() {
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = scripted_upstream(&[429, 503, 200], None);
+        let provider = StraicoProvider {
+            client: upstream.client(),
+            max_retries: 2,
+            retry_base_delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        let started = std::time::Instant::now();
+        let response = provider
+            .send_request(request_with_tools())
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        // 50ms before the first retry, 100ms before the second
+        assert!(started.elapsed() >= Duration::from_millis(150));
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_client_errors_are_not_retried() {
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = scripted_upstream(&[400, 200], None);
+        let provider = StraicoProvider {
+            client: upstream.client(),
+            max_retries: 2,
+            ..Default::default()
+        };
+
+        let response = provider
+            .send_request(request_with_tools())
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_remapped_upstream_status() {
+        use actix_web::ResponseError;
+
+        let forbidden = || {
+            reqwest::Response::from(
+                http::Response::builder()
+                    .status(403)
+                    .body("API key lacks access")
+                    .unwrap(),
+            )
+        };
+
+        let remap = Arc::new(StatusRemap::new([(403, 401)]));
+        let error = map_common_non_streaming_errors(forbidden(), RateLimitStatus::default(), remap)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.status_code(),
+            actix_web::http::StatusCode::UNAUTHORIZED
+        );
+        let message = error.error_body()["error"]["message"].to_string();
+        assert!(message.contains("403 Forbidden"), "{message}");
+        assert!(message.contains("API key lacks access"), "{message}");
+
+        // Without a remap the upstream status passes through
+        let error = map_common_non_streaming_errors(
+            forbidden(),
+            RateLimitStatus::default(),
+            Arc::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status_code(), actix_web::http::StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_remapped_rate_limit_keeps_retry_after() {
+        use actix_web::ResponseError;
+
+        let rate_limited = reqwest::Response::from(
+            http::Response::builder()
+                .status(429)
+                .header("retry-after", "12")
+                .body("slow down")
+                .unwrap(),
+        );
+        let remap = Arc::new(StatusRemap::new([(429, 503)]));
+        let error =
+            map_common_non_streaming_errors(rate_limited, RateLimitStatus::default(), remap)
+                .await
+                .unwrap_err();
+        let response = error.error_response();
+        assert_eq!(
+            response.status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get(actix_web::http::header::RETRY_AFTER)
+                .unwrap(),
+            "12"
+        );
+        let message = error.error_body()["error"]["message"].to_string();
+        assert!(message.contains("429 Too Many Requests"), "{message}");
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_error_payload_with_200() {
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(r#"{"success": false, "error": "Insufficient coins"}"#)
+            .unwrap();
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::ready(Ok(reqwest::Response::from(upstream))),
+            StreamOptions::default(),
+            ResponseOptions::default(),
+        )
+        .unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let error_chunk = sse_events(&body)
+            .into_iter()
+            .find(|chunk| chunk.get("error").is_some())
+            .expect("stream should contain an error chunk");
+
+        let message = error_chunk["error"]["message"].as_str().unwrap();
+        assert!(message.contains("Insufficient coins"), "{message}");
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[test]
+    fn test_non_streaming_error_payload_with_200() {
+        let raw = serde_json::json!({"error": {"message": "Model not available"}});
+        let err = non_streaming_json(raw, &ResponseOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Straico"));
+        assert!(matches!(
+            err,
+            ProxyError::Straico(StraicoError::Api(message)) if message.contains("Model not available")
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_stream_starts_with_retry_line() {
+        let provider = StraicoProvider {
+            sse_retry_ms: Some(5000),
+            ..Default::default()
+        };
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw_straico_response("openai/gpt-4").to_string())
+            .unwrap();
+        let response = provider
+            .create_streaming_response(
+                "openai/gpt-4",
+                future::ready(Ok(reqwest::Response::from(upstream))),
+                ResponseOptions::default(),
+            )
+            .unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("retry: 5000\n\ndata: "), "{body}");
+        assert_eq!(body.matches("retry:").count(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_fast_upstream_emits_no_heartbeats() {
+        async fn heartbeat_count(
+            upstream: impl Future<Output = Result<reqwest::Response, reqwest::Error>> + 'static,
+        ) -> usize {
+            let response = create_straico_streaming_response(
+                "openai/gpt-4",
+                upstream,
+                StreamOptions::default(),
+                ResponseOptions::default(),
+            )
+            .unwrap();
+            let body = actix_web::body::to_bytes(response.into_body())
+                .await
+                .unwrap();
+            sse_events(body)
+                .iter()
+                .filter(|chunk| chunk["id"] == "")
+                .count()
+        }
+        let upstream = || {
+            reqwest::Response::from(
+                http::Response::builder()
+                    .status(200)
+                    .body(raw_straico_response("openai/gpt-4").to_string())
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(heartbeat_count(future::ready(Ok(upstream()))).await, 0);
+
+        // Ready shortly after streaming starts, but well within the first interval
+        let delayed = tokio::time::sleep(Duration::from_millis(20)).map(move |_| Ok(upstream()));
+        assert_eq!(heartbeat_count(delayed).await, 0);
+    }
+
+    async fn first_stream_chunks(dialect: SseDialect) -> Vec<serde_json::Value> {
+        let provider = StraicoProvider {
+            sse_dialect: dialect,
+            ..Default::default()
+        };
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw_straico_response("openai/gpt-4").to_string())
+            .unwrap();
+        let response = provider
+            .create_streaming_response(
+                "openai/gpt-4",
+                future::ready(Ok(reqwest::Response::from(upstream))),
+                ResponseOptions::default(),
+            )
+            .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        sse_events(body).into_iter().take(2).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_stream_starts_without_bom() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = "\u{feff}Hello".into();
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw.to_string())
+            .unwrap();
+        let response = StraicoProvider::default()
+            .create_streaming_response(
+                "openai/gpt-4",
+                future::ready(Ok(reqwest::Response::from(upstream))),
+                ResponseOptions::default(),
+            )
+            .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+
+        assert!(body.starts_with(b"data: "));
+        // No event may start with a BOM either
+        for event in String::from_utf8(body.to_vec())
+            .unwrap()
+            .split_terminator("\n\n")
+        {
+            assert!(event.starts_with("data: "), "unexpected event: {event:?}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_object_names_do_not_follow_the_upstream() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["object"] = serde_json::json!("chat.completion.chunk");
+
+        let json = non_streaming_json(raw.clone(), &ResponseOptions::default()).unwrap();
+        assert_eq!(json["object"], "chat.completion");
+
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw.to_string())
+            .unwrap();
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::ready(Ok(reqwest::Response::from(upstream))),
+            StreamOptions::default(),
+            ResponseOptions::default(),
+        )
+        .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let chunks = sse_events(body);
+        assert_eq!(chunks.len(), 2);
+        for chunk in chunks {
+            assert_eq!(chunk["object"], "chat.completion.chunk");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_openai_dialect_first_chunk() {
+        let chunks = first_stream_chunks(SseDialect::OpenAi).await;
+        assert_eq!(chunks[0]["object"], "chat.completion.chunk");
+        assert_eq!(chunks[0]["model"], "openai/gpt-4");
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert!(chunks[0].get("prompt_filter_results").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_azure_dialect_first_chunk() {
+        let chunks = first_stream_chunks(SseDialect::Azure).await;
+        assert_eq!(chunks[0]["choices"], serde_json::json!([]));
+        assert_eq!(chunks[0]["model"], "");
+        let filter = &chunks[0]["prompt_filter_results"][0];
+        assert_eq!(filter["prompt_index"], 0);
+        assert_eq!(filter["content_filter_results"]["hate"]["filtered"], false);
+
+        // The regular role chunk follows
+        assert_eq!(chunks[1]["model"], "openai/gpt-4");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["role"], "assistant");
+    }
+
+    #[actix_web::test]
+    async fn test_azure_dialect_content_filter_results() {
+        let chunks = first_stream_chunks(SseDialect::Azure).await;
+        let filter = &chunks[1]["choices"][0]["content_filter_results"];
+        assert_eq!(filter["violence"]["severity"], "safe");
+
+        let chunks = first_stream_chunks(SseDialect::OpenAi).await;
+        assert!(chunks[0]["choices"][0]
+            .get("content_filter_results")
+            .is_none());
+
+        let azure = StraicoProvider {
+            sse_dialect: SseDialect::Azure,
+            ..Default::default()
+        };
+        let json = non_streaming_json(
+            raw_straico_response("openai/gpt-4"),
+            &azure.response_options("openai/gpt-4"),
+        )
+        .unwrap();
+        assert_eq!(json["prompt_filter_results"][0]["prompt_index"], 0);
+        assert_eq!(
+            json["choices"][0]["content_filter_results"]["hate"]["filtered"],
+            false
+        );
+
+        let json = non_streaming_json(
+            raw_straico_response("openai/gpt-4"),
+            &StraicoProvider::default().response_options("openai/gpt-4"),
+        )
+        .unwrap();
+        assert!(json.get("prompt_filter_results").is_none());
+        assert!(json["choices"][0].get("content_filter_results").is_none());
+    }
+
+    #[test]
+    fn test_empty_tool_call_arguments_are_normalized_in_chunks() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] =
+            "<tool_calls>\n[{\"name\": \"list_files\", \"arguments\": \"\"}]\n</tool_calls>".into();
+        let (response, _) = parse_straico_response(&raw, &ResponseOptions::default())
+            .and_then(|response| convert_straico_response(response, &ResponseOptions::default()))
+            .unwrap();
+
+        let chunk = serde_json::to_value(CompletionStream::from(response)).unwrap();
+        let function = &chunk["choices"][0]["delta"]["tool_calls"][0]["function"];
+        assert_eq!(function["name"], "list_files");
+        assert_eq!(function["arguments"], "{}");
+    }
+
+    #[test]
+    fn test_structured_tool_calls_take_precedence_over_content() {
+        let mut raw = raw_straico_response("anthropic/claude-3-5-sonnet");
+        raw["choices"][0]["message"] = serde_json::json!({
+            "role": "assistant",
+            "content": "<tool_calls>\n[{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Rome\"}}]\n</tool_calls>",
+            "tool_calls": [{
+                "id": "call_native",
+                "type": "function",
+                "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+            }]
+        });
+        let options = ResponseOptions::default();
+
+        let json = non_streaming_json(raw.clone(), &options).unwrap();
+        let tool_calls = json["choices"][0]["message"]["tool_calls"]
+            .as_array()
+            .unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0]["id"], "call_native");
+        assert_eq!(json["choices"][0]["finish_reason"], "tool_calls");
+
+        let (response, _) = parse_straico_response(&raw, &options)
+            .and_then(|response| convert_straico_response(response, &options))
+            .unwrap();
+        let chunk = serde_json::to_value(CompletionStream::from(response)).unwrap();
+        let tool_calls = chunk["choices"][0]["delta"]["tool_calls"]
+            .as_array()
+            .unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0]["id"], "call_native");
+    }
+
+    #[actix_web::test]
+    async fn test_heartbeat_cap_ends_stream() {
+        let stream_options = StreamOptions {
+            heartbeat_interval: Duration::from_millis(10),
+            max_heartbeats: Some(3),
+            ..Default::default()
+        };
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::pending::<Result<reqwest::Response, reqwest::Error>>(),
+            stream_options,
+            ResponseOptions::default(),
+        )
+        .unwrap();
+
+        let body = tokio::time::timeout(
+            Duration::from_secs(5),
+            actix_web::body::to_bytes(response.into_body()),
+        )
+        .await
+        .expect("stream should terminate")
+        .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let chunks = sse_events(&body);
+
+        let heartbeats = chunks.iter().filter(|chunk| chunk["id"] == "").count();
+        assert_eq!(heartbeats, 3);
+        let error = chunks.last().unwrap();
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("after 3 heartbeats"));
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[actix_web::test]
+    async fn test_heartbeat_interval_sets_cadence() {
+        assert_eq!(
+            StraicoProvider::default()
+                .stream_options()
+                .heartbeat_interval,
+            HEARTBEAT_INTERVAL
+        );
+
+        let provider = StraicoProvider {
+            heartbeat_interval: Some(Duration::from_millis(50)),
+            max_heartbeats: Some(4),
+            ..Default::default()
+        };
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::pending::<Result<reqwest::Response, reqwest::Error>>(),
+            provider.stream_options(),
+            ResponseOptions::default(),
+        )
+        .unwrap();
+
+        let started = std::time::Instant::now();
+        let mut body = std::pin::pin!(response.into_body());
+        let mut heartbeat_times = Vec::new();
+        while let Some(chunk) =
+            std::future::poll_fn(|cx| actix_web::body::MessageBody::poll_next(body.as_mut(), cx))
+                .await
+        {
+            let chunk = String::from_utf8(chunk.unwrap().to_vec()).unwrap();
+            if chunk.contains(r#""id":"""#) {
+                heartbeat_times.push(started.elapsed());
+            }
+        }
+
+        assert_eq!(heartbeat_times.len(), 4);
+        for (count, elapsed) in (1..).zip(&heartbeat_times) {
+            assert!(
+                *elapsed >= Duration::from_millis(50) * count,
+                "{heartbeat_times:?}"
+            );
+        }
+        assert!(
+            heartbeat_times[3] < Duration::from_secs(2),
+            "{heartbeat_times:?}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_chunk_indices_increase_across_stream() {
+        let stream_options = StreamOptions {
+            heartbeat_interval: Duration::from_millis(10),
+            chunk_index: true,
+            ..Default::default()
+        };
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw_straico_response("openai/gpt-4").to_string())
+            .unwrap();
+        let delayed_upstream = async move {
+            tokio::time::sleep(Duration::from_millis(45)).await;
+            Ok(reqwest::Response::from(upstream))
+        };
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            delayed_upstream,
+            stream_options,
+            ResponseOptions::default(),
+        )
+        .unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let chunks = sse_events(&body);
+
+        // Initial chunk, at least one heartbeat, then the content chunk
+        assert!(chunks.len() >= 3);
+        assert_eq!(
+            chunks.last().unwrap()["choices"][0]["delta"]["content"],
+            "Hello"
+        );
+        let indices: Vec<u64> = chunks
+            .iter()
+            .map(|chunk| chunk["chunk_index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(indices, (0..chunks.len() as u64).collect::<Vec<_>>());
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[actix_web::test]
+    async fn test_streamed_choices_keep_their_indices() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"] = serde_json::json!([
+            {"index": 0, "message": {"role": "assistant", "content": "First"}, "finish_reason": "stop"},
+            {"index": 1, "message": {"role": "assistant", "content": "Second"}, "finish_reason": "length"}
+        ]);
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw.to_string())
+            .unwrap();
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::ready(Ok(reqwest::Response::from(upstream))),
+            StreamOptions::default(),
+            ResponseOptions::default(),
+        )
+        .unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let content_chunks: Vec<serde_json::Value> = sse_events(&body)
+            .into_iter()
+            .filter(|chunk| !chunk["choices"][0]["finish_reason"].is_null())
+            .collect();
+
+        assert_eq!(content_chunks.len(), 2, "{body}");
+        let choices: Vec<_> = content_chunks
+            .iter()
+            .map(|chunk| {
+                assert_eq!(chunk["choices"].as_array().unwrap().len(), 1);
+                let choice = &chunk["choices"][0];
+                (
+                    choice["index"].as_u64().unwrap(),
+                    choice["delta"]["content"].as_str().unwrap(),
+                    choice["finish_reason"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(choices, [(0, "First", "stop"), (1, "Second", "length")]);
+        assert_eq!(
+            content_chunks[1]["choices"][0]["delta"]["role"],
+            "assistant"
+        );
+        // Usage is only sent when asked for with `stream_options.include_usage`
+        assert!(!body.contains("usage"), "{body}");
+    }
+
+    fn request_with_stop(stop: serde_json::Value) -> OpenAiChatRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Count to five"}],
+            "stop": stop
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_content_is_cut_at_single_stop_sequence() {
+        let options =
+            StraicoProvider::default().request_options(&request_with_stop(serde_json::json!("4")));
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = serde_json::json!("1, 2, 3, 4, 5");
+        raw["choices"][0]["finish_reason"] = serde_json::json!("length");
+
+        let json = non_streaming_json(raw, &options).unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "1, 2, 3, ");
+        assert_eq!(json["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[actix_web::test]
+    async fn test_content_is_cut_at_earliest_of_stop_sequences() {
+        let options = StraicoProvider::default()
+            .request_options(&request_with_stop(serde_json::json!(["5", "", "3"])));
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] = serde_json::json!("1, 2, 3, 4, 5");
+
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw.to_string())
+            .unwrap();
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::ready(Ok(reqwest::Response::from(upstream))),
+            StreamOptions::default(),
+            options,
+        )
+        .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let content: String = sse_events(body)
+            .into_iter()
+            .filter_map(|chunk| {
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(str::to_string)
+            })
+            .collect();
+        assert_eq!(content, "1, 2, ");
+    }
+
+    #[test]
+    fn test_content_without_stop_sequence_is_unchanged() {
+        let options = StraicoProvider::default()
+            .request_options(&request_with_stop(serde_json::json!(["STOP"])));
+        let json = non_streaming_json(raw_straico_response("openai/gpt-4"), &options).unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello");
+    }
+
+    #[actix_web::test]
+    async fn test_stream_ends_with_usage_chunk_when_requested() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "stream": true,
+            "stream_options": {"include_usage": true}
+        }))
+        .unwrap();
+        let options = StraicoProvider::default().request_options(&request);
+        assert!(options.include_usage);
+
+        let upstream = http::Response::builder()
+            .status(200)
+            .body(raw_straico_response("openai/gpt-4").to_string())
+            .unwrap();
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            future::ready(Ok(reqwest::Response::from(upstream))),
+            StreamOptions::default(),
+            options,
+        )
+        .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        let chunks = sse_events(body);
+
+        // Initial chunk, content chunk, usage chunk
+        assert_eq!(chunks.len(), 3, "{body}");
+        let (usage_chunk, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|chunk| chunk.get("usage").is_none()));
+        assert_eq!(usage_chunk["choices"], serde_json::json!([]));
+        assert_eq!(usage_chunk["usage"]["total_tokens"], 2);
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[test]
+    fn test_coerce_arg_keys_to_schema() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Weather in Paris?"}],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {"location": {"type": "string"}}
+                    }
+                }
+            }]
+        }))
+        .unwrap();
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"][0]["message"]["content"] =
+            "<tool_calls>\n[{\"name\": \"get_weather\", \"arguments\": {\"Location\": \"Paris\"}}]\n</tool_calls>".into();
+        let arguments = |provider: StraicoProvider| {
+            let json =
+                non_streaming_json(raw.clone(), &provider.request_options(&request)).unwrap();
+            let arguments = json["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            serde_json::from_str::<serde_json::Value>(&arguments).unwrap()
+        };
+
+        let enabled = StraicoProvider {
+            coerce_arg_keys: true,
+            ..Default::default()
+        };
+        assert_eq!(arguments(enabled), serde_json::json!({"location": "Paris"}));
+        assert_eq!(
+            arguments(StraicoProvider::default()),
+            serde_json::json!({"Location": "Paris"})
+        );
+    }
+
+    #[test]
+    fn test_select_best_only_when_client_wants_one_choice() {
+        let mut raw = raw_straico_response("openai/gpt-4");
+        raw["choices"] = serde_json::json!([
+            {"index": 0, "message": {"role": "assistant", "content": "Short"}, "finish_reason": "stop"},
+            {"index": 1, "message": {"role": "assistant", "content": "A longer answer"}, "finish_reason": "stop"}
+        ]);
+        let provider = StraicoProvider {
+            select_best: Some(SelectBest::Longest),
+            ..Default::default()
+        };
+        let choices = |n: Option<u32>| {
+            let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "n": n
+            }))
+            .unwrap();
+            let json =
+                non_streaming_json(raw.clone(), &provider.request_options(&request)).unwrap();
+            json["choices"].as_array().unwrap().clone()
+        };
+
+        let selected = choices(None);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0]["message"]["content"], "A longer answer");
+        assert_eq!(choices(Some(1)).len(), 1);
+        assert_eq!(choices(Some(2)).len(), 2);
+    }
+
+    #[test]
+    fn test_string_typed_xml_arguments_are_kept_raw() {
+        let request: OpenAiChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "z-ai/glm-4.5",
+            "messages": [{"role": "user", "content": "Look up 02139"}],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "lookup",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {"code": {"type": "string"}, "limit": {"type": "integer"}}
+                    }
+                }
+            }]
+        }))
+        .unwrap();
+        let mut raw = raw_straico_response("z-ai/glm-4.5");
+        raw["choices"][0]["message"]["content"] = "<tool_call>lookup\n<arg_key>code</arg_key>\n<arg_value>1.50</arg_value>\n<arg_key>limit</arg_key>\n<arg_value>3</arg_value>\n</tool_call>".into();
+        let provider = StraicoProvider::default();
+        let json = non_streaming_json(raw, &provider.request_options(&request)).unwrap();
+        let arguments = json["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"]
+            .as_str()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(arguments).unwrap(),
+            serde_json::json!({"code": "1.50", "limit": 3})
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_only_first_chunk_carries_role() {
+        let stream_options = StreamOptions {
+            heartbeat_interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        // Slow enough for a few heartbeats before the content chunk
+        let upstream = tokio::time::sleep(Duration::from_millis(50)).map(|_| {
+            Ok(reqwest::Response::from(
+                http::Response::builder()
+                    .status(200)
+                    .body(raw_straico_response("openai/gpt-4").to_string())
+                    .unwrap(),
+            ))
+        });
+        let response = create_straico_streaming_response(
+            "openai/gpt-4",
+            upstream,
+            stream_options,
+            ResponseOptions::default(),
+        )
+        .unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let chunks = sse_events(body);
+        assert!(chunks.len() >= 3, "{chunks:?}");
+
+        let with_role: Vec<usize> = chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| chunk["choices"][0]["delta"].get("role").is_some())
+            .map(|(position, _)| position)
+            .collect();
+        assert_eq!(with_role, vec![0]);
+        assert_eq!(
+            chunks.last().unwrap()["choices"][0]["delta"]["content"],
+            "Hello"
+        );
+    }
+
+    #[test]
+    fn test_max_message_length() {
+        let provider = StraicoProvider {
+            max_message_length: Some(5),
+            ..Default::default()
+        };
+        let request = |last: &str| -> OpenAiChatRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [
+                    {"role": "system", "content": "Short"},
+                    {"role": "user", "content": last}
+                ]
+            }))
+            .unwrap()
+        };
+
+        // Exactly at the limit (counted in characters, not bytes)
+        assert!(provider.build_chat_request(request("héllo")).is_ok());
+
+        let err = provider.build_chat_request(request("hello!")).unwrap_err();
+        match err {
+            ProxyError::InvalidParameter { parameter, reason } => {
+                assert_eq!(parameter, "messages[1].content");
+                assert!(reason.contains("6 characters"), "{reason}");
+            }
+            other => panic!("Unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_require_user_message() {
+        let userless = || -> OpenAiChatRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [
+                    {"role": "system", "content": "You are helpful."},
+                    {"role": "assistant", "content": "Let me continue."}
+                ]
+            }))
+            .unwrap()
+        };
+
+        let strict = StraicoProvider {
+            require_user_message: true,
+            ..Default::default()
+        };
+        let err = strict.build_chat_request(userless()).unwrap_err();
+        assert!(matches!(err, ProxyError::BadRequest(_)), "{err:?}");
+        assert!(strict.build_chat_request(request_with_tools()).is_ok());
+
+        // Permissive by default, for agents continuing from assistant state
+        assert!(StraicoProvider::default()
+            .build_chat_request(userless())
+            .is_ok());
+    }
+
+    fn agent_history(rounds: usize) -> OpenAiChatRequest {
+        let mut messages = vec![serde_json::json!({"role": "user", "content": "List the files"})];
+        for round in 0..rounds {
+            messages.push(serde_json::json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": format!("call_{round}"),
+                    "type": "function",
+                    "function": {"name": "list_files", "arguments": "{}"}
+                }]
+            }));
+            messages.push(serde_json::json!({
+                "role": "tool",
+                "tool_call_id": format!("call_{round}"),
+                "content": "a.txt"
+            }));
+        }
+        messages.push(serde_json::json!({"role": "assistant", "content": "Done."}));
+        serde_json::from_value(serde_json::json!({"model": "openai/gpt-4", "messages": messages}))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_count_tool_turns() {
+        assert_eq!(count_tool_turns(&agent_history(0)), 0);
+        assert_eq!(count_tool_turns(&agent_history(3)), 3);
+    }
+
+    #[test]
+    fn test_max_tool_turns() {
+        let provider = StraicoProvider {
+            max_tool_turns: Some(2),
+            ..Default::default()
+        };
+        assert!(provider.build_chat_request(agent_history(2)).is_ok());
+
+        let err = provider.build_chat_request(agent_history(3)).unwrap_err();
+        assert!(matches!(err, ProxyError::BadRequest(_)), "{err:?}");
+        assert!(err.to_string().contains("3 tool-call turns"), "{err}");
+
+        // Unlimited by default
+        assert!(StraicoProvider::default()
+            .build_chat_request(agent_history(10))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_partial_content_from_truncated_body() {
+        let body = br#"{"id": "x", "choices": [{"index": 0, "message": {"role": "assistant", "content": "Line one\nLine \"two\" and caf\u00e9 and more tex"#;
+        assert_eq!(
+            partial_content(body).as_deref(),
+            Some("Line one\nLine \"two\" and café and more tex")
+        );
+        assert_eq!(partial_content(br#"{"id": "x", "choi"#), None);
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_timeout_delivers_partial_content() {
+        use actix_web::{web, HttpResponse};
+
+        // Sends the start of a response body, then stalls
+        let upstream = MockUpstream::start(|cfg| {
+            cfg.default_service(web::to(|| async {
+                let head = Bytes::from_static(
+                    br#"{"id": "chatcmpl-1", "choices": [{"index": 0, "message": {"role": "assistant", "content": "The answer is"#,
+                );
+                let body = stream::once(future::ready(Ok::<_, actix_web::Error>(head)))
+                    .chain(stream::pending());
+                HttpResponse::Ok()
+                    .content_type("application/json")
+                    .streaming(body)
+            }));
+        });
+
+        let provider = StraicoProvider {
+            client: StraicoClient::builder()
+                .base_url(upstream.base_url.clone())
+                .timeout(std::time::Duration::from_millis(500))
+                .build()
+                .unwrap(),
+            ..Default::default()
+        };
+        let response = provider
+            .create_streaming_response(
+                "openai/gpt-4",
+                provider.send_request(request_with_tools()).unwrap(),
+                ResponseOptions::default(),
+            )
+            .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let last_chunk = sse_events(&body).pop().unwrap();
+        let choice = &last_chunk["choices"][0];
+        assert_eq!(choice["finish_reason"], "timeout");
+        let content = choice["delta"]["content"].as_str().unwrap();
+        assert!(content.starts_with("The answer is"), "{content}");
+        assert!(content.contains("truncated"), "{content}");
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+
+        upstream.stop().await;
+    }
 }
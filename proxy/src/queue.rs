@@ -0,0 +1,121 @@
+//! Concurrency limit for chat completions with a bounded waiting queue
+//! (`--max-concurrent-requests`, `--queue-depth`, `--queue-timeout-ms`).
+//!
+//! Requests beyond the limit wait for a free slot instead of failing right away, which
+//! smooths out bursts. They are rejected with `503` only when the queue is already full
+//! or no slot frees up within the maximum wait.
+
+use crate::error::ProxyError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A slot for one in-flight request, released when dropped.
+pub type RequestPermit = Arc<OwnedSemaphorePermit>;
+
+#[derive(Debug)]
+pub struct RequestQueue {
+    permits: Arc<Semaphore>,
+    /// Maximum number of requests waiting for a permit
+    depth: usize,
+    max_wait: Duration,
+    waiting: AtomicUsize,
+}
+
+/// Counts a request as waiting for as long as it is alive.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RequestQueue {
+    pub fn new(max_concurrent: usize, depth: usize, max_wait: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            depth,
+            max_wait,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits for a free slot, or fails with `ServiceUnavailable` if the queue is full or
+    /// the wait exceeds the configured maximum.
+    pub async fn acquire(&self) -> Result<RequestPermit, ProxyError> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(Arc::new(permit));
+        }
+
+        let queued = self
+            .waiting
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |waiting| {
+                (waiting < self.depth).then_some(waiting + 1)
+            });
+        if queued.is_err() {
+            return Err(ProxyError::ServiceUnavailable(
+                "too many concurrent requests and the request queue is full".to_string(),
+            ));
+        }
+        let _waiting = Waiting(&self.waiting);
+
+        match tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Arc::new(permit)),
+            Ok(Err(_)) => Err(ProxyError::ServiceUnavailable(
+                "request queue is closed".to_string(),
+            )),
+            Err(_) => Err(ProxyError::ServiceUnavailable(format!(
+                "no request slot became available within {} ms",
+                self.max_wait.as_millis()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[actix_web::test]
+    async fn test_queued_request_gets_freed_permit() {
+        let queue = Arc::new(RequestQueue::new(1, 1, Duration::from_secs(5)));
+        let held = queue.acquire().await.unwrap();
+
+        let waiter = actix_web::rt::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire().await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        drop(held);
+        waiter.await.unwrap().unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_full_queue_rejects_promptly() {
+        let queue = Arc::new(RequestQueue::new(1, 1, Duration::from_secs(5)));
+        let _held = queue.acquire().await.unwrap();
+        let _waiter = actix_web::rt::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire().await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let started = Instant::now();
+        let error = queue.acquire().await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(error, ProxyError::ServiceUnavailable(_)));
+    }
+
+    #[actix_web::test]
+    async fn test_wait_beyond_max_is_rejected() {
+        let queue = RequestQueue::new(1, 1, Duration::from_millis(20));
+        let _held = queue.acquire().await.unwrap();
+        let error = queue.acquire().await.unwrap_err();
+        assert!(error.to_string().contains("within 20 ms"));
+    }
+}
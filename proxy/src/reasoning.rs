@@ -0,0 +1,101 @@
+//! Moving a model's leading reasoning block out of the response content and into
+//! `reasoning_content` (`--extract-reasoning`).
+//!
+//! Reasoning models think out loud before answering, wrapped in model-specific
+//! delimiters. Left in the content, the reasoning is shown to users as part of the answer
+//! and may contain example tool calls that get extracted as real ones.
+
+/// Opening delimiters recognized when `--extract-reasoning` is given without a list.
+pub const DEFAULT_THINKING_TAGS: &[&str] = &["<think>", "<thinking>", "<reasoning>", "◁think▷"];
+
+/// A pair of reasoning delimiters, e.g. `<think>` and `</think>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThinkingTag {
+    open: String,
+    close: String,
+}
+
+impl ThinkingTag {
+    /// Builds the pair for an opening delimiter; the closing one has a `/` after its first
+    /// character (`<think>` → `</think>`, `◁think▷` → `◁/think▷`).
+    pub fn new(open: &str) -> Self {
+        let mut chars = open.chars();
+        let first = chars.next().map(String::from).unwrap_or_default();
+        Self {
+            open: open.to_string(),
+            close: format!("{first}/{}", chars.as_str()),
+        }
+    }
+}
+
+/// Builds the recognized tags from `--extract-reasoning` values, using the defaults for an
+/// empty list.
+pub fn thinking_tags(opening: &[String]) -> Vec<ThinkingTag> {
+    let opening: Vec<&str> = opening
+        .iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    if opening.is_empty() {
+        DEFAULT_THINKING_TAGS
+            .iter()
+            .map(|tag| ThinkingTag::new(tag))
+            .collect()
+    } else {
+        opening.into_iter().map(ThinkingTag::new).collect()
+    }
+}
+
+/// Splits a reasoning block at the start of `content` into `(reasoning, answer)`, both
+/// trimmed.
+///
+/// Content that does not start with one of `tags`, or whose reasoning block is never
+/// closed (e.g. cut off by `max_tokens`), is left alone.
+pub fn extract_reasoning(content: &str, tags: &[ThinkingTag]) -> Option<(String, String)> {
+    let content = content.trim_start();
+    tags.iter().find_map(|tag| {
+        let inner = content.strip_prefix(&tag.open)?;
+        let (reasoning, answer) = inner.split_once(&tag.close)?;
+        Some((reasoning.trim().to_string(), answer.trim().to_string()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_delimiters_are_recognized() {
+        let tags = thinking_tags(&[]);
+        for open in DEFAULT_THINKING_TAGS {
+            let close = &ThinkingTag::new(open).close;
+            let content = format!("\n{open}\nThe user greets me.\n{close}\n\nHello!");
+            assert_eq!(
+                extract_reasoning(&content, &tags),
+                Some(("The user greets me.".to_string(), "Hello!".to_string())),
+                "{open}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_custom_delimiter() {
+        let tags = thinking_tags(&["[THINK]".to_string()]);
+        assert_eq!(
+            extract_reasoning("[THINK]Easy.[/THINK]42", &tags),
+            Some(("Easy.".to_string(), "42".to_string()))
+        );
+        // Custom tags replace the defaults
+        assert_eq!(extract_reasoning("<think>Easy.</think>42", &tags), None);
+    }
+
+    #[test]
+    fn test_unclosed_or_inner_reasoning_is_left_alone() {
+        let tags = thinking_tags(&[]);
+        assert_eq!(extract_reasoning("<think>Cut off mid-thou", &tags), None);
+        assert_eq!(
+            extract_reasoning("Answer first <think>x</think>", &tags),
+            None
+        );
+    }
+}
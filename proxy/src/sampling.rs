@@ -0,0 +1,46 @@
+//! Per-request sampling for `--debug-sample-rate`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Decides whether the request with `request_id` is sampled at `rate` (0.0 to 1.0).
+///
+/// The decision is derived from a hash of the request id, so every check made for the same
+/// request agrees.
+pub fn is_sampled(request_id: &str, rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    if rate >= 1.0 {
+        return true;
+    }
+    let mut hasher = DefaultHasher::new();
+    request_id.hash(&mut hasher);
+    (hasher.finish() as f64 / u64::MAX as f64) < rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_ids() -> impl Iterator<Item = String> {
+        (0..1000).map(|i| format!("req-{i}"))
+    }
+
+    #[test]
+    fn test_full_rate_samples_everything() {
+        assert!(request_ids().all(|id| is_sampled(&id, 1.0)));
+    }
+
+    #[test]
+    fn test_zero_rate_samples_nothing() {
+        assert!(!request_ids().any(|id| is_sampled(&id, 0.0)));
+    }
+
+    #[test]
+    fn test_partial_rate_is_consistent_per_request() {
+        let sampled = request_ids().filter(|id| is_sampled(id, 0.25)).count();
+        assert!((150..350).contains(&sampled), "{sampled}");
+        assert!(request_ids().all(|id| is_sampled(&id, 0.25) == is_sampled(&id, 0.25)));
+    }
+}
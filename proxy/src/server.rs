@@ -1,38 +1,212 @@
-use crate::streaming::HeartbeatChar;
-use crate::{error::ProxyError, provider::StraicoProvider, types::OpenAiChatRequest};
-use actix_web::{get, post, web, HttpResponse};
+use crate::cache::ResponseCache;
+use crate::choice_selection::SelectBest;
+use crate::latency::LatencyTracker;
+use crate::model_map::ModelMap;
+use crate::model_transforms::ModelTransforms;
+use crate::models::{ModelCatalog, OpenAiModelList};
+use crate::persistence::{ConversationStore, PendingConversation};
+use crate::queue::{RequestPermit, RequestQueue};
+use crate::reasoning::ThinkingTag;
+use crate::streaming::{HeartbeatChar, SseChunk, SseDialect};
+use crate::system_prompt::{CurrentDateZone, SystemOverrideMode, SystemPromptMode};
+use crate::token_limits::MaxTokensPolicy;
+use crate::tool_format::ToolFormatTracker;
+use crate::tool_policy::{ToolPolicy, ToolsOverLimit};
+use crate::transport::HttpTransport;
+use crate::{
+    error::{ProxyError, RateLimitStatus, StatusRemap},
+    headers::{self, AcceptMismatch, RequestOverrides},
+    moderation::{self, ModerationBackend},
+    preprocess,
+    provider::{self, ResponseStats, StraicoProvider},
+    sampling,
+    text_completion::{self, TextCompletion, TextCompletionRequest},
+    types::OpenAiChatRequest,
+};
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse, ResponseError};
+use bytes::Bytes;
 use futures::TryStreamExt;
-use log::warn;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
 use straico_client::client::StraicoClient;
+use straico_client::endpoints::chat::ModelProvider;
+use straico_client::endpoints::models::ModelsResponse;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct AppState {
     pub client: StraicoClient,
     pub key: String,
     pub heartbeat_char: HeartbeatChar,
+    /// Time between streaming heartbeats (`--heartbeat-interval`; `None`: 3 seconds)
+    pub heartbeat_interval: Option<Duration>,
+    pub max_heartbeats: Option<u32>,
+    /// Upstream timeout of non-streaming requests (`--request-timeout`); the client's read
+    /// timeout bounds streaming ones
+    pub request_timeout: Option<Duration>,
+    /// Upstream retries for 429, 5xx and connection errors (`--max-retries`), unless
+    /// overridden per request
+    pub max_retries: u32,
+    /// Backoff before the first retry (`--retry-base-delay-ms`)
+    pub retry_base_delay: Duration,
+    pub strip_model_prefix: Option<String>,
+    pub include_debug_info: bool,
+    /// Attach parsed tool call arguments under `_debug` (`--debug-tool-arguments`)
+    pub debug_tool_arguments: bool,
+    pub lenient_parsing: bool,
+    pub disable_tools: bool,
+    pub inject_current_date: Option<CurrentDateZone>,
+    /// Default system prompt (`--system-prompt`)
+    pub system_prompt: Option<String>,
+    pub system_prompt_mode: SystemPromptMode,
+    /// How `x-straico-system-override` combines with the request's system prompt
+    pub system_override_mode: SystemOverrideMode,
+    pub strip_leaked_tools_prompt: bool,
+    /// Chat template tokens to remove from response content (`--strip-special-tokens`)
+    pub strip_special_tokens: Option<Arc<Vec<String>>>,
+    /// Reasoning delimiters moved into `reasoning_content` (`--extract-reasoning`)
+    pub thinking_tags: Option<Arc<Vec<ThinkingTag>>>,
+    /// Trim leading/trailing whitespace from response content (`--trim-response`)
+    pub trim_response: bool,
+    /// Pick one choice from multi-choice upstream responses (`--select-best`)
+    pub select_best: Option<SelectBest>,
+    pub coerce_arg_keys: bool,
+    pub model_map: Arc<ModelMap>,
+    /// Per-model request transformations (`--model-transforms`)
+    pub model_transforms: Arc<ModelTransforms>,
+    pub sse_retry_ms: Option<u64>,
+    pub sse_dialect: SseDialect,
+    /// Number streamed chunks with a non-standard `chunk_index` field
+    pub sse_chunk_index: bool,
+    /// Report pre-flight errors of streaming requests as SSE events instead of JSON
+    pub errors_as_sse: bool,
+    /// Handling of streaming requests from clients that don't accept SSE (`--accept-mismatch`)
+    pub accept_mismatch: AcceptMismatch,
+    pub tool_policy: Arc<ToolPolicy>,
+    pub max_message_length: Option<usize>,
+    /// Limit on the injected tool definitions (`--max-tools-prompt-length`)
+    pub max_tools_prompt_length: Option<usize>,
+    pub tools_prompt_over_limit: ToolsOverLimit,
+    /// Reject requests without a user or tool message (`--require-user-message`)
+    pub require_user_message: bool,
+    /// Reject requests with more tool-call turns than this (`--max-tool-turns`)
+    pub max_tool_turns: Option<usize>,
+    /// Handling of `max_tokens` above the model's known limit (`--max-tokens-over-limit`)
+    pub max_tokens_policy: MaxTokensPolicy,
+    /// Providers whose `temperature: 0` is replaced by a tiny epsilon
+    pub zero_temperature_epsilon: Arc<Vec<String>>,
+    /// Temperature applied to requests that don't set one (`--default-temperature`)
+    pub default_temperature: Option<f32>,
+    /// Status returned for upstream rate limits (`--rate-limit-status`)
+    pub rate_limit_status: RateLimitStatus,
+    /// Client-facing statuses for upstream error statuses (`--remap-status`)
+    pub status_remap: Arc<StatusRemap>,
+    /// Attach diagnostic context under `_debug` in error responses (development only)
+    pub verbose_errors: bool,
+    /// Fraction of requests (0.0 to 1.0) whose raw traffic is logged
+    pub debug_sample_rate: f64,
+    /// Conversation history database (`--persist-db`)
+    pub persist_db: Option<Arc<ConversationStore>>,
+    /// Checks user content before it is forwarded (`--moderation-url`)
+    pub moderation: Option<Arc<dyn ModerationBackend>>,
+    /// Bearer token required by the `/admin` endpoints, when set
+    pub admin_token: Option<String>,
+    /// Per-model latency of recent requests, reported by `GET /admin/stats`
+    pub latency: Arc<LatencyTracker>,
+    /// Per-model tool call format fallbacks, reported by `GET /admin/stats`
+    pub tool_formats: Arc<ToolFormatTracker>,
+    /// Last model list fetched for `GET /v1/models`, served when the upstream fails
+    pub model_catalog: Arc<ModelCatalog>,
+    /// Make `GET /readyz` fetch the upstream model list (`--readiness-check-upstream`)
+    pub readiness_check_upstream: bool,
+    /// Responses of deterministic requests (`--cache-size`)
+    pub response_cache: Option<Arc<ResponseCache>>,
+    /// Concurrency limit and waiting queue for chat completions
+    pub request_queue: Option<Arc<RequestQueue>>,
+    /// Sends chat requests upstream; `client` is used when unset
+    pub transport: Option<Arc<dyn HttpTransport>>,
 }
 
-#[get("/v1/models")]
-pub async fn models_handler(data: web::Data<AppState>) -> Result<HttpResponse, ProxyError> {
-    let client = data.client.clone();
-    let straico_response = client.models().bearer_auth(&data.key).send().await?;
+/// Registers the proxy's routes; `main` mounts them under the `--route-prefix` scope.
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(openai_chat_completion)
+        .service(model_handler)
+        .service(models_handler)
+        .service(text_completion_handler)
+        .service(admin_reload_handler)
+        .service(admin_stats_handler);
+}
 
-    let status_code = actix_web::http::StatusCode::from_u16(straico_response.status().as_u16())
-        .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+/// Upper bound on the upstream call made by `GET /readyz`, so probes fail rather than hang.
+const READINESS_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
 
-    let mut response_builder = HttpResponse::build(status_code);
+/// Liveness probe: answers as long as the server is running.
+#[get("/health")]
+pub async fn health_check() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
 
-    // Copy headers from the Straico response to the new response
-    for (name, value) in straico_response.headers().iter() {
-        if let Ok(value_str) = value.to_str() {
-            response_builder.insert_header((name.as_str(), value_str));
-        } else {
-            warn!("Skipping header with non-ASCII value: {:?}", name);
+/// Readiness probe: 503 without an API key or, with `--readiness-check-upstream`, when the
+/// upstream model list cannot be fetched.
+#[get("/readyz")]
+pub async fn readiness_check(data: web::Data<AppState>) -> HttpResponse {
+    let unavailable = |reason: String| {
+        warn!("Readiness check failed: {}", reason);
+        HttpResponse::ServiceUnavailable()
+            .json(serde_json::json!({ "status": "unavailable", "reason": reason }))
+    };
+
+    if data.key.is_empty() {
+        return unavailable("Straico API key is not set".to_string());
+    }
+    if data.readiness_check_upstream {
+        let probe = data.client.clone().models().bearer_auth(&data.key).send();
+        match tokio::time::timeout(READINESS_UPSTREAM_TIMEOUT, probe).await {
+            Ok(Ok(response)) if response.status().is_success() => {}
+            Ok(Ok(response)) => {
+                return unavailable(format!("upstream returned {}", response.status()))
+            }
+            Ok(Err(e)) => return unavailable(format!("upstream unreachable: {e}")),
+            Err(_) => return unavailable("upstream timed out".to_string()),
         }
     }
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ready" }))
+}
 
-    let body_stream = straico_response.bytes_stream().map_err(ProxyError::from);
-    Ok(response_builder.streaming(body_stream))
+/// Lists the upstream models in OpenAI's `GET /v1/models` shape.
+///
+/// When Straico cannot be reached or answers with an error, the last list fetched (or a
+/// static list) is returned instead, so clients that load models on startup still work.
+#[get("/v1/models")]
+pub async fn models_handler(data: web::Data<AppState>) -> Result<HttpResponse, ProxyError> {
+    let fetched = async {
+        data.client
+            .clone()
+            .models()
+            .bearer_auth(&data.key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ModelsResponse>()
+            .await
+    }
+    .await;
+
+    let list = match fetched {
+        Ok(response) => {
+            let list = OpenAiModelList::from(response);
+            data.model_catalog.update(list.clone());
+            list
+        }
+        Err(e) => {
+            warn!(
+                "Failed to fetch models from Straico, serving the fallback list: {}",
+                e
+            );
+            data.model_catalog.fallback()
+        }
+    };
+    Ok(HttpResponse::Ok().json(list))
 }
 
 /// Proxies a request for a single model to Straico's `GET /v2/models/{model_id}` endpoint.
@@ -70,42 +244,1687 @@ pub async fn model_handler(
     Ok(response_builder.streaming(body_stream))
 }
 
-/// Generic handler for chat completions that works with any provider implementing ChatProvider.
-/// The compiler will monomorphize this function for each concrete provider type, generating
-/// specialized code with zero abstraction overhead.
+/// Rejects admin requests without `Authorization: Bearer <token>`. Without `--admin-token`
+/// the admin endpoints are disabled and every request is rejected.
+fn check_admin_token(http_req: &HttpRequest, data: &AppState) -> Result<(), ProxyError> {
+    let Some(expected) = &data.admin_token else {
+        return Err(ProxyError::Forbidden(
+            "admin endpoints are disabled; set --admin-token to enable them".to_string(),
+        ));
+    };
+    let provided = http_req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided.is_some_and(|provided| constant_time_eq(provided.as_bytes(), expected.as_bytes())) {
+        Ok(())
+    } else {
+        Err(ProxyError::Unauthorized(
+            "missing or invalid admin token".to_string(),
+        ))
+    }
+}
+
+/// Compares `a` and `b` in time independent of where they differ, so response timing does
+/// not reveal how much of a guessed token is correct. Only the length can leak.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Re-reads the `--model-map` file so alias changes apply without a restart.
+#[post("/admin/reload")]
+pub async fn admin_reload_handler(
+    http_req: HttpRequest,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ProxyError> {
+    check_admin_token(&http_req, &data)?;
+    let aliases = data.model_map.reload()?;
+    info!("Reloaded model map with {} aliases", aliases);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "model_aliases": aliases })))
+}
+
+/// Reports the rolling average and p95 latency of recent requests per upstream model,
+/// tool format fallbacks and response cache lookups. Cache hits never reach the upstream,
+/// so they are counted under `cache` only, not in the per-model latency.
+#[get("/admin/stats")]
+pub async fn admin_stats_handler(
+    http_req: HttpRequest,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ProxyError> {
+    check_admin_token(&http_req, &data)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "models": data.latency.stats(),
+        "tool_format_fallbacks": data.tool_formats.stats(),
+        "cache": data.response_cache.as_ref().map(|cache| cache.stats())
+    })))
+}
+
+/// Sends a chat request upstream with `provider`: streamed requests get an SSE response for
+/// their first model, non-streaming ones go through the fallback models (see
+/// [`complete_with_fallbacks`]) and are stored in `cache` under its key when given.
 async fn handle_chat_completion_async(
     provider: &StraicoProvider,
-    openai_request: OpenAiChatRequest,
+    mut openai_request: OpenAiChatRequest,
+    cache: Option<(&ResponseCache, String)>,
 ) -> Result<HttpResponse, ProxyError> {
+    if let Some(tier) = &openai_request.service_tier {
+        info!(
+            "Request for {} with service_tier {}",
+            openai_request.chat_request.model, tier
+        );
+    }
     if openai_request.stream {
-        let model = openai_request.chat_request.model.clone();
+        // Once streaming starts there is nothing to fall back from, so only the first of
+        // several comma-separated models is used
+        let model = provider::fallback_models(&openai_request.chat_request.model).remove(0);
+        openai_request.chat_request.model = model.clone();
+        let options = provider.request_options(&openai_request);
         let response_future = provider.send_request(openai_request)?;
-        provider.create_streaming_response(&model, response_future)
+        provider.create_streaming_response(&model, response_future, options)
     } else {
-        let response_future = provider.send_request(openai_request)?;
-        let response = response_future.await?;
-        let json = provider.parse_non_streaming(response).await?;
+        let json = complete_with_fallbacks(provider, openai_request).await?;
+        if let Some((cache, key)) = cache {
+            cache.insert(key, json.clone());
+        }
         Ok(HttpResponse::Ok().json(json))
     }
 }
 
+/// Sends a non-streaming request to each of its comma-separated models in turn, until one
+/// answers with a non-empty completion. The last model's result is returned as-is.
+///
+/// Only the returned response counts towards the latency and tool format statistics and
+/// is recorded in the conversation history.
+async fn complete_with_fallbacks(
+    provider: &StraicoProvider,
+    openai_request: OpenAiChatRequest,
+) -> Result<serde_json::Value, ProxyError> {
+    let (json, stats) = first_non_empty_completion(provider, openai_request).await?;
+    stats.record();
+    // Detached: the response does not wait for the row to be written
+    if let Some(conversation) = provider.conversation.clone() {
+        conversation.complete(json.clone());
+    }
+    Ok(json)
+}
+
+async fn first_non_empty_completion(
+    provider: &StraicoProvider,
+    openai_request: OpenAiChatRequest,
+) -> Result<(serde_json::Value, ResponseStats), ProxyError> {
+    let models = provider::fallback_models(&openai_request.chat_request.model);
+    let (last, fallbacks) = models.split_last().expect("at least one model");
+    for model in fallbacks {
+        let mut request = openai_request.clone();
+        request.chat_request.model = model.clone();
+        match complete(provider, request).await {
+            Ok((json, stats)) if !provider::is_empty_completion(&json) => return Ok((json, stats)),
+            Ok(_) => warn!(
+                "{} returned an empty completion; trying the next model",
+                model
+            ),
+            Err(e) => warn!("{} failed ({}); trying the next model", model, e),
+        }
+    }
+
+    let mut request = openai_request;
+    request.chat_request.model = last.clone();
+    complete(provider, request).await
+}
+
+/// Sends a non-streaming request upstream and converts the response.
+async fn complete(
+    provider: &StraicoProvider,
+    openai_request: OpenAiChatRequest,
+) -> Result<(serde_json::Value, ResponseStats), ProxyError> {
+    let options = provider.request_options(&openai_request);
+    let response = provider.send_request(openai_request)?.await?;
+    provider.parse_non_streaming(response, options).await
+}
+
+/// Serves the legacy `POST /v1/completions` endpoint by sending each prompt upstream as a
+/// chat request with a single user message.
+#[post("/v1/completions")]
+pub async fn text_completion_handler(
+    http_req: HttpRequest,
+    req: web::Json<TextCompletionRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ProxyError> {
+    let request = req.into_inner();
+    let request_id = headers::request_id(http_req.headers());
+    let mut prepared = Vec::new();
+    for body in request.chat_bodies()? {
+        prepared.push(prepare_chat_request(&http_req, &request_id, body, &data).await?);
+    }
+    let stream_options = prepared[0].0.stream_options();
+
+    let completion = async move {
+        let mut responses = Vec::with_capacity(prepared.len());
+        for (mut provider, chat_request) in prepared {
+            provider.permit = acquire_permit(&data).await?;
+            responses.push(complete_with_fallbacks(&provider, chat_request).await?);
+        }
+        Ok(TextCompletion::from_chat_responses(
+            &request.model,
+            &responses,
+        ))
+    };
+
+    if request.stream {
+        text_completion::streaming_response(completion, stream_options)
+    } else {
+        Ok(HttpResponse::Ok().json(completion.await?))
+    }
+}
+
 #[post("/v1/chat/completions")]
 pub async fn openai_chat_completion(
-    req: web::Json<OpenAiChatRequest>,
+    http_req: HttpRequest,
+    req: web::Json<serde_json::Value>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, ProxyError> {
-    let openai_request = req.into_inner();
+    let body = req.into_inner();
+    // Checked on the raw body, since the error may be that it does not deserialize
+    let sse_errors = data.errors_as_sse
+        && preprocess::requests_stream(&body, data.lenient_parsing)
+        && !downgrades_stream(&http_req, data.accept_mismatch);
+    let request_id = headers::request_id(http_req.headers());
+    let provider = data.verbose_errors.then(|| {
+        let model = body.get("model").and_then(serde_json::Value::as_str);
+        ModelProvider::from(model.unwrap_or_default())
+    });
+
+    chat_completion(&http_req, &request_id, body, &data)
+        .await
+        .or_else(|error| {
+            let debug =
+                provider.map(|provider| error.debug_context(&request_id, &format!("{provider:?}")));
+            if sse_errors {
+                sse_error_response(error, debug)
+            } else if let Some(debug) = debug {
+                let mut body = error.error_body();
+                body["_debug"] = debug;
+                Ok(HttpResponse::build(error.status_code()).json(body))
+            } else {
+                Err(error)
+            }
+        })
+}
+
+/// Whether a streaming request must be answered with plain JSON, because the client's
+/// `Accept` header excludes SSE and `--accept-mismatch json` is set.
+fn downgrades_stream(http_req: &HttpRequest, accept_mismatch: AcceptMismatch) -> bool {
+    accept_mismatch == AcceptMismatch::Json && !headers::accepts_event_stream(http_req.headers())
+}
+
+/// Frames an error raised before streaming started as an SSE error chunk followed by `[DONE]`.
+fn sse_error_response(
+    error: ProxyError,
+    debug: Option<serde_json::Value>,
+) -> Result<HttpResponse, ProxyError> {
+    let status = error.status_code();
+    let mut error_chunk = error.to_streaming_chunk();
+    if let Some(debug) = debug {
+        error_chunk["_debug"] = debug;
+    }
+    let error_chunk: Bytes = SseChunk::from(error_chunk).try_into()?;
+    let done: Bytes = SseChunk::from("[DONE]".to_string()).try_into()?;
+    Ok(HttpResponse::build(status)
+        .content_type("text/event-stream")
+        .body([error_chunk, done].concat()))
+}
+
+async fn chat_completion(
+    http_req: &HttpRequest,
+    request_id: &str,
+    body: serde_json::Value,
+    data: &AppState,
+) -> Result<HttpResponse, ProxyError> {
+    let (mut provider, openai_request) =
+        prepare_chat_request(http_req, request_id, body, data).await?;
+    let tool_turns = provider::count_tool_turns(&openai_request);
+    let cache = data.response_cache.as_deref().and_then(|cache| {
+        // A request that fails to build is not cached; sending it reports the error
+        let upstream = provider.build_chat_request(openai_request.clone()).ok()?;
+        ResponseCache::key(&openai_request, &upstream, provider.default_temperature)
+            .map(|key| (cache, key))
+    });
+    let mut response = match cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+        Some(json) => {
+            info!(
+                "[{}] Cache hit for {}; not sending upstream",
+                request_id, openai_request.chat_request.model
+            );
+            // Hits are stored in the conversation history like any answered request
+            if let Some(conversation) = provider.conversation.clone() {
+                conversation.complete(json.clone());
+            }
+            HttpResponse::Ok().json(json)
+        }
+        None => {
+            provider.permit = acquire_permit(data).await?;
+            handle_chat_completion_async(&provider, openai_request, cache).await?
+        }
+    };
+    response.headers_mut().insert(
+        header::HeaderName::from_static(headers::TOOL_TURNS_HEADER),
+        header::HeaderValue::from(tool_turns),
+    );
+    Ok(response)
+}
+
+/// Validates and checks a raw chat request body, and builds the provider that will send it
+/// upstream with this server's settings.
+///
+/// The provider holds no concurrency slot yet; see [`acquire_permit`].
+async fn prepare_chat_request(
+    http_req: &HttpRequest,
+    request_id: &str,
+    mut body: serde_json::Value,
+    data: &AppState,
+) -> Result<(StraicoProvider, OpenAiChatRequest), ProxyError> {
+    let overrides = RequestOverrides::from_headers(http_req.headers())?;
+    let sampled = sampling::is_sampled(request_id, data.debug_sample_rate);
+    if sampled {
+        info!("[{}] Sampled request body: {}", request_id, body);
+    }
 
     let AppState {
         ref client,
         ref key,
         ref heartbeat_char,
-    } = &*data.into_inner();
+        ref heartbeat_interval,
+        ref max_heartbeats,
+        ref request_timeout,
+        ref max_retries,
+        ref retry_base_delay,
+        ref strip_model_prefix,
+        ref include_debug_info,
+        ref debug_tool_arguments,
+        ref lenient_parsing,
+        ref disable_tools,
+        ref inject_current_date,
+        ref system_prompt,
+        ref system_prompt_mode,
+        ref system_override_mode,
+        ref strip_leaked_tools_prompt,
+        ref strip_special_tokens,
+        ref thinking_tags,
+        ref trim_response,
+        ref select_best,
+        ref coerce_arg_keys,
+        ref model_map,
+        ref model_transforms,
+        ref sse_retry_ms,
+        ref sse_dialect,
+        ref sse_chunk_index,
+        errors_as_sse: _,
+        ref accept_mismatch,
+        ref tool_policy,
+        ref max_message_length,
+        ref max_tools_prompt_length,
+        ref tools_prompt_over_limit,
+        ref require_user_message,
+        ref max_tool_turns,
+        ref max_tokens_policy,
+        ref zero_temperature_epsilon,
+        ref default_temperature,
+        ref rate_limit_status,
+        ref status_remap,
+        verbose_errors: _,
+        debug_sample_rate: _,
+        ref persist_db,
+        ref moderation,
+        admin_token: _,
+        ref latency,
+        ref tool_formats,
+        model_catalog: _,
+        readiness_check_upstream: _,
+        response_cache: _,
+        request_queue: _,
+        ref transport,
+    } = data;
 
+    preprocess::coerce_scalar_content(&mut body, *lenient_parsing)?;
+    preprocess::coerce_string_stream(&mut body, *lenient_parsing)?;
+    preprocess::reject_audio_content(&body)?;
+    let conversation = persist_db
+        .as_ref()
+        .map(|store| PendingConversation::new(store.clone(), request_id, body.clone()));
+    let mut openai_request: OpenAiChatRequest = serde_json::from_value(body)?;
+    if openai_request.stream && downgrades_stream(http_req, *accept_mismatch) {
+        info!(
+            "[{}] Client does not accept text/event-stream; answering without streaming",
+            request_id
+        );
+        openai_request.stream = false;
+    }
+    if let Some(backend) = moderation {
+        moderation::check_request(backend.as_ref(), &openai_request).await?;
+    }
     let provider = StraicoProvider {
         client: client.clone(),
         key: key.clone(),
         heartbeat_char: *heartbeat_char,
+        heartbeat_interval: *heartbeat_interval,
+        strip_model_prefix: strip_model_prefix.clone(),
+        include_debug_info: *include_debug_info,
+        debug_tool_arguments: *debug_tool_arguments,
+        disable_tools: *disable_tools,
+        inject_current_date: *inject_current_date,
+        system_prompt: system_prompt.clone(),
+        system_prompt_mode: *system_prompt_mode,
+        system_override: overrides.system_override,
+        system_override_mode: *system_override_mode,
+        strip_leaked_tools_prompt: *strip_leaked_tools_prompt,
+        strip_special_tokens: strip_special_tokens.clone(),
+        thinking_tags: thinking_tags.clone(),
+        trim_response: *trim_response,
+        select_best: *select_best,
+        coerce_arg_keys: *coerce_arg_keys,
+        model_map: model_map.clone(),
+        model_transforms: model_transforms.clone(),
+        max_retries: overrides.max_retries.unwrap_or(*max_retries),
+        retry_base_delay: *retry_base_delay,
+        // A stream has no total timeout, so it is not cut off once the upstream answers
+        request_timeout: overrides
+            .timeout
+            .or_else(|| request_timeout.filter(|_| !openai_request.stream)),
+        sse_retry_ms: *sse_retry_ms,
+        sse_dialect: *sse_dialect,
+        sse_chunk_index: *sse_chunk_index,
+        max_heartbeats: *max_heartbeats,
+        tool_policy: tool_policy.clone(),
+        max_message_length: *max_message_length,
+        max_tools_prompt_length: *max_tools_prompt_length,
+        tools_prompt_over_limit: *tools_prompt_over_limit,
+        require_user_message: *require_user_message,
+        max_tool_turns: *max_tool_turns,
+        max_tokens_policy: *max_tokens_policy,
+        zero_temperature_epsilon: zero_temperature_epsilon.clone(),
+        default_temperature: *default_temperature,
+        rate_limit_status: *rate_limit_status,
+        status_remap: status_remap.clone(),
+        sampled_request_id: sampled.then(|| request_id.to_string()),
+        conversation,
+        latency: latency.clone(),
+        tool_formats: tool_formats.clone(),
+        permit: None,
+        transport: transport.clone(),
     };
-    handle_chat_completion_async(&provider, openai_request).await
+    Ok((provider, openai_request))
+}
+
+/// Waits for a concurrency slot when `--max-concurrent-requests` is set.
+///
+/// Taken only once a request is about to go upstream, so cache hits never queue.
+async fn acquire_permit(data: &AppState) -> Result<Option<RequestPermit>, ProxyError> {
+    match &data.request_queue {
+        Some(queue) => Ok(Some(queue.acquire().await?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{sse_events, straico_json, MockUpstream};
+    use actix_web::{http::StatusCode, test, App};
+
+    async fn post_chat(state: AppState, body: serde_json::Value) -> (StatusCode, String, String) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = test::read_body(response).await;
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn invalid_streaming_request() -> serde_json::Value {
+        serde_json::json!({
+            "model": "openai/gpt-4",
+            "stream": true,
+            "messages": [{"role": "user", "content": 42}]
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_error_as_sse() {
+        let state = AppState {
+            errors_as_sse: true,
+            ..Default::default()
+        };
+        let (status, content_type, body) = post_chat(state, invalid_streaming_request()).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(content_type, "text/event-stream");
+        let events: Vec<&str> = body.split_terminator("\n\n").collect();
+        assert_eq!(events.len(), 2, "{body}");
+        let error: serde_json::Value =
+            serde_json::from_str(events[0].strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(error["error"]["code"], "invalid_parameter");
+        assert_eq!(events[1], "data: [DONE]");
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_error_as_sse_for_lenient_string_stream() {
+        let state = AppState {
+            errors_as_sse: true,
+            lenient_parsing: true,
+            ..Default::default()
+        };
+        let body = serde_json::json!({
+            "model": "openai/gpt-4",
+            "stream": "true",
+            "temperature": 3.5,
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+        let (status, content_type, body) = post_chat(state, body).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(content_type, "text/event-stream");
+        assert!(body.ends_with("data: [DONE]\n\n"), "{body}");
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_error_as_json_by_default() {
+        let (status, content_type, body) =
+            post_chat(AppState::default(), invalid_streaming_request()).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(content_type, "application/json");
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["error"]["code"], "invalid_parameter");
+    }
+
+    #[actix_web::test]
+    async fn test_temperature_out_of_range_names_param() {
+        let body = serde_json::json!({
+            "model": "openai/gpt-4",
+            "temperature": 3.5,
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+        let (status, _, body) = post_chat(AppState::default(), body).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["error"]["code"], "invalid_parameter");
+        assert_eq!(error["error"]["param"], "temperature");
+    }
+
+    #[actix_web::test]
+    async fn test_denied_tool_returns_403() {
+        let state = AppState {
+            tool_policy: Arc::new(ToolPolicy::new(None, vec!["execute_shell".to_string()])),
+            ..Default::default()
+        };
+        let body = serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Delete everything"}],
+            "tools": [{
+                "type": "function",
+                "function": {"name": "execute_shell", "parameters": {"type": "object"}}
+            }]
+        });
+        let (status, _, body) = post_chat(state, body).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("execute_shell"));
+    }
+
+    #[actix_web::test]
+    async fn test_verbose_errors_include_source_chain_and_request_id() {
+        // Nothing listens on port 9 (discard), so the upstream connection fails
+        let state = AppState {
+            client: StraicoClient::builder()
+                .base_url("http://127.0.0.1:9")
+                .build()
+                .unwrap(),
+            verbose_errors: true,
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((headers::REQUEST_ID_HEADER, "req-test-123"))
+            .set_json(serde_json::json!({
+                "model": "anthropic/claude-3",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(response.status().is_server_error());
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+        let debug = &body["_debug"];
+        assert_eq!(debug["request_id"], "req-test-123");
+        assert_eq!(debug["provider"], "Anthropic");
+        let chain = debug["source_chain"].as_array().unwrap();
+        assert!(chain.len() >= 2, "{chain:?}");
+        assert_eq!(chain[0], "Error from HTTP client");
+        assert!(body["error"]["message"].is_string());
+    }
+
+    struct FlagKeyword(&'static str);
+
+    #[async_trait::async_trait]
+    impl ModerationBackend for FlagKeyword {
+        async fn moderate(
+            &self,
+            input: &[String],
+        ) -> Result<moderation::ModerationVerdict, ProxyError> {
+            let flagged = input.iter().any(|content| content.contains(self.0));
+            Ok(moderation::ModerationVerdict {
+                flagged,
+                categories: flagged
+                    .then(|| "violence".to_string())
+                    .into_iter()
+                    .collect(),
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_flagged_content_is_blocked() {
+        let state = AppState {
+            // Unreachable: a request that gets past moderation fails with a network error
+            client: StraicoClient::builder()
+                .base_url("http://127.0.0.1:9")
+                .build()
+                .unwrap(),
+            moderation: Some(Arc::new(FlagKeyword("forbidden topic"))),
+            ..Default::default()
+        };
+        let (status, _, body) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [
+                    {"role": "system", "content": "Be helpful"},
+                    {"role": "user", "content": "Tell me about the forbidden topic"}
+                ]
+            }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"]["code"], "content_filter");
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("violence"));
+    }
+
+    #[actix_web::test]
+    async fn test_errors_have_no_debug_by_default() {
+        let (_, _, body) = post_chat(AppState::default(), invalid_streaming_request()).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(body.get("_debug").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_routes_mounted_under_prefix() {
+        let upstream = MockUpstream::start(|cfg| {
+            cfg.route(
+                "/v2/chat/completions",
+                web::post().to(|| async {
+                    HttpResponse::Ok().json(straico_json("openai/gpt-4", "Hello"))
+                }),
+            );
+        });
+
+        let state = AppState {
+            client: upstream.client(),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(web::scope("/api").configure(configure_routes))
+                .default_service(web::to(HttpResponse::NotFound)),
+        )
+        .await;
+        let body = serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/chat/completions")
+            .set_json(&body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(&body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_timeout_header_limits_upstream_wait() {
+        use crate::headers::TIMEOUT_HEADER;
+        use std::time::Duration;
+
+        // Upstream takes 300ms to answer
+        let upstream = MockUpstream::start(|cfg| {
+            cfg.route(
+                "/v2/chat/completions",
+                web::post().to(|| async {
+                    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+                    HttpResponse::Ok().json(straico_json("openai/gpt-4", "Hello"))
+                }),
+            );
+        });
+
+        let state = AppState {
+            client: upstream.client(),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let body = serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((TIMEOUT_HEADER, "0.05"))
+            .set_json(&body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((TIMEOUT_HEADER, "5"))
+            .set_json(&body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        upstream.stop().await;
+    }
+
+    fn mock_chat_body(content: &str) -> String {
+        straico_json("openai/gpt-4", content).to_string()
+    }
+
+    #[actix_web::test]
+    async fn test_mock_transport_drives_full_cycle() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Hello from the mock"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .set_json(serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            "Hello from the mock"
+        );
+
+        let sent = transport.requests();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].model, "openai/gpt-4");
+        assert_eq!(sent[0].messages.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_extra_body_is_forwarded_upstream() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Hi"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let (status, _, _) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "extra_body": {"web_search": true, "model": "openai/gpt-3.5-turbo"}
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let sent = serde_json::to_value(&transport.requests()[0]).unwrap();
+        assert_eq!(sent["web_search"], true);
+        assert_eq!(sent["model"], "openai/gpt-4");
+        assert_eq!(sent["messages"].as_array().unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_repeated_deterministic_request_is_served_from_cache() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport
+            .push_response(200, mock_chat_body("Cached answer"))
+            .push_response(200, mock_chat_body("Sampled answer"))
+            .push_response(200, mock_chat_body("Sampled again"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            response_cache: Some(Arc::new(ResponseCache::new(8, Duration::from_secs(60)))),
+            ..Default::default()
+        };
+        let deterministic = serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "temperature": 0
+        });
+
+        for _ in 0..2 {
+            let (status, _, body) = post_chat(state.clone(), deterministic.clone()).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(body.contains("Cached answer"), "{body}");
+        }
+        assert_eq!(transport.requests().len(), 1);
+        assert_eq!(
+            state.response_cache.as_ref().unwrap().stats(),
+            crate::cache::CacheStats { hits: 1, misses: 1 }
+        );
+
+        // Sampled answers are never cached
+        let mut sampled = deterministic;
+        sampled["temperature"] = serde_json::json!(0.7);
+        post_chat(state.clone(), sampled.clone()).await;
+        let (_, _, body) = post_chat(state, sampled).await;
+        assert!(body.contains("Sampled again"), "{body}");
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_cache_hits_skip_the_queue_and_overrides_are_keyed() {
+        use crate::headers::SYSTEM_OVERRIDE_HEADER;
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Cached answer"));
+        let queue = Arc::new(RequestQueue::new(1, 0, Duration::from_millis(10)));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            response_cache: Some(Arc::new(ResponseCache::new(8, Duration::from_secs(60)))),
+            request_queue: Some(queue.clone()),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = |override_prompt: Option<&str>| {
+            let mut request = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "openai/gpt-4",
+                    "messages": [{"role": "user", "content": "Hi"}]
+                }));
+            if let Some(prompt) = override_prompt {
+                request = request.insert_header((SYSTEM_OVERRIDE_HEADER, prompt));
+            }
+            request.to_request()
+        };
+
+        let response = test::call_service(&app, request(None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        drop(response);
+
+        // Every slot is taken, yet the repeated request is answered from the cache
+        let _held = queue.acquire().await.unwrap();
+        let response = test::call_service(&app, request(None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["choices"][0]["message"]["content"], "Cached answer");
+
+        // A different system override changes the upstream request, so it must queue
+        let response = test::call_service(&app, request(Some("Answer in French."))).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_comma_separated_models_fall_through_on_failure() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport
+            .push_response(500, "upstream exploded")
+            .push_response(200, mock_chat_body(""))
+            .push_response(200, mock_chat_body("Third model answered"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let (status, _, body) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "anthropic/claude-3, google/gemini-pro,openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Third model answered"), "{body}");
+
+        let models: Vec<String> = transport
+            .requests()
+            .into_iter()
+            .map(|request| request.model)
+            .collect();
+        assert_eq!(
+            models,
+            ["anthropic/claude-3", "google/gemini-pro", "openai/gpt-4"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_only_the_returned_fallback_response_is_persisted() {
+        use crate::transport::MockTransport;
+
+        let path = std::env::temp_dir().join(format!(
+            "straico-proxy-persist-server-{}.db",
+            std::process::id()
+        ));
+        let transport = Arc::new(MockTransport::new());
+        transport
+            .push_response(200, mock_chat_body(""))
+            .push_response(200, mock_chat_body("Second model answered"));
+        let state = AppState {
+            transport: Some(transport),
+            persist_db: Some(Arc::new(ConversationStore::open(&path).unwrap())),
+            ..Default::default()
+        };
+        let request = serde_json::json!({
+            "model": "anthropic/claude-3,openai/gpt-4",
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+        let (status, _, _) = post_chat(state, request.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // The row is written after the response, so wait for it
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let mut rows: Vec<(String, bool, String, String)> = Vec::new();
+        for _ in 0..100 {
+            rows = conn
+                .prepare("SELECT model, stream, request, response FROM conversations")
+                .unwrap()
+                .query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            if !rows.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(rows.len(), 1);
+        let (model, stream, stored_request, stored_response) = &rows[0];
+        assert_eq!(model, "anthropic/claude-3,openai/gpt-4");
+        assert!(!stream);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(stored_request).unwrap(),
+            request
+        );
+        let response: serde_json::Value = serde_json::from_str(stored_response).unwrap();
+        assert_eq!(
+            response["choices"][0]["message"]["content"],
+            "Second model answered"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_discarded_fallback_is_not_counted_in_latency_stats() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport
+            .push_response(200, mock_chat_body(""))
+            .push_response(200, mock_chat_body("Second model answered"));
+        let latency = Arc::new(LatencyTracker::default());
+        let state = AppState {
+            transport: Some(transport),
+            latency: latency.clone(),
+            ..Default::default()
+        };
+        let (status, _, _) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "anthropic/claude-3,openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let stats = latency.stats();
+        assert_eq!(stats.keys().collect::<Vec<_>>(), ["openai/gpt-4"]);
+        assert_eq!(stats["openai/gpt-4"].samples, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_last_fallback_model_error_is_returned() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport
+            .push_response(500, "first failed")
+            .push_response(400, "second failed");
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let (status, _, _) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "anthropic/claude-3,openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_mock_transport_retries_server_errors() {
+        use crate::headers::MAX_RETRIES_HEADER;
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport
+            .push_response(503, "upstream overloaded")
+            .push_response(200, mock_chat_body("Second time lucky"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((MAX_RETRIES_HEADER, "1"))
+            .set_json(serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": true
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Second time lucky"));
+        assert!(body.contains("data: [DONE]"));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_max_retries_header_overrides_server_setting() {
+        use crate::headers::MAX_RETRIES_HEADER;
+        use crate::test_support::scripted_upstream;
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = scripted_upstream(&[500], None);
+        let state = AppState {
+            client: upstream.client(),
+            max_retries: 2,
+            retry_base_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = |max_retries: Option<&str>| {
+            let mut request = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": "openai/gpt-4",
+                    "messages": [{"role": "user", "content": "Hi"}]
+                }));
+            if let Some(max_retries) = max_retries {
+                request = request.insert_header((MAX_RETRIES_HEADER, max_retries));
+            }
+            request.to_request()
+        };
+
+        // `--max-retries 2`: one attempt plus two retries
+        let response = test::call_service(&app, request(None)).await;
+        assert!(response.status().is_server_error());
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 3);
+
+        // `x-straico-max-retries: 0` fails fast with a single attempt
+        let response = test::call_service(&app, request(Some("0"))).await;
+        assert!(response.status().is_server_error());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_tuned_server_handles_requests() {
+        use crate::cli::Cli;
+        use crate::transport::MockTransport;
+        use actix_web::HttpServer;
+        use clap::Parser;
+
+        let cli = Cli::parse_from([
+            "straico-proxy",
+            "--workers",
+            "2",
+            "--keep-alive-secs",
+            "30",
+            "--client-request-timeout-secs",
+            "10",
+        ]);
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Hello"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .configure(configure_routes)
+        })
+        .workers(cli.workers.unwrap())
+        .keep_alive(cli.keep_alive().unwrap())
+        .client_request_timeout(cli.client_request_timeout().unwrap())
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{addr}/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello");
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_models_listed_in_openai_shape_with_fallback() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // Upstream lists one model, then starts failing
+        let failing = Arc::new(AtomicBool::new(false));
+        let upstream = MockUpstream::start({
+            let failing = failing.clone();
+            move |cfg| {
+                let failing = failing.clone();
+                cfg.route(
+                    "/v2/models",
+                    web::get().to(move || {
+                        let failing = failing.load(Ordering::SeqCst);
+                        async move {
+                            if failing {
+                                HttpResponse::InternalServerError().finish()
+                            } else {
+                                HttpResponse::Ok().json(serde_json::json!({
+                                    "data": [{
+                                        "name": "Nova Lite",
+                                        "id": "amazon/nova-lite-v1",
+                                        "pricing": {"coins": 1}
+                                    }],
+                                    "success": true
+                                }))
+                            }
+                        }
+                    }),
+                );
+            }
+        });
+
+        let state = AppState {
+            client: upstream.client(),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(models_handler),
+        )
+        .await;
+        let list_models = || test::TestRequest::get().uri("/v1/models").to_request();
+
+        let expected = serde_json::json!({
+            "object": "list",
+            "data": [{
+                "id": "amazon/nova-lite-v1",
+                "object": "model",
+                "created": 0,
+                "owned_by": "amazon"
+            }]
+        });
+        let body: serde_json::Value = test::call_and_read_body_json(&app, list_models()).await;
+        assert_eq!(body, expected);
+
+        // The last fetched list is served while the upstream fails
+        failing.store(true, Ordering::SeqCst);
+        let response = test::call_service(&app, list_models()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body, expected);
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_admin_endpoints_are_disabled_without_a_token() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::default()))
+                .service(admin_reload_handler)
+                .service(admin_stats_handler),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/admin/stats").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let request = test::TestRequest::post()
+            .uri("/admin/reload")
+            .insert_header((header::AUTHORIZATION, "Bearer "))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_stats_report_per_model_latency() {
+        use std::time::Duration;
+
+        // Upstream answers `openai/gpt-4o-mini` after 10ms and everything else after 80ms
+        let upstream = MockUpstream::start(|cfg| {
+            cfg.route(
+                "/v2/chat/completions",
+                web::post().to(|body: web::Json<serde_json::Value>| async move {
+                    let model = body["model"].as_str().unwrap_or_default();
+                    let delay = if model == "openai/gpt-4o-mini" {
+                        10
+                    } else {
+                        80
+                    };
+                    actix_web::rt::time::sleep(Duration::from_millis(delay)).await;
+                    HttpResponse::Ok().json(straico_json(model, "Hello"))
+                }),
+            );
+        });
+
+        let state = AppState {
+            client: upstream.client(),
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion)
+                .service(admin_stats_handler),
+        )
+        .await;
+        for (model, stream) in [
+            ("openai/gpt-4o-mini", false),
+            ("openai/gpt-4o-mini", true),
+            ("openai/gpt-4", false),
+            ("openai/gpt-4", true),
+        ] {
+            let request = test::TestRequest::post()
+                .uri("/v1/chat/completions")
+                .set_json(serde_json::json!({
+                    "model": model,
+                    "stream": stream,
+                    "messages": [{"role": "user", "content": "Hi"}]
+                }))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert!(response.status().is_success());
+            test::read_body(response).await;
+        }
+
+        let request = test::TestRequest::get().uri("/admin/stats").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        for token in ["Bearer secreT", "Bearer secret2", "Bearer "] {
+            let request = test::TestRequest::get()
+                .uri("/admin/stats")
+                .insert_header((header::AUTHORIZATION, token))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let request = test::TestRequest::get()
+            .uri("/admin/stats")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let stats: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let fast = &stats["models"]["openai/gpt-4o-mini"];
+        let slow = &stats["models"]["openai/gpt-4"];
+        assert_eq!(fast["samples"], 2);
+        assert_eq!(slow["samples"], 2);
+        assert!(fast["avg_ms"].as_f64().unwrap() >= 10.0);
+        assert!(slow["avg_ms"].as_f64().unwrap() >= 80.0);
+        assert!(slow["p95_ms"].as_f64().unwrap() > fast["p95_ms"].as_f64().unwrap());
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_health_check() {
+        let app = test::init_service(App::new().service(health_check)).await;
+        let request = test::TestRequest::get().uri("/health").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body, serde_json::json!({"status": "ok"}));
+    }
+
+    /// Starts an upstream whose chat endpoint waits `delay` before answering, then sends
+    /// its body in `parts` pieces `delay` apart.
+    fn slow_upstream(delay: Duration, parts: usize) -> (MockUpstream, StraicoClient) {
+        use futures::StreamExt;
+
+        let upstream = MockUpstream::start(move |cfg| {
+            cfg.route(
+                "/v2/chat/completions",
+                web::post().to(move || async move {
+                    tokio::time::sleep(delay).await;
+                    let body = mock_chat_body("Slow but steady").into_bytes();
+                    let pieces: Vec<Bytes> = body
+                        .chunks(body.len().div_ceil(parts))
+                        .map(Bytes::copy_from_slice)
+                        .collect();
+                    let body = futures::stream::iter(pieces.into_iter().enumerate()).then(
+                        move |(index, piece)| async move {
+                            if index > 0 {
+                                tokio::time::sleep(delay).await;
+                            }
+                            Ok::<_, actix_web::Error>(piece)
+                        },
+                    );
+                    HttpResponse::Ok()
+                        .content_type("application/json")
+                        .streaming(body)
+                }),
+            );
+        });
+        let client = StraicoClient::builder()
+            .base_url(upstream.base_url.clone())
+            .read_timeout(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        (upstream, client)
+    }
+
+    #[actix_web::test]
+    async fn test_slow_upstream_times_out_with_504() {
+        let (upstream, client) = slow_upstream(Duration::from_millis(200), 1);
+
+        let state = AppState {
+            client,
+            key: "test-key".to_string(),
+            request_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let (status, _, body) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(body.contains("did not respond in time"), "{body}");
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_request_timeout_does_not_cut_off_streams() {
+        // Every read arrives within the client's read timeout, but the whole response
+        // takes longer than the request timeout
+        let (upstream, client) = slow_upstream(Duration::from_millis(100), 3);
+
+        let state = AppState {
+            client,
+            key: "test-key".to_string(),
+            request_timeout: Some(Duration::from_millis(150)),
+            ..Default::default()
+        };
+        let (status, _, body) = post_chat(
+            state,
+            serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": true
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Slow but steady"), "{body}");
+        // Not a partial answer recovered from a cut-off body
+        assert!(body.contains(r#""finish_reason":"stop""#), "{body}");
+
+        upstream.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn test_readiness_check() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let failing = Arc::new(AtomicBool::new(false));
+        let upstream = MockUpstream::start({
+            let failing = failing.clone();
+            move |cfg| {
+                let failing = failing.clone();
+                cfg.route(
+                    "/v2/models",
+                    web::get().to(move || {
+                        let failing = failing.load(Ordering::SeqCst);
+                        async move {
+                            if failing {
+                                HttpResponse::Unauthorized().finish()
+                            } else {
+                                HttpResponse::Ok().json(serde_json::json!({"data": []}))
+                            }
+                        }
+                    }),
+                );
+            }
+        });
+
+        let readyz = |state: AppState| async move {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state))
+                    .service(readiness_check),
+            )
+            .await;
+            let request = test::TestRequest::get().uri("/readyz").to_request();
+            test::call_service(&app, request).await.status()
+        };
+        let client = upstream.client();
+
+        // Missing API key
+        assert_eq!(
+            readyz(AppState::default()).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // The upstream is only probed when asked to
+        failing.store(true, Ordering::SeqCst);
+        let state = AppState {
+            client: client.clone(),
+            key: "test-key".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(readyz(state.clone()).await, StatusCode::OK);
+
+        let state = AppState {
+            readiness_check_upstream: true,
+            ..state
+        };
+        assert_eq!(readyz(state.clone()).await, StatusCode::SERVICE_UNAVAILABLE);
+        failing.store(false, Ordering::SeqCst);
+        assert_eq!(readyz(state).await, StatusCode::OK);
+
+        upstream.stop().await;
+    }
+
+    /// Posts a `stream: true` request from a client that only accepts JSON, returning the
+    /// response's content type and body.
+    async fn post_stream_from_json_client(accept_mismatch: AcceptMismatch) -> (String, String) {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Hello"));
+        let state = AppState {
+            transport: Some(transport),
+            accept_mismatch,
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(openai_chat_completion),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/v1/chat/completions")
+            .insert_header((header::ACCEPT, "application/json"))
+            .set_json(serde_json::json!({
+                "model": "openai/gpt-4",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": true
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = test::read_body(response).await;
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_accept_mismatch_downgrades_to_json() {
+        let (content_type, body) = post_stream_from_json_client(AcceptMismatch::Json).await;
+        assert_eq!(content_type, "application/json");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello");
+
+        // The default honors `stream: true`
+        let (content_type, body) = post_stream_from_json_client(AcceptMismatch::Stream).await;
+        assert_eq!(content_type, "text/event-stream");
+        assert!(body.contains("chat.completion.chunk"));
+    }
+
+    #[actix_web::test]
+    async fn test_text_completion_endpoint() {
+        use crate::transport::MockTransport;
+
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Hi!"));
+        transport.push_response(200, mock_chat_body("Bye"));
+        transport.push_response(200, mock_chat_body("Streamed"));
+        let state = AppState {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(text_completion_handler),
+        )
+        .await;
+
+        // One upstream chat request and one choice per prompt
+        let request = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "openai/gpt-4",
+                "prompt": ["Say hi", "Say bye"],
+                "max_tokens": 16
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["object"], "text_completion");
+        assert_eq!(body["choices"][0]["text"], "Hi!");
+        assert_eq!(body["choices"][1]["text"], "Bye");
+        assert_eq!(body["choices"][1]["index"], 1);
+        let sent = transport.requests();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].messages.len(), 1);
+        assert_eq!(sent[1].messages[0].content().to_string(), "Say bye");
+
+        let request = test::TestRequest::post()
+            .uri("/v1/completions")
+            .set_json(serde_json::json!({
+                "model": "openai/gpt-4",
+                "prompt": "Say something",
+                "stream": true
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        let chunk = &sse_events(body)[0];
+        assert_eq!(chunk["object"], "text_completion");
+        assert_eq!(chunk["choices"][0]["text"], "Streamed");
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[actix_web::test]
+    async fn test_streamed_text_completion_uses_stream_options() {
+        use crate::transport::MockTransport;
+
+        let stream = |state: AppState| async move {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state))
+                    .service(text_completion_handler),
+            )
+            .await;
+            let request = test::TestRequest::post()
+                .uri("/v1/completions")
+                .set_json(serde_json::json!({
+                    "model": "openai/gpt-4",
+                    "prompt": "Say something",
+                    "stream": true
+                }))
+                .to_request();
+            let body = test::call_and_read_body(&app, request).await;
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(200, mock_chat_body("Streamed"));
+        let body = stream(AppState {
+            transport: Some(transport),
+            sse_retry_ms: Some(1500),
+            sse_dialect: SseDialect::Azure,
+            sse_chunk_index: true,
+            ..Default::default()
+        })
+        .await;
+        assert!(body.starts_with("retry: 1500\n\n"), "{body}");
+        let chunks = sse_events(&body);
+        assert!(chunks[0].get("prompt_filter_results").is_some());
+        let completion = chunks.last().unwrap();
+        assert_eq!(completion["choices"][0]["text"], "Streamed");
+        assert!(completion["choices"][0]["content_filter_results"].is_object());
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk["chunk_index"], index);
+        }
+
+        // The heartbeat cap gives up on a slow upstream
+        let (upstream, client) = slow_upstream(Duration::from_millis(300), 1);
+        let body = stream(AppState {
+            client,
+            key: "test-key".to_string(),
+            heartbeat_interval: Some(Duration::from_millis(20)),
+            max_heartbeats: Some(2),
+            ..Default::default()
+        })
+        .await;
+        let chunks = sse_events(&body);
+        assert_eq!(chunks.len(), 3, "{body}");
+        assert!(chunks[2]["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("after 2 heartbeats"));
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+        upstream.stop().await;
+    }
 }
@@ -0,0 +1,162 @@
+//! Removal of chat template special tokens leaked into response content
+//! (`--strip-special-tokens`).
+
+/// Tokens stripped when `--strip-special-tokens` is given without a list.
+pub const DEFAULT_SPECIAL_TOKENS: &[&str] = &[
+    "<|im_start|>",
+    "<|im_end|>",
+    "<|eot_id|>",
+    "<|end_of_text|>",
+    "<|endoftext|>",
+    "<|end|>",
+    "<s>",
+    "</s>",
+];
+
+/// Role names that follow a leaked message header such as `<|im_start|>assistant`.
+const ROLE_MARKERS: &[&str] = &["system", "user", "assistant", "tool"];
+
+/// Removes `tokens` from `content`.
+///
+/// `<|...|>` tokens never occur in normal text and are removed wherever they appear,
+/// together with a role name right after them (`<|im_start|>assistant`). Other tokens,
+/// such as `</s>`, are also valid HTML, so they are only removed from the start and end of
+/// the content, and only when unbalanced (a `</s>` closing a `<s>` stays). A last line
+/// holding nothing but a role name, left over from a header, is removed as well.
+/// Content without any of the tokens or a trailing role line is returned unchanged.
+pub fn strip_special_tokens(content: &str, tokens: &[String]) -> String {
+    let (delimited, edge_only): (Vec<&String>, Vec<&String>) = tokens
+        .iter()
+        .filter(|token| !token.is_empty())
+        .partition(|token| token.starts_with("<|") && token.ends_with("|>"));
+
+    let mut stripped = content.to_string();
+    for token in delimited {
+        let mut parts = stripped.split(token.as_str());
+        let mut joined = parts.next().unwrap_or_default().to_string();
+        for part in parts {
+            joined.push_str(strip_role_marker(part));
+        }
+        stripped = joined;
+    }
+    loop {
+        let trimmed = stripped.trim();
+        let rest = edge_only
+            .iter()
+            .filter(|token| is_unbalanced(trimmed, token))
+            .find_map(|token| {
+                trimmed
+                    .strip_prefix(token.as_str())
+                    .or_else(|| trimmed.strip_suffix(token.as_str()))
+            });
+        match rest {
+            Some(rest) => stripped = rest.to_string(),
+            None => break,
+        }
+    }
+    if let Some((body, last_line)) = stripped.trim_end().rsplit_once('\n') {
+        if ROLE_MARKERS.contains(&last_line.trim()) {
+            stripped = body.to_string();
+        }
+    }
+
+    if stripped == content {
+        stripped
+    } else {
+        stripped.trim().to_string()
+    }
+}
+
+/// `text` without a leading role name that ends its line (or the text).
+fn strip_role_marker(text: &str) -> &str {
+    ROLE_MARKERS
+        .iter()
+        .find_map(|role| {
+            let rest = text.strip_prefix(role)?;
+            (rest.is_empty() || rest.starts_with(['\n', '\r'])).then_some(rest)
+        })
+        .unwrap_or(text)
+}
+
+/// Whether a tag-like `token` (`<s>` or `</s>`) occurs more often than its counterpart.
+/// Tokens that don't look like tags are always considered unbalanced.
+fn is_unbalanced(content: &str, token: &str) -> bool {
+    let counterpart = if let Some(name) = token.strip_prefix("</") {
+        format!("<{name}")
+    } else if let Some(name) = token.strip_prefix('<') {
+        format!("</{name}")
+    } else {
+        return true;
+    };
+    content.matches(token).count() > content.matches(counterpart.as_str()).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Vec<String> {
+        DEFAULT_SPECIAL_TOKENS
+            .iter()
+            .map(|t| t.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_im_end_is_stripped() {
+        assert_eq!(
+            strip_special_tokens("The answer is 42.<|im_end|>", &defaults()),
+            "The answer is 42."
+        );
+        assert_eq!(
+            strip_special_tokens("Done<|im_end|>\n<|im_start|>assistant", &defaults()),
+            "Done"
+        );
+        assert_eq!(
+            strip_special_tokens("<|im_start|>assistant\nHello<|im_end|>", &defaults()),
+            "Hello"
+        );
+    }
+
+    #[test]
+    fn test_trailing_role_marker_line_is_stripped() {
+        assert_eq!(strip_special_tokens("Done\nassistant", &defaults()), "Done");
+        assert_eq!(strip_special_tokens("Done\n\nuser\n", &defaults()), "Done");
+        // Only a whole line naming a role counts
+        assert_eq!(
+            strip_special_tokens("Ask the assistant", &defaults()),
+            "Ask the assistant"
+        );
+        assert_eq!(
+            strip_special_tokens("Roles:\nuser and assistant", &defaults()),
+            "Roles:\nuser and assistant"
+        );
+        assert_eq!(strip_special_tokens("assistant", &defaults()), "assistant");
+    }
+
+    #[test]
+    fn test_trailing_eos_is_stripped() {
+        assert_eq!(
+            strip_special_tokens("The answer is 42. </s>", &defaults()),
+            "The answer is 42."
+        );
+        assert_eq!(
+            strip_special_tokens("<s> Hello<|eot_id|>", &defaults()),
+            "Hello"
+        );
+    }
+
+    #[test]
+    fn test_legitimate_content_is_preserved() {
+        let html = "Use <s>strikethrough</s> for deleted text, e.g. <s>old</s> new";
+        assert_eq!(strip_special_tokens(html, &defaults()), html);
+        assert_eq!(
+            strip_special_tokens("Text ending in <s>old</s>", &defaults()),
+            "Text ending in <s>old</s>"
+        );
+        assert_eq!(
+            strip_special_tokens("  Indented code\n", &defaults()),
+            "  Indented code\n"
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::fmt::Write;
 
 #[derive(Clone, Copy, Debug, clap::ValueEnum, Default)]
 pub enum HeartbeatChar {
@@ -15,6 +16,17 @@ pub enum HeartbeatChar {
     Wj,
 }
 
+/// Client family whose SSE framing streamed responses follow.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum SseDialect {
+    /// OpenAI framing: the first chunk carries the assistant role
+    #[default]
+    #[value(name = "openai")]
+    OpenAi,
+    /// Azure OpenAI framing: a prompt content-filter chunk precedes the role chunk
+    Azure,
+}
+
 impl HeartbeatChar {
     pub fn as_str(&self) -> &str {
         match self {
@@ -32,6 +44,9 @@ use straico_client::StraicoChatResponse;
 
 use crate::ProxyError;
 
+/// `object` of every streamed chat chunk.
+pub const CHAT_COMPLETION_CHUNK_OBJECT: &str = "chat.completion.chunk";
+
 /// Enum representing different types of SSE chunks
 #[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
@@ -42,6 +57,8 @@ pub enum SseChunk {
     Done(String),
     /// Error chunk containing error information
     Error(Value),
+    /// Data chunk whose shape is not a `CompletionStream` (e.g. dialect-specific chunks)
+    Event(Value),
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -52,7 +69,12 @@ pub struct CompletionStream {
     pub id: Box<str>,
     pub model: Box<str>,
     pub created: u64,
-    pub usage: Usage,
+    /// Only set on the final usage chunk, sent when the client asks for it with
+    /// `stream_options.include_usage`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<Box<str>>,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -60,6 +82,9 @@ pub struct ChoiceStream {
     pub index: u8,
     pub delta: Delta,
     pub finish_reason: Option<Box<str>>,
+    /// Azure OpenAI's per-choice filter results (`--sse-dialect azure`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<Value>,
 }
 
 #[derive(Serialize, Debug, Clone, Default)]
@@ -70,29 +95,30 @@ pub struct Delta {
     pub content: Option<Box<str>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<Box<str>>,
 }
 
+/// Content deltas never carry `role`: OpenAI streams send it only in the first chunk,
+/// which is [`CompletionStream::initial_chunk`]. Content that accompanies tool calls is
+/// kept next to them.
 impl From<OpenAiChatMessage> for Delta {
     fn from(value: OpenAiChatMessage) -> Self {
         match value {
             OpenAiChatMessage::Assistant {
                 content,
                 tool_calls,
-            } => {
-                if let Some(tool_calls) = tool_calls {
-                    Self {
-                        role: None,
-                        content: None,
-                        tool_calls: Some(tool_calls),
-                    }
-                } else {
-                    Self {
-                        role: None,
-                        content: content.map(|c| c.to_string().into()),
-                        tool_calls: None,
-                    }
-                }
-            }
+                reasoning_content,
+                ..
+            } => Self {
+                role: None,
+                content: content
+                    .map(|c| c.to_string())
+                    .filter(|c| tool_calls.is_none() || !c.is_empty())
+                    .map(Into::into),
+                tool_calls,
+                reasoning_content: reasoning_content.map(Into::into),
+            },
             _ => Self::default(),
         }
     }
@@ -103,7 +129,8 @@ impl From<ChatChoice<OpenAiChatMessage>> for ChoiceStream {
         Self {
             index: value.index,
             delta: value.message.into(),
-            finish_reason: Some(value.finish_reason.into()),
+            finish_reason: value.finish_reason.map(Into::into),
+            content_filter_results: None,
         }
     }
 }
@@ -112,11 +139,12 @@ impl From<OpenAiChatResponse> for CompletionStream {
     fn from(value: OpenAiChatResponse) -> Self {
         Self {
             choices: value.choices.into_iter().map(Into::into).collect(),
-            object: "chat.completion.chunk".into(),
+            object: CHAT_COMPLETION_CHUNK_OBJECT.into(),
             id: value.id.into(),
             model: value.model.into(),
             created: value.created,
-            usage: value.usage,
+            usage: Some(value.usage),
+            service_tier: value.service_tier.map(Into::into),
         }
     }
 }
@@ -129,6 +157,58 @@ impl TryFrom<StraicoChatResponse> for CompletionStream {
 }
 
 impl CompletionStream {
+    /// Splits a converted response into the chunks streamed to the client: one per choice,
+    /// each keeping its choice's `index`, so clients can reassemble every choice separately.
+    ///
+    /// The initial chunk only announces the role for index 0, so the other choices carry
+    /// it in their own delta. Usage covers the whole response: with `include_usage` it
+    /// follows in a final chunk without choices, otherwise it is dropped.
+    pub fn into_choice_chunks(self, include_usage: bool) -> Vec<CompletionStream> {
+        let Self {
+            choices,
+            object,
+            id,
+            model,
+            created,
+            usage,
+            service_tier,
+        } = self;
+        let chunk = |choices| CompletionStream {
+            choices,
+            object: object.clone(),
+            id: id.clone(),
+            model: model.clone(),
+            created,
+            usage: None,
+            service_tier: service_tier.clone(),
+        };
+        let mut chunks: Vec<CompletionStream> = choices
+            .into_iter()
+            .map(|mut choice| {
+                if choice.index != 0 {
+                    choice.delta.role = Some("assistant".into());
+                }
+                chunk(vec![choice])
+            })
+            .collect();
+        if include_usage {
+            chunks.push(CompletionStream {
+                usage: Some(usage.unwrap_or_default()),
+                ..chunk(Vec::new())
+            });
+        }
+        chunks
+    }
+
+    /// Marks every choice as not filtered, the way Azure OpenAI reports content that
+    /// passed its filters.
+    pub fn with_content_filter_results(mut self) -> Self {
+        for choice in &mut self.choices {
+            choice.content_filter_results = Some(content_filter_results());
+        }
+        self
+    }
+
     /// Creates an initial SSE chunk with basic metadata and assistant role
     pub fn initial_chunk(model: &str, id: &str, created: u64) -> Self {
         Self {
@@ -136,16 +216,42 @@ impl CompletionStream {
                 index: 0,
                 delta: Delta {
                     role: Some("assistant".into()),
-                    content: None,
-                    tool_calls: None,
+                    ..Default::default()
                 },
                 finish_reason: None,
+                content_filter_results: None,
             }],
-            object: "chat.completion.chunk".into(),
+            object: CHAT_COMPLETION_CHUNK_OBJECT.into(),
             id: id.into(),
             model: model.into(),
             created,
-            usage: Usage::default(), // All zeros
+            usage: None,
+            service_tier: None,
+        }
+    }
+
+    /// Creates the final chunk for a response cut short by an upstream timeout, carrying the
+    /// content received so far plus a note that it is truncated.
+    pub fn timeout_chunk(model: &str, id: &str, created: u64, partial_content: &str) -> Self {
+        Self {
+            choices: vec![ChoiceStream {
+                index: 0,
+                delta: Delta {
+                    content: Some(
+                        format!("{partial_content}\n\n[Response truncated: the upstream request timed out]")
+                            .into(),
+                    ),
+                    ..Default::default()
+                },
+                finish_reason: Some("timeout".into()),
+                content_filter_results: None,
+            }],
+            object: CHAT_COMPLETION_CHUNK_OBJECT.into(),
+            id: id.into(),
+            model: model.into(),
+            created,
+            usage: None,
+            service_tier: None,
         }
     }
 
@@ -166,12 +272,14 @@ impl CompletionStream {
                     ..Default::default()
                 },
                 finish_reason: None,
+                content_filter_results: None,
             }],
-            object: "chat.completion.chunk".into(),
+            object: CHAT_COMPLETION_CHUNK_OBJECT.into(),
             id: "".into(), // Empty for heartbeat
             model: "".into(),
             created: 0,
-            usage: Usage::default(),
+            usage: None,
+            service_tier: None,
         }
     }
 }
@@ -200,23 +308,118 @@ impl From<ProxyError> for SseChunk {
     }
 }
 
+/// Encodes a single chunk. Streams encode through one [`SseEncoder`] instead.
 impl TryFrom<SseChunk> for Bytes {
     type Error = ProxyError;
     fn try_from(value: SseChunk) -> Result<Self, Self::Error> {
-        let json_bytes = match value {
-            SseChunk::Data(stream) => serde_json::to_vec(&stream)?,
-            SseChunk::Done(msg) => msg.into_bytes(),
-            SseChunk::Error(error_value) => serde_json::to_vec(&error_value)?,
-        };
+        SseEncoder::default().encode(value)
+    }
+}
 
-        // Prepend "data: " and append "\n\n"
-        let mut sse_bytes = Vec::with_capacity(json_bytes.len() + 8); // "data: " (6) + "\n\n" (2)
-        sse_bytes.extend_from_slice(b"data: ");
-        sse_bytes.extend_from_slice(&json_bytes);
-        sse_bytes.extend_from_slice(b"\n\n");
+/// Serializes SSE chunks into one reusable buffer.
+///
+/// Each encoded event is split off the buffer, so a long-lived encoder (one per stream)
+/// reuses the buffer's spare capacity instead of allocating a fresh `Vec` per chunk.
+#[derive(Debug, Default)]
+pub struct SseEncoder {
+    buffer: BytesMut,
+    /// Next `chunk_index`, when chunks are numbered
+    next_index: Option<u64>,
+}
 
-        Ok(Bytes::from(sse_bytes))
+impl SseEncoder {
+    /// An encoder adding a non-standard, increasing `"chunk_index"` field to every chunk
+    /// carrying a JSON object. The `[DONE]` sentinel is not JSON and stays unnumbered.
+    pub fn numbered() -> Self {
+        Self {
+            next_index: Some(0),
+            ..Default::default()
+        }
     }
+
+    /// Frames `chunk` as a `data: ...\n\n` event.
+    ///
+    /// Every event starts with exactly `data: `. JSON payloads never carry a UTF-8 BOM
+    /// (`serde_json` writes none, and a BOM inside content stays inside a JSON string); the
+    /// raw `Done` text is the one place one could slip in, so it is stripped there.
+    pub fn encode(&mut self, chunk: SseChunk) -> Result<Bytes, ProxyError> {
+        let index = self.next_index.filter(|_| match &chunk {
+            SseChunk::Data(_) => true,
+            SseChunk::Done(_) => false,
+            SseChunk::Error(value) | SseChunk::Event(value) => value.is_object(),
+        });
+        self.buffer.extend_from_slice(b"data: ");
+        if let Some(index) = index {
+            // The object's own `{` is overwritten below, so the index comes first
+            let _ = write!(self.buffer, "{{\"chunk_index\":{index}");
+        }
+        let object_start = self.buffer.len();
+        let written = match chunk {
+            SseChunk::Data(stream) => serde_json::to_writer((&mut self.buffer).writer(), &stream),
+            SseChunk::Done(msg) => {
+                self.buffer
+                    .extend_from_slice(msg.trim_start_matches('\u{feff}').as_bytes());
+                Ok(())
+            }
+            SseChunk::Error(value) | SseChunk::Event(value) => {
+                serde_json::to_writer((&mut self.buffer).writer(), &value)
+            }
+        };
+        if let Err(e) = written {
+            self.buffer.clear();
+            return Err(e.into());
+        }
+        if let Some(index) = index {
+            if self.buffer[object_start + 1] == b'}' {
+                // `{}`: close the object after the index alone
+                self.buffer.truncate(object_start);
+                self.buffer.put_u8(b'}');
+            } else {
+                self.buffer[object_start] = b',';
+            }
+            self.next_index = Some(index + 1);
+        }
+        self.buffer.extend_from_slice(b"\n\n");
+        Ok(self.buffer.split().freeze())
+    }
+}
+
+/// Formats an SSE `retry:` field telling clients how long to wait before reconnecting.
+pub fn retry_line(ms: u64) -> Bytes {
+    Bytes::from(format!("retry: {ms}\n\n"))
+}
+
+/// Azure OpenAI's content-filter results for content that was not filtered. The proxy
+/// does no filtering of its own, so everything is reported as safe.
+pub fn content_filter_results() -> Value {
+    let safe = json!({"filtered": false, "severity": "safe"});
+    json!({
+        "hate": safe,
+        "self_harm": safe,
+        "sexual": safe,
+        "violence": safe
+    })
+}
+
+/// Azure OpenAI's `prompt_filter_results` for a prompt that was not filtered.
+pub fn prompt_filter_results() -> Value {
+    json!([{
+        "prompt_index": 0,
+        "content_filter_results": content_filter_results()
+    }])
+}
+
+/// First chunk of an Azure OpenAI stream: no choices, only the prompt's content-filter
+/// results.
+pub fn azure_prompt_filter_chunk() -> Value {
+    json!({
+        "choices": [],
+        "created": 0,
+        "id": "",
+        "model": "",
+        "object": "",
+        "prompt_filter_results": prompt_filter_results()
+    })
 }
 
 pub fn create_error_chunk(error: &str) -> Value {
@@ -247,8 +450,8 @@ pub fn create_error_chunk_with_type(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::straico_json;
     use bytes::Bytes;
-    use straico_client::endpoints::chat::response_types::Usage;
 
     #[test]
     fn test_sse_chunk_data_serialization() {
@@ -258,15 +461,17 @@ mod tests {
                 delta: Delta {
                     role: Some("assistant".into()),
                     content: Some("Hello".into()),
-                    tool_calls: None,
+                    ..Default::default()
                 },
                 finish_reason: None,
+                content_filter_results: None,
             }],
             object: "chat.completion.chunk".into(),
             id: "test-id".into(),
             model: "test-model".into(),
             created: 1234567890,
-            usage: Usage::default(),
+            usage: None,
+            service_tier: None,
         };
 
         let sse_chunk = SseChunk::from(stream);
@@ -294,6 +499,74 @@ mod tests {
 
         let bytes_str = String::from_utf8(bytes.unwrap().to_vec()).unwrap();
         assert_eq!(bytes_str, "data: [DONE]\n\n");
+
+        let bytes: Bytes = SseChunk::from("\u{feff}[DONE]".to_string())
+            .try_into()
+            .unwrap();
+        assert_eq!(&bytes[..], b"data: [DONE]\n\n");
+    }
+
+    #[test]
+    fn test_reused_encoder_matches_single_chunk_encoding() {
+        let chunks = || {
+            vec![
+                SseChunk::from(CompletionStream::initial_chunk(
+                    "openai/gpt-4",
+                    "chatcmpl-1",
+                    1,
+                )),
+                SseChunk::from(CompletionStream::heartbeat_chunk(&HeartbeatChar::Zwsp)),
+                SseChunk::Event(azure_prompt_filter_chunk()),
+                SseChunk::from(json!({"error": {"message": "boom"}})),
+                SseChunk::from("[DONE]".to_string()),
+            ]
+        };
+
+        let mut encoder = SseEncoder::default();
+        let pooled: Vec<Bytes> = chunks()
+            .into_iter()
+            .map(|chunk| encoder.encode(chunk).unwrap())
+            .collect();
+        let expected: Vec<String> = chunks()
+            .into_iter()
+            .map(|chunk| match chunk {
+                SseChunk::Done(msg) => format!("data: {msg}\n\n"),
+                other => format!("data: {}\n\n", serde_json::to_string(&other).unwrap()),
+            })
+            .collect();
+
+        for (pooled, expected) in pooled.iter().zip(&expected) {
+            assert_eq!(std::str::from_utf8(pooled).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_numbered_encoder_indexes_json_objects() {
+        let mut encoder = SseEncoder::numbered();
+        let mut encode =
+            |chunk| String::from_utf8(encoder.encode(chunk).unwrap().to_vec()).unwrap();
+
+        assert_eq!(
+            encode(SseChunk::Event(json!({"a": 1}))),
+            "data: {\"chunk_index\":0,\"a\":1}\n\n"
+        );
+        assert_eq!(
+            encode(SseChunk::Event(json!({}))),
+            "data: {\"chunk_index\":1}\n\n"
+        );
+        // Non-objects and the sentinel are left alone and do not use up an index
+        assert_eq!(encode(SseChunk::Event(json!([1]))), "data: [1]\n\n");
+        assert_eq!(
+            encode(SseChunk::from("[DONE]".to_string())),
+            "data: [DONE]\n\n"
+        );
+        let heartbeat = encode(SseChunk::from(CompletionStream::heartbeat_chunk(
+            &HeartbeatChar::Empty,
+        )));
+        let heartbeat: Value =
+            serde_json::from_str(heartbeat.strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(heartbeat["chunk_index"], 2);
+        assert_eq!(heartbeat["object"], CHAT_COMPLETION_CHUNK_OBJECT);
     }
 
     #[test]
@@ -447,6 +720,39 @@ mod tests {
         assert_eq!(parsed["error"]["message"], "Test error");
     }
 
+    #[test]
+    fn test_streaming_normalizes_straico_finish_reason() {
+        let mut raw = straico_json("anthropic/claude-3", "Hello");
+        raw["choices"][0]["finish_reason"] = json!("end_turn");
+        let straico_response: StraicoChatResponse = serde_json::from_value(raw).unwrap();
+
+        let stream = CompletionStream::try_from(straico_response).unwrap();
+        assert_eq!(
+            stream.choices[0].finish_reason.as_ref().unwrap().as_ref(),
+            "stop"
+        );
+    }
+
+    #[test]
+    fn test_streamed_tool_calls_keep_accompanying_content() {
+        let mut raw = straico_json("openai/gpt-4", "Let me check the weather.");
+        raw["choices"][0]["message"]["tool_calls"] = json!([{
+            "id": "call_native",
+            "type": "function",
+            "function": {"name": "get_weather", "arguments": "{}"}
+        }]);
+        let straico_response: StraicoChatResponse = serde_json::from_value(raw).unwrap();
+
+        let stream = CompletionStream::try_from(straico_response).unwrap();
+        let choice = &stream.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(
+            choice.delta.content.as_deref(),
+            Some("Let me check the weather.")
+        );
+        assert_eq!(choice.delta.tool_calls.as_ref().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_byte_efficiency() {
         let stream = CompletionStream::initial_chunk("test", "id", 123);
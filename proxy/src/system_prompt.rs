@@ -0,0 +1,212 @@
+use crate::types::OpenAiChatRequest;
+use chrono::{Local, Utc};
+use straico_client::endpoints::chat::{ChatContent, OpenAiChatMessage};
+
+/// Time zone used when injecting the current date into requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CurrentDateZone {
+    /// Coordinated Universal Time
+    Utc,
+    /// The proxy host's local time zone
+    Local,
+}
+
+/// How an `x-straico-system-override` header combines with the request's own system prompt.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum SystemOverrideMode {
+    /// Drop the request's system and developer messages in favour of the header's
+    #[default]
+    Replace,
+    /// Put the header's system message in front of the request's own
+    Prepend,
+}
+
+/// How the operator's default system prompt (`--system-prompt`) combines with a request's own.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, serde::Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemPromptMode {
+    /// Only add it to requests without a system or developer message
+    #[default]
+    Fill,
+    /// Add it after the request's own system messages
+    Append,
+    /// Drop the request's system and developer messages in favour of it
+    Replace,
+}
+
+/// Builds the system note announcing today's date in the given zone.
+pub fn current_date_note(zone: CurrentDateZone) -> String {
+    let (date, offset) = match zone {
+        CurrentDateZone::Utc => (Utc::now().format("%Y-%m-%d"), "UTC".to_string()),
+        CurrentDateZone::Local => {
+            let now = Local::now();
+            (now.format("%Y-%m-%d"), format!("UTC{}", now.format("%:z")))
+        }
+    };
+    format!("Current date: {date} ({offset}).")
+}
+
+/// Inserts a system message at the front of the request's messages.
+pub fn prepend_system_message(request: &mut OpenAiChatRequest, text: String) {
+    request.chat_request.messages.insert(
+        0,
+        OpenAiChatMessage::System {
+            content: ChatContent::String(text),
+            name: None,
+        },
+    );
+}
+
+fn is_system_message(message: &OpenAiChatMessage) -> bool {
+    matches!(
+        message,
+        OpenAiChatMessage::System { .. } | OpenAiChatMessage::Developer { .. }
+    )
+}
+
+/// Applies the operator's default system prompt from `--system-prompt`.
+pub fn apply_default_system_prompt(
+    request: &mut OpenAiChatRequest,
+    text: &str,
+    mode: SystemPromptMode,
+) {
+    let messages = &mut request.chat_request.messages;
+    let position = match mode {
+        SystemPromptMode::Fill if messages.iter().any(is_system_message) => return,
+        SystemPromptMode::Fill => 0,
+        SystemPromptMode::Append => messages
+            .iter()
+            .rposition(is_system_message)
+            .map_or(0, |last| last + 1),
+        SystemPromptMode::Replace => {
+            messages.retain(|message| !is_system_message(message));
+            0
+        }
+    };
+    messages.insert(
+        position,
+        OpenAiChatMessage::System {
+            content: ChatContent::String(text.to_string()),
+            name: None,
+        },
+    );
+}
+
+/// Applies a per-request system prompt from the `x-straico-system-override` header.
+pub fn apply_system_override(
+    request: &mut OpenAiChatRequest,
+    text: String,
+    mode: SystemOverrideMode,
+) {
+    if mode == SystemOverrideMode::Replace {
+        request
+            .chat_request
+            .messages
+            .retain(|message| !is_system_message(message));
+    }
+    prepend_system_message(request, text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_date_note_utc() {
+        let note = current_date_note(CurrentDateZone::Utc);
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+        assert_eq!(note, format!("Current date: {today} (UTC)."));
+    }
+
+    #[test]
+    fn test_current_date_note_local_includes_offset() {
+        let note = current_date_note(CurrentDateZone::Local);
+        assert!(note.starts_with("Current date: "));
+        assert!(note.contains("(UTC+") || note.contains("(UTC-"));
+    }
+
+    fn request() -> OpenAiChatRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4",
+            "messages": [
+                {"role": "system", "content": "You are helpful."},
+                {"role": "user", "content": "Hi"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn system_texts(request: &OpenAiChatRequest) -> Vec<String> {
+        request
+            .chat_request
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                OpenAiChatMessage::System { content, .. } => Some(content.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn request_without_system_message() -> OpenAiChatRequest {
+        let mut request = request();
+        request.chat_request.messages.remove(0);
+        request
+    }
+
+    #[test]
+    fn test_default_system_prompt_fills_missing_system_message() {
+        let mut bare = request_without_system_message();
+        apply_default_system_prompt(&mut bare, "Be kind.", SystemPromptMode::Fill);
+        assert_eq!(system_texts(&bare), ["Be kind."]);
+        assert!(matches!(
+            bare.chat_request.messages[0],
+            OpenAiChatMessage::System { .. }
+        ));
+
+        // The request's own system message is left alone
+        let mut with_system = request();
+        apply_default_system_prompt(&mut with_system, "Be kind.", SystemPromptMode::Fill);
+        assert_eq!(system_texts(&with_system), ["You are helpful."]);
+        assert_eq!(with_system.chat_request.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_default_system_prompt_append_and_replace() {
+        let mut appended = request();
+        apply_default_system_prompt(&mut appended, "Be kind.", SystemPromptMode::Append);
+        assert_eq!(system_texts(&appended), ["You are helpful.", "Be kind."]);
+        assert!(matches!(
+            appended.chat_request.messages[2],
+            OpenAiChatMessage::User { .. }
+        ));
+
+        let mut replaced = request();
+        apply_default_system_prompt(&mut replaced, "Be kind.", SystemPromptMode::Replace);
+        assert_eq!(system_texts(&replaced), ["Be kind."]);
+        assert_eq!(replaced.chat_request.messages.len(), 2);
+
+        let mut bare = request_without_system_message();
+        apply_default_system_prompt(&mut bare, "Be kind.", SystemPromptMode::Append);
+        assert_eq!(system_texts(&bare), ["Be kind."]);
+    }
+
+    #[test]
+    fn test_system_override_replaces_system_prompt() {
+        let mut request = request();
+        apply_system_override(&mut request, "Be terse.".to_string(), Default::default());
+        assert_eq!(system_texts(&request), ["Be terse."]);
+        assert_eq!(request.chat_request.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_system_override_prepends_system_prompt() {
+        let mut request = request();
+        apply_system_override(
+            &mut request,
+            "Be terse.".to_string(),
+            SystemOverrideMode::Prepend,
+        );
+        assert_eq!(system_texts(&request), ["Be terse.", "You are helpful."]);
+    }
+}
@@ -0,0 +1,121 @@
+//! Fixtures shared by the unit tests: a canned Straico chat response and local mock
+//! upstream servers.
+
+use actix_web::dev::ServerHandle;
+use actix_web::http::{KeepAlive, StatusCode};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use straico_client::client::StraicoClient;
+
+/// A successful Straico chat response from `model` answering with `content`.
+pub fn straico_json(model: &str, content: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": "chatcmpl-123",
+        "object": "chat.completion",
+        "created": 1700000000,
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop"
+        }],
+        "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+        "price": {"input": 0.0, "output": 0.0, "total": 0.0},
+        "words": {"input": 1.0, "output": 1.0, "total": 2.0}
+    })
+}
+
+/// The JSON payloads of the `data:` events in an SSE `body`, in order; `[DONE]` and
+/// comment or `retry:` events are skipped.
+pub fn sse_events(body: impl AsRef<[u8]>) -> Vec<serde_json::Value> {
+    std::str::from_utf8(body.as_ref())
+        .unwrap()
+        .split("\n\n")
+        .filter_map(|event| event.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str(data).ok())
+        .collect()
+}
+
+/// A local HTTP(S) server standing in for the Straico API, on a free port.
+///
+/// Keep-alive is off, so a handler that answers without reading the request body never
+/// leaves the client a half-closed connection to reuse.
+pub struct MockUpstream {
+    pub base_url: String,
+    handle: ServerHandle,
+}
+
+impl MockUpstream {
+    /// Starts a plain-HTTP server whose routes are registered by `configure`.
+    pub fn start<F>(configure: F) -> Self
+    where
+        F: Fn(&mut web::ServiceConfig) + Clone + Send + 'static,
+    {
+        Self::bind(configure, None)
+    }
+
+    /// Starts an HTTPS server with `tls`, whose routes are registered by `configure`.
+    pub fn start_tls<F>(configure: F, tls: rustls::ServerConfig) -> Self
+    where
+        F: Fn(&mut web::ServiceConfig) + Clone + Send + 'static,
+    {
+        Self::bind(configure, Some(tls))
+    }
+
+    fn bind<F>(configure: F, tls: Option<rustls::ServerConfig>) -> Self
+    where
+        F: Fn(&mut web::ServiceConfig) + Clone + Send + 'static,
+    {
+        let server = HttpServer::new(move || App::new().configure(configure.clone()))
+            .workers(1)
+            .keep_alive(KeepAlive::Disabled);
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let server = match tls {
+            Some(tls) => server.bind_rustls_0_23(("127.0.0.1", 0), tls),
+            None => server.bind(("127.0.0.1", 0)),
+        }
+        .unwrap();
+        let base_url = format!("{scheme}://{}", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        Self { base_url, handle }
+    }
+
+    /// A client sending to this server.
+    pub fn client(&self) -> StraicoClient {
+        StraicoClient::builder()
+            .base_url(self.base_url.clone())
+            .build()
+            .unwrap()
+    }
+
+    pub async fn stop(self) {
+        self.handle.stop(false).await;
+    }
+}
+
+/// Starts a mock upstream answering every request with `statuses` in turn (the last one
+/// repeats) and a [`straico_json`] body, sending `retry-after` with the 429s when given.
+/// Returns the server and the number of requests it has seen.
+pub fn scripted_upstream(
+    statuses: &'static [u16],
+    retry_after: Option<&'static str>,
+) -> (MockUpstream, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let upstream = MockUpstream::start(move |cfg| {
+        let counter = counter.clone();
+        cfg.default_service(web::to(move || {
+            let hit = counter.fetch_add(1, Ordering::SeqCst);
+            let status = statuses[hit.min(statuses.len() - 1)];
+            let mut response = HttpResponse::build(StatusCode::from_u16(status).unwrap());
+            if let (429, Some(seconds)) = (status, retry_after) {
+                response.insert_header(("retry-after", seconds));
+            }
+            async move { response.json(straico_json("openai/gpt-4", "Hello")) }
+        }));
+    });
+    (upstream, hits)
+}
@@ -0,0 +1,240 @@
+//! The legacy `POST /v1/completions` endpoint, for tooling that sends a raw `prompt`
+//! instead of `messages`.
+//!
+//! Each prompt is sent upstream as a chat request with a single user message, and the
+//! answers are returned in OpenAI's `text_completion` shape.
+
+use crate::error::ProxyError;
+use crate::provider::{self, StreamOptions};
+use crate::streaming::{HeartbeatChar, SseChunk};
+use actix_web::HttpResponse;
+use futures::future;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
+use straico_client::endpoints::chat::Usage;
+
+/// A `POST /v1/completions` request body.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TextCompletionRequest {
+    pub model: String,
+    pub prompt: Prompt,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// A single prompt, or a batch answered with one choice per prompt.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Prompt {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl TextCompletionRequest {
+    /// Builds one chat request body per prompt.
+    pub fn chat_bodies(&self) -> Result<Vec<Value>, ProxyError> {
+        let prompts = match &self.prompt {
+            Prompt::Single(prompt) => std::slice::from_ref(prompt),
+            Prompt::Batch(prompts) => prompts.as_slice(),
+        };
+        if prompts.is_empty() {
+            return Err(ProxyError::InvalidParameter {
+                parameter: "prompt".to_string(),
+                reason: "expected at least one prompt".to_string(),
+            });
+        }
+        Ok(prompts
+            .iter()
+            .map(|prompt| {
+                serde_json::json!({
+                    "model": self.model,
+                    "messages": [{"role": "user", "content": prompt}],
+                    "max_tokens": self.max_tokens,
+                    "temperature": self.temperature,
+                    "stream": false
+                })
+            })
+            .collect())
+    }
+}
+
+/// OpenAI's `text_completion` object.
+#[derive(Serialize, Debug, Clone)]
+pub struct TextCompletion {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<TextChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TextChoice {
+    pub text: String,
+    pub index: usize,
+    pub logprobs: Option<Value>,
+    pub finish_reason: Option<String>,
+    /// Azure OpenAI's per-choice filter results (`--sse-dialect azure`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<Value>,
+}
+
+impl TextCompletion {
+    /// Combines the converted chat responses for each prompt, in prompt order, into one
+    /// completion. Choices are numbered across all prompts and usage is summed.
+    pub fn from_chat_responses(model: &str, responses: &[Value]) -> Self {
+        let choices = responses
+            .iter()
+            .flat_map(|response| response["choices"].as_array().into_iter().flatten())
+            .enumerate()
+            .map(|(index, choice)| TextChoice {
+                text: choice["message"]["content"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                index,
+                logprobs: None,
+                finish_reason: choice["finish_reason"].as_str().map(str::to_string),
+                content_filter_results: choice.get("content_filter_results").cloned(),
+            })
+            .collect();
+        let usage = responses
+            .iter()
+            .filter_map(|response| Usage::deserialize(&response["usage"]).ok())
+            .fold(Usage::default(), |total, usage| Usage {
+                prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+                completion_tokens: total.completion_tokens + usage.completion_tokens,
+                total_tokens: total.total_tokens + usage.total_tokens,
+                completion_tokens_details: None,
+            });
+        Self {
+            id: format!("cmpl-{}", uuid::Uuid::new_v4()),
+            object: "text_completion",
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            model: model.to_string(),
+            choices,
+            usage: Some(usage),
+        }
+    }
+
+    /// Keep-alive chunk sent while waiting for the upstream.
+    fn heartbeat(heartbeat_char: &HeartbeatChar) -> Self {
+        Self {
+            id: String::new(),
+            object: "text_completion",
+            created: 0,
+            model: String::new(),
+            choices: vec![TextChoice {
+                text: heartbeat_char.as_str().to_string(),
+                index: 0,
+                logprobs: None,
+                finish_reason: None,
+                content_filter_results: None,
+            }],
+            usage: None,
+        }
+    }
+}
+
+/// Streams `completion` with the chat streams' framing (see [`provider::sse_response`]):
+/// heartbeats until it is ready, then the completion as a single chunk (or an error
+/// chunk), then `[DONE]`.
+pub fn streaming_response(
+    completion: impl Future<Output = Result<TextCompletion, ProxyError>> + 'static,
+    stream_options: StreamOptions,
+) -> Result<HttpResponse, ProxyError> {
+    let heartbeat = TextCompletion::heartbeat(&stream_options.heartbeat_char);
+    let heartbeat = SseChunk::Event(serde_json::to_value(heartbeat)?);
+    provider::sse_response(stream_options, None, heartbeat, completion, |completion| {
+        let chunk = match completion.and_then(|completion| completion) {
+            Ok(mut completion) => {
+                completion.usage = None;
+                serde_json::to_value(completion)
+                    .map(SseChunk::Event)
+                    .unwrap_or_else(|e| SseChunk::from(ProxyError::from(e)))
+            }
+            Err(e) => SseChunk::from(e),
+        };
+        future::ready(vec![chunk])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_response(content: &str, finish_reason: &str) -> Value {
+        serde_json::json!({
+            "object": "chat.completion",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": finish_reason
+            }],
+            "usage": {"prompt_tokens": 2, "completion_tokens": 3, "total_tokens": 5}
+        })
+    }
+
+    #[test]
+    fn test_prompts_become_single_message_chat_requests() {
+        let request: TextCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4o",
+            "prompt": ["Say hi", "Say bye"],
+            "max_tokens": 16
+        }))
+        .unwrap();
+        let bodies = request.chat_bodies().unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(
+            bodies[1]["messages"],
+            serde_json::json!([{"role": "user", "content": "Say bye"}])
+        );
+        assert_eq!(bodies[1]["max_tokens"], 16);
+
+        let request: TextCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "openai/gpt-4o",
+            "prompt": []
+        }))
+        .unwrap();
+        assert!(request.chat_bodies().is_err());
+    }
+
+    #[test]
+    fn test_chat_responses_to_text_completion() {
+        let completion = TextCompletion::from_chat_responses(
+            "openai/gpt-4o",
+            &[chat_response("Hi!", "stop"), chat_response("Bye", "length")],
+        );
+        assert_eq!(completion.object, "text_completion");
+        assert_eq!(
+            completion.choices,
+            [
+                TextChoice {
+                    text: "Hi!".to_string(),
+                    index: 0,
+                    logprobs: None,
+                    finish_reason: Some("stop".to_string()),
+                    content_filter_results: None,
+                },
+                TextChoice {
+                    text: "Bye".to_string(),
+                    index: 1,
+                    logprobs: None,
+                    finish_reason: Some("length".to_string()),
+                    content_filter_results: None,
+                },
+            ]
+        );
+        assert_eq!(completion.usage.unwrap().total_tokens, 10);
+    }
+}
@@ -0,0 +1,107 @@
+//! Known output-token limits of upstream models, checked against a request's `max_tokens`
+//! before it is forwarded (`--max-tokens-over-limit`).
+//!
+//! Requests above a model's documented maximum would be rejected upstream anyway, with a
+//! less helpful error. Models missing from the table are never checked.
+
+use crate::error::ProxyError;
+
+/// Documented maximum output tokens per upstream model ID.
+const OUTPUT_TOKEN_LIMITS: &[(&str, u32)] = &[
+    ("openai/gpt-4", 8_192),
+    ("openai/gpt-4o", 16_384),
+    ("openai/gpt-4o-mini", 16_384),
+    ("openai/gpt-4.1", 32_768),
+    ("openai/gpt-4.1-mini", 32_768),
+    ("openai/gpt-4.1-nano", 32_768),
+    ("anthropic/claude-3-opus", 4_096),
+    ("anthropic/claude-3-haiku", 4_096),
+    ("anthropic/claude-3.5-sonnet", 8_192),
+    ("anthropic/claude-3-5-sonnet", 8_192),
+    ("anthropic/claude-3.5-haiku", 8_192),
+    ("anthropic/claude-3.7-sonnet", 64_000),
+    ("anthropic/claude-sonnet-4", 64_000),
+    ("anthropic/claude-opus-4", 32_000),
+    ("google/gemini-2.5-pro", 65_536),
+    ("google/gemini-2.5-flash", 65_536),
+];
+
+/// What to do with a `max_tokens` above the model's known limit.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum MaxTokensPolicy {
+    /// Fail the request with an invalid parameter error
+    #[default]
+    Reject,
+    /// Lower `max_tokens` to the model's limit
+    Clamp,
+    /// Forward `max_tokens` unchecked
+    Off,
+}
+
+/// Returns the documented maximum output tokens of `model`, if known.
+pub fn output_token_limit(model: &str) -> Option<u32> {
+    OUTPUT_TOKEN_LIMITS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(model))
+        .map(|&(_, limit)| limit)
+}
+
+/// Applies `policy` to a request for `model` asking for `max_tokens`.
+pub fn check_max_tokens(
+    model: &str,
+    max_tokens: &mut Option<u32>,
+    policy: MaxTokensPolicy,
+) -> Result<(), ProxyError> {
+    let (Some(requested), Some(limit)) = (*max_tokens, output_token_limit(model)) else {
+        return Ok(());
+    };
+    if requested <= limit {
+        return Ok(());
+    }
+    match policy {
+        MaxTokensPolicy::Reject => Err(ProxyError::InvalidParameter {
+            parameter: "max_tokens".to_string(),
+            reason: format!(
+                "{requested} exceeds the maximum of {limit} output tokens for model '{model}'"
+            ),
+        }),
+        MaxTokensPolicy::Clamp => {
+            *max_tokens = Some(limit);
+            Ok(())
+        }
+        MaxTokensPolicy::Off => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_over_limit_is_rejected() {
+        let mut max_tokens = Some(10_000);
+        let error =
+            check_max_tokens("openai/gpt-4", &mut max_tokens, MaxTokensPolicy::Reject).unwrap_err();
+        assert!(matches!(
+            error,
+            ProxyError::InvalidParameter { ref parameter, .. } if parameter == "max_tokens"
+        ));
+        assert!(error.to_string().contains("8192"));
+    }
+
+    #[test]
+    fn test_over_limit_is_clamped() {
+        let mut max_tokens = Some(10_000);
+        check_max_tokens("openai/gpt-4", &mut max_tokens, MaxTokensPolicy::Clamp).unwrap();
+        assert_eq!(max_tokens, Some(8_192));
+    }
+
+    #[test]
+    fn test_within_limit_or_unknown_model_is_untouched() {
+        for (model, requested) in [("openai/gpt-4", 8_192), ("acme/custom-model", 1_000_000)] {
+            let mut max_tokens = Some(requested);
+            check_max_tokens(model, &mut max_tokens, MaxTokensPolicy::Reject).unwrap();
+            assert_eq!(max_tokens, Some(requested));
+        }
+    }
+}
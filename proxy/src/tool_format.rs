@@ -0,0 +1,125 @@
+//! Tracking of tool calls that only parsed with a fallback format, reported by
+//! `GET /admin/stats` (`--adapt-tool-format`).
+//!
+//! Each provider's models are asked to emit tool calls in one format, but the parser also
+//! accepts a few others. A model whose calls keep matching a fallback format is ignoring
+//! the instructions it is given, which usually means the model is mapped to the wrong
+//! provider. With adaptation enabled, the last fallback format seen for a model is tried
+//! first on its later responses.
+
+use log::warn;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use straico_client::endpoints::chat::{ModelProvider, ToolCallFormat};
+
+/// Fallback format matches per upstream model, safe to share across workers.
+#[derive(Debug, Default)]
+pub struct ToolFormatTracker {
+    /// Prefer a model's last fallback format when parsing its later responses
+    adapt: bool,
+    fallbacks: Mutex<HashMap<String, FormatFallbacks>>,
+}
+
+/// Fallback format matches of one model.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FormatFallbacks {
+    /// Responses whose tool calls only parsed with a fallback format
+    pub count: u64,
+    /// Format expected from the model's provider
+    pub expected: ToolCallFormat,
+    /// Fallback format of the most recent match
+    pub last_format: ToolCallFormat,
+}
+
+impl ToolFormatTracker {
+    pub fn new(adapt: bool) -> Self {
+        Self {
+            adapt,
+            fallbacks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Format to try first on `model`'s responses, when adaptation is enabled and the model
+    /// has needed a fallback before.
+    pub fn preferred(&self, model: &str) -> Option<ToolCallFormat> {
+        if !self.adapt {
+            return None;
+        }
+        let fallbacks = self.fallbacks.lock().unwrap_or_else(|e| e.into_inner());
+        fallbacks.get(model).map(|stats| stats.last_format)
+    }
+
+    /// Parses `content` from `model` like the response conversion does, recording the match
+    /// if it needed a format other than the provider's own.
+    pub fn observe(&self, model: &str, content: &str, preferred: Option<ToolCallFormat>) {
+        let provider = ModelProvider::from(model);
+        let expected = provider.tool_call_format();
+        let Some((_, format)) = provider.parse_tool_calls_with_format(content, preferred) else {
+            return;
+        };
+        if format == expected {
+            return;
+        }
+        warn!(
+            "Tool calls from {} parsed as {:?} instead of the expected {:?}",
+            model, format, expected
+        );
+        let mut fallbacks = self.fallbacks.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = fallbacks
+            .entry(model.to_string())
+            .or_insert(FormatFallbacks {
+                count: 0,
+                expected,
+                last_format: format,
+            });
+        stats.count += 1;
+        stats.last_format = format;
+    }
+
+    /// Recorded fallback matches, keyed by model.
+    pub fn stats(&self) -> BTreeMap<String, FormatFallbacks> {
+        let fallbacks = self.fallbacks.lock().unwrap_or_else(|e| e.into_inner());
+        fallbacks
+            .iter()
+            .map(|(model, stats)| (model.clone(), stats.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QWEN: &str = "qwen/qwen3-coder";
+    const JSON_WRAPPER: &str =
+        r#"<tool_calls>[{"name": "get_weather", "arguments": {"city": "Paris"}}]</tool_calls>"#;
+    const QWEN_XML: &str = r#"<tool_call>{"name": "get_weather", "arguments": {}}</tool_call>"#;
+
+    #[test]
+    fn test_fallback_success_is_recorded() {
+        let tracker = ToolFormatTracker::default();
+        tracker.observe(QWEN, QWEN_XML, None);
+        tracker.observe(QWEN, "No tools needed.", None);
+        assert!(tracker.stats().is_empty());
+
+        tracker.observe(QWEN, JSON_WRAPPER, None);
+        let stats = &tracker.stats()[QWEN];
+        assert_eq!(stats.count, 1);
+        assert_eq!(stats.expected, ToolCallFormat::Xml);
+        assert_eq!(stats.last_format, ToolCallFormat::Json);
+    }
+
+    #[test]
+    fn test_adaptation_prefers_last_fallback() {
+        let passive = ToolFormatTracker::new(false);
+        passive.observe(QWEN, JSON_WRAPPER, None);
+        assert_eq!(passive.preferred(QWEN), None);
+
+        let adaptive = ToolFormatTracker::new(true);
+        assert_eq!(adaptive.preferred(QWEN), None);
+        adaptive.observe(QWEN, JSON_WRAPPER, None);
+        assert_eq!(adaptive.preferred(QWEN), Some(ToolCallFormat::Json));
+        assert_eq!(adaptive.preferred("openai/gpt-4"), None);
+    }
+}
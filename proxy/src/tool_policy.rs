@@ -0,0 +1,247 @@
+//! Operator-configured restrictions on which tools clients may offer the model
+//! (`--allow-tools` / `--deny-tools`), and on how large their definitions may be
+//! (`--max-tools-prompt-length`).
+
+use crate::error::ProxyError;
+use crate::types::{OpenAiTool, OpenAiToolChoice};
+use log::warn;
+use std::collections::HashSet;
+
+/// Allow/deny lists of tool (function) names.
+///
+/// The deny list always wins. When an allow list is configured, only the tools it names
+/// may be used.
+#[derive(Clone, Debug, Default)]
+pub struct ToolPolicy {
+    pub allow: Option<HashSet<String>>,
+    pub deny: HashSet<String>,
+}
+
+impl ToolPolicy {
+    pub fn new(allow: Option<Vec<String>>, deny: Vec<String>) -> Self {
+        Self {
+            allow: allow.map(|names| names.into_iter().collect()),
+            deny: deny.into_iter().collect(),
+        }
+    }
+
+    /// Rejects the request with `Forbidden` if it references a tool the policy excludes.
+    pub fn check(
+        &self,
+        tools: Option<&[OpenAiTool]>,
+        tool_choice: Option<&OpenAiToolChoice>,
+    ) -> Result<(), ProxyError> {
+        let chosen = match tool_choice {
+            Some(OpenAiToolChoice::Object(tool)) => Some(tool),
+            _ => None,
+        };
+
+        for OpenAiTool::Function(function) in tools.unwrap_or_default().iter().chain(chosen) {
+            self.check_name(&function.name)?;
+        }
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), ProxyError> {
+        if self.deny.contains(name) {
+            return Err(ProxyError::Forbidden(format!(
+                "Tool '{name}' is denied by the proxy configuration"
+            )));
+        }
+        if let Some(allow) = &self.allow {
+            if !allow.contains(name) {
+                return Err(ProxyError::Forbidden(format!(
+                    "Tool '{name}' is not in the proxy's tool allowlist"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What to do with tool definitions longer than `--max-tools-prompt-length`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum, Default, PartialEq, Eq)]
+pub enum ToolsOverLimit {
+    /// Fail the request with an invalid parameter error
+    #[default]
+    Reject,
+    /// Drop tools from the end of the list until the rest fit
+    Truncate,
+}
+
+/// Length in characters of the tool definitions as they are written into the injected
+/// tools system message.
+fn tools_prompt_length(tools: &[OpenAiTool]) -> usize {
+    let functions: Vec<_> = tools
+        .iter()
+        .map(|OpenAiTool::Function(function)| function)
+        .collect();
+    serde_json::to_string_pretty(&functions).map_or(0, |json| json.chars().count())
+}
+
+/// Applies `policy` to tool definitions that serialize to more than `max` characters.
+///
+/// Truncation keeps the leading tools that fit, and fails like `Reject` if the function
+/// named by `tool_choice` would be dropped.
+pub fn check_tools_length(
+    tools: &mut Option<Vec<OpenAiTool>>,
+    tool_choice: &mut Option<OpenAiToolChoice>,
+    max: usize,
+    policy: ToolsOverLimit,
+) -> Result<(), ProxyError> {
+    let Some(list) = tools.as_mut() else {
+        return Ok(());
+    };
+    let length = tools_prompt_length(list);
+    if length <= max {
+        return Ok(());
+    }
+    let rejection = || ProxyError::InvalidParameter {
+        parameter: "tools".to_string(),
+        reason: format!("tool definitions are {length} characters, exceeding the limit of {max}"),
+    };
+    if policy == ToolsOverLimit::Reject {
+        return Err(rejection());
+    }
+
+    let offered = list.len();
+    while !list.is_empty() && tools_prompt_length(list) > max {
+        list.pop();
+    }
+    if let Some(OpenAiToolChoice::Object(OpenAiTool::Function(chosen))) = tool_choice.as_ref() {
+        if !list
+            .iter()
+            .any(|OpenAiTool::Function(function)| function.name == chosen.name)
+        {
+            return Err(rejection());
+        }
+    }
+    warn!(
+        "Tool definitions are {length} characters, exceeding the limit of {max}; \
+         forwarding {} of {offered} tools",
+        list.len()
+    );
+    if list.is_empty() {
+        *tools = None;
+        *tool_choice = None;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OpenAiFunction;
+
+    fn tool(name: &str) -> OpenAiTool {
+        OpenAiTool::Function(OpenAiFunction {
+            name: name.to_string(),
+            description: None,
+            parameters: None,
+        })
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_denied_tool_is_forbidden() {
+        let policy = ToolPolicy::new(None, names(&["execute_shell"]));
+        let err = policy
+            .check(Some(&[tool("get_weather"), tool("execute_shell")]), None)
+            .unwrap_err();
+        assert!(matches!(&err, ProxyError::Forbidden(msg) if msg.contains("execute_shell")));
+    }
+
+    #[test]
+    fn test_allowed_tool_passes() {
+        let policy = ToolPolicy::new(None, names(&["execute_shell"]));
+        assert!(policy.check(Some(&[tool("get_weather")]), None).is_ok());
+        assert!(policy.check(None, None).is_ok());
+    }
+
+    #[test]
+    fn test_allowlist_mode() {
+        let policy = ToolPolicy::new(
+            Some(names(&["get_weather", "execute_shell"])),
+            names(&["execute_shell"]),
+        );
+        assert!(policy.check(Some(&[tool("get_weather")]), None).is_ok());
+
+        let err = policy.check(Some(&[tool("read_file")]), None).unwrap_err();
+        assert!(matches!(&err, ProxyError::Forbidden(msg) if msg.contains("allowlist")));
+
+        // The deny list wins over the allow list
+        let err = policy
+            .check(Some(&[tool("execute_shell")]), None)
+            .unwrap_err();
+        assert!(matches!(&err, ProxyError::Forbidden(msg) if msg.contains("denied")));
+    }
+
+    fn described_tool(name: &str) -> OpenAiTool {
+        OpenAiTool::Function(OpenAiFunction {
+            name: name.to_string(),
+            description: Some("x".repeat(100)),
+            parameters: Some(serde_json::json!({"type": "object"})),
+        })
+    }
+
+    fn tool_names(tools: &Option<Vec<OpenAiTool>>) -> Vec<String> {
+        tools
+            .iter()
+            .flatten()
+            .map(|OpenAiTool::Function(function)| function.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_oversized_tools_are_rejected() {
+        let mut tools = Some(vec![described_tool("a"), described_tool("b")]);
+        let max = tools_prompt_length(&tools.clone().unwrap()) - 1;
+        let err =
+            check_tools_length(&mut tools, &mut None, max, ToolsOverLimit::Reject).unwrap_err();
+        assert!(
+            matches!(&err, ProxyError::InvalidParameter { parameter, .. } if parameter == "tools")
+        );
+        assert_eq!(tool_names(&tools), ["a", "b"]);
+
+        // Definitions within the limit pass untouched
+        let max = max + 1;
+        assert!(check_tools_length(&mut tools, &mut None, max, ToolsOverLimit::Reject).is_ok());
+        assert_eq!(tool_names(&tools), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_oversized_tools_are_truncated() {
+        let mut tools = Some(vec![
+            described_tool("a"),
+            described_tool("b"),
+            described_tool("c"),
+        ]);
+        let max = tools_prompt_length(&[described_tool("a"), described_tool("b")]);
+        check_tools_length(&mut tools, &mut None, max, ToolsOverLimit::Truncate).unwrap();
+        assert_eq!(tool_names(&tools), ["a", "b"]);
+
+        // A forced tool that would be dropped cannot be honoured
+        let mut tools = Some(vec![described_tool("a"), described_tool("b")]);
+        let mut choice = Some(OpenAiToolChoice::Object(tool("b")));
+        let max = tools_prompt_length(&[described_tool("a")]);
+        assert!(
+            check_tools_length(&mut tools, &mut choice, max, ToolsOverLimit::Truncate).is_err()
+        );
+
+        // Nothing fits: the request goes out without tools
+        let mut choice = Some(OpenAiToolChoice::String("required".to_string()));
+        check_tools_length(&mut tools, &mut choice, 10, ToolsOverLimit::Truncate).unwrap();
+        assert!(tools.is_none());
+        assert!(choice.is_none());
+    }
+
+    #[test]
+    fn test_named_tool_choice_is_checked() {
+        let policy = ToolPolicy::new(None, names(&["execute_shell"]));
+        let choice = OpenAiToolChoice::Object(tool("execute_shell"));
+        assert!(policy.check(None, Some(&choice)).is_err());
+    }
+}
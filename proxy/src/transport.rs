@@ -0,0 +1,110 @@
+//! Transport that sends chat requests upstream.
+//!
+//! [`StraicoClient`] is the transport used in production. Tests can inject the test-only
+//! `MockTransport` through `AppState::transport` to drive full request/response cycles,
+//! including retries and streaming, deterministically and without a network.
+
+use async_trait::async_trait;
+use std::time::Duration;
+use straico_client::client::StraicoClient;
+use straico_client::StraicoChatRequest;
+
+/// Sends one chat request upstream and returns the raw response.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    async fn send_chat(
+        &self,
+        key: &str,
+        request: &StraicoChatRequest,
+        timeout: Option<Duration>,
+    ) -> Result<reqwest::Response, reqwest::Error>;
+}
+
+#[async_trait]
+impl HttpTransport for StraicoClient {
+    async fn send_chat(
+        &self,
+        key: &str,
+        request: &StraicoChatRequest,
+        timeout: Option<Duration>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let builder = self.clone().chat().bearer_auth(key);
+        let builder = match timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        };
+        builder.json(request.clone()).send().await
+    }
+}
+
+#[cfg(test)]
+pub use mock::MockTransport;
+
+#[cfg(test)]
+mod mock {
+    use super::HttpTransport;
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use straico_client::StraicoChatRequest;
+
+    /// Transport answering from a queue of canned responses and recording every request.
+    ///
+    /// Once the queue is empty, requests get a `500` response.
+    #[derive(Debug, Default)]
+    pub struct MockTransport {
+        responses: Mutex<VecDeque<(u16, String)>>,
+        requests: Mutex<Vec<StraicoChatRequest>>,
+    }
+
+    impl MockTransport {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queues a response with the given status and body.
+        pub fn push_response(&self, status: u16, body: impl Into<String>) -> &Self {
+            self.responses
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push_back((status, body.into()));
+            self
+        }
+
+        /// The requests sent so far, oldest first.
+        pub fn requests(&self) -> Vec<StraicoChatRequest> {
+            self.requests
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        }
+    }
+
+    #[async_trait]
+    impl HttpTransport for MockTransport {
+        async fn send_chat(
+            &self,
+            _key: &str,
+            request: &StraicoChatRequest,
+            _timeout: Option<Duration>,
+        ) -> Result<reqwest::Response, reqwest::Error> {
+            self.requests
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(request.clone());
+            let (status, body) = self
+                .responses
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .pop_front()
+                .unwrap_or_else(|| (500, "no mock response queued".to_string()));
+            let response = http::Response::builder()
+                .status(status)
+                .header("content-type", "application/json")
+                .body(body)
+                .expect("mock responses have a valid status");
+            Ok(reqwest::Response::from(response))
+        }
+    }
+}
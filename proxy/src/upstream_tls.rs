@@ -0,0 +1,153 @@
+//! TLS trust settings for upstream connections (`--upstream-ca-cert`, `--upstream-insecure`).
+//!
+//! Testing against a self-signed mock server, or running behind a corporate TLS
+//! interceptor, needs the upstream client to trust a root that is not in the built-in set.
+
+use log::warn;
+use std::io;
+use std::path::Path;
+use straico_client::client::StraicoClientBuilder;
+
+/// Applies the upstream TLS options to `builder`.
+///
+/// `ca_cert` is a PEM file whose certificates are trusted in addition to the built-in
+/// roots. `insecure` disables certificate verification entirely.
+pub fn configure(
+    mut builder: StraicoClientBuilder,
+    ca_cert: Option<&Path>,
+    insecure: bool,
+) -> io::Result<StraicoClientBuilder> {
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read CA certificate {}: {}", path.display(), e),
+            )
+        })?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid CA certificate {}: {}", path.display(), e),
+            )
+        })?;
+        if certs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No certificates found in {}", path.display()),
+            ));
+        }
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if insecure {
+        warn!("!!! --upstream-insecure: upstream TLS certificates are NOT verified. !!!");
+        warn!("!!! Anyone on the network path can read and alter requests, including the API key. Never use this in production. !!!");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockUpstream;
+    use actix_web::{web, HttpResponse};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use straico_client::client::StraicoClient;
+
+    /// Starts an HTTPS server for `/v2/models` with a certificate signed by a fresh CA,
+    /// and writes that CA to a PEM file.
+    fn https_server_with_ca() -> (MockUpstream, PathBuf) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = CertificateParams::new(vec!["127.0.0.1".to_string()])
+            .unwrap()
+            .signed_by(&leaf_key, &ca, &ca_key)
+            .unwrap();
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let tls_config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![leaf.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(leaf_key.serialize_der())),
+            )
+            .unwrap();
+        let upstream = MockUpstream::start_tls(
+            |cfg| {
+                cfg.route(
+                    "/v2/models",
+                    web::get().to(|| async { HttpResponse::Ok().body("{}") }),
+                );
+            },
+            tls_config,
+        );
+
+        let ca_path = std::env::temp_dir().join(format!(
+            "straico-proxy-test-ca-{}.pem",
+            uuid::Uuid::new_v4()
+        ));
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        (upstream, ca_path)
+    }
+
+    async fn fetch_models(
+        base_url: &str,
+        ca_cert: Option<&Path>,
+        insecure: bool,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let builder = StraicoClient::builder().base_url(base_url);
+        let client = configure(builder, ca_cert, insecure)
+            .unwrap()
+            .build()
+            .unwrap();
+        client.models().bearer_auth("test-key").send().await
+    }
+
+    #[actix_web::test]
+    async fn test_custom_ca_is_trusted() {
+        let (upstream, ca_path) = https_server_with_ca();
+        let base_url = upstream.base_url.as_str();
+
+        // The mock's CA is not a built-in root
+        assert!(fetch_models(base_url, None, false).await.is_err());
+
+        let response = fetch_models(base_url, Some(&ca_path), false).await.unwrap();
+        assert!(response.status().is_success());
+
+        let response = fetch_models(base_url, None, true).await.unwrap();
+        assert!(response.status().is_success());
+
+        upstream.stop().await;
+        std::fs::remove_file(ca_path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_ca_file_is_rejected() {
+        let path = std::env::temp_dir().join(format!(
+            "straico-proxy-test-bad-ca-{}.pem",
+            uuid::Uuid::new_v4()
+        ));
+        std::fs::write(&path, "not a certificate").unwrap();
+        let result = configure(StraicoClient::builder(), Some(&path), false);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+
+        assert!(configure(
+            StraicoClient::builder(),
+            Some(Path::new("/nonexistent.pem")),
+            false
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Periodic upstream pings that keep the connection pool warm (`--warmup-interval-secs`).
+//!
+//! After a quiet period the first completion would otherwise pay for a new TCP and TLS
+//! handshake. Each ping is an authenticated `GET /v2/models`, so an expired or revoked
+//! API key also shows up in the logs before a client request fails on it.
+
+use log::{debug, warn};
+use std::time::Duration;
+use straico_client::client::StraicoClient;
+
+/// Pings the upstream through `client` every `interval`, until the returned handle is
+/// aborted or the runtime shuts down. The first ping is sent after one interval.
+///
+/// `client` should be the one completions are sent with, since each client has its own
+/// connection pool.
+pub fn spawn(
+    client: StraicoClient,
+    key: String,
+    interval: Duration,
+) -> actix_web::rt::task::JoinHandle<()> {
+    actix_web::rt::spawn(async move {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        // A ping that took longer than the interval is not followed by a burst of catch-ups
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            ping(&client, &key).await;
+        }
+    })
+}
+
+async fn ping(client: &StraicoClient, key: &str) {
+    match client.clone().models().bearer_auth(key).send().await {
+        Ok(response) if response.status().is_success() => {
+            debug!("Upstream warmup ping succeeded");
+        }
+        Ok(response) if response.status() == reqwest::StatusCode::UNAUTHORIZED => {
+            warn!(
+                "Upstream warmup ping was rejected with 401: the Straico API key may have expired"
+            );
+        }
+        Ok(response) => warn!("Upstream warmup ping returned {}", response.status()),
+        Err(e) => warn!("Upstream warmup ping failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockUpstream;
+    use actix_web::{web, HttpRequest, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn test_warmup_pings_upstream_at_interval() {
+        let pings = Arc::new(AtomicUsize::new(0));
+        let upstream = MockUpstream::start({
+            let pings = pings.clone();
+            move |cfg| {
+                let pings = pings.clone();
+                cfg.route(
+                    "/v2/models",
+                    web::get().to(move |request: HttpRequest| {
+                        let authorized = request
+                            .headers()
+                            .get("authorization")
+                            .is_some_and(|value| value == "Bearer test-key");
+                        if authorized {
+                            pings.fetch_add(1, Ordering::SeqCst);
+                        }
+                        async { HttpResponse::Ok().json(serde_json::json!({"data": []})) }
+                    }),
+                );
+            }
+        });
+
+        let client = upstream.client();
+        let warmup = spawn(client, "test-key".to_string(), Duration::from_millis(100));
+
+        // Nothing is sent before the first interval has passed
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(pings.load(Ordering::SeqCst), 0);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        warmup.abort();
+        let sent = pings.load(Ordering::SeqCst);
+        assert!((2..=4).contains(&sent), "{sent} pings");
+
+        upstream.stop().await;
+    }
+}